@@ -0,0 +1,180 @@
+use rusqlite::Connection;
+
+use crate::{db, models::AuditEntry};
+
+// Every insert, update and delete on clients and sessions is written to `audit_log`, one entry per
+// changed field. Unlike the undo journal this log is never pruned, and triggers reject any attempt
+// to modify or delete its entries.
+
+/// Audited tables and the fields tracked for each
+const AUDITED_TABLES: [(&str, &[&str]); 2] = [
+    ("clients", &["name", "note"]),
+    (
+        "sessions",
+        &[
+            "client_id",
+            "start_timestamp",
+            "end_timestamp",
+            "note",
+            "offset_minutes",
+        ],
+    ),
+];
+
+/// SQL for the command responsible for a change: the journaled operation if one is running on
+/// this connection, otherwise its `audit_command` flag (set e.g. by undo / redo)
+pub fn current_command() -> String {
+    format!(
+        "COALESCE(
+            (SELECT command FROM operations WHERE id = {}),
+            {}
+        )",
+        db::connection_flag("journal_operation"),
+        db::connection_flag("audit_command")
+    )
+}
+
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp TEXT NOT NULL,
+            table_name TEXT NOT NULL,
+            row_id INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            field TEXT NOT NULL,
+            old_value TEXT,
+            new_value TEXT,
+            command TEXT
+        );
+
+        CREATE INDEX IF NOT EXISTS audit_log_row ON audit_log (table_name, row_id);
+
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+        BEGIN
+            SELECT RAISE(ABORT, 'The audit log is append-only');
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+        BEGIN
+            SELECT RAISE(ABORT, 'The audit log is append-only');
+        END;",
+    )?;
+    create_triggers(conn)
+}
+
+/// (Re)create the auditing triggers, needed whenever an audited table's columns change
+pub fn create_triggers(conn: &Connection) -> rusqlite::Result<()> {
+    let command = current_command();
+    for (table, fields) in AUDITED_TABLES {
+        for (event, row_id) in [
+            ("INSERT", "NEW.id"),
+            ("UPDATE", "NEW.id"),
+            ("DELETE", "OLD.id"),
+        ] {
+            let entries: Vec<String> = fields
+                .iter()
+                .map(|field| {
+                    let (old, new, condition) = match event {
+                        "INSERT" => (
+                            "NULL".into(),
+                            format!("NEW.{field}"),
+                            format!("WHERE NEW.{field} IS NOT NULL"),
+                        ),
+                        "DELETE" => (
+                            format!("OLD.{field}"),
+                            "NULL".into(),
+                            format!("WHERE OLD.{field} IS NOT NULL"),
+                        ),
+                        _ => (
+                            format!("OLD.{field}"),
+                            format!("NEW.{field}"),
+                            format!("WHERE OLD.{field} IS NOT NEW.{field}"),
+                        ),
+                    };
+                    format!(
+                        "INSERT INTO audit_log
+                            (timestamp, table_name, row_id, action, field, old_value, new_value, command)
+                        SELECT strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), '{table}', {row_id},
+                            '{}', '{field}', {old}, {new}, {command}
+                        {condition};",
+                        event.to_lowercase()
+                    )
+                })
+                .collect();
+            let name = format!("audit_{table}_{}", event.to_lowercase());
+            conn.execute_batch(&format!(
+                "DROP TRIGGER IF EXISTS {name};
+                CREATE TRIGGER {name} AFTER {event} ON {table}
+                BEGIN
+                    {}
+                END;",
+                entries.join("\n")
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Every recorded change to a session, oldest first
+pub fn session_history(conn: &Connection, session_id: i32) -> rusqlite::Result<Vec<AuditEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT timestamp, action, field, old_value, new_value, command
+         FROM audit_log
+         WHERE table_name = 'sessions' AND row_id = ?1
+         ORDER BY id ASC",
+    )?;
+    let entries = stmt.query_map([session_id], |row| {
+        Ok(AuditEntry {
+            timestamp: row.get(0)?,
+            action: row.get(1)?,
+            field: row.get(2)?,
+            old_value: row.get(3)?,
+            new_value: row.get(4)?,
+            command: row.get(5)?,
+        })
+    })?;
+    entries.collect()
+}
+
+// TESTS
+
+#[test]
+fn test_session_history() {
+    use crate::{db, journal, models::Session};
+
+    let conn = Connection::open_in_memory().unwrap();
+    db::init_schema(&conn).unwrap();
+    db::apply_migrations(&conn).unwrap();
+    conn.execute("INSERT INTO clients (name) VALUES ('Alice')", [])
+        .unwrap();
+
+    let mut session = Session {
+        id: 0,
+        client_id: 1,
+        start_timestamp: "2025-01-01T09:00:00+00:00".parse().unwrap(),
+        end_timestamp: None,
+        note: None,
+        offset_minutes: 0,
+    };
+    session.id = db::store_session(&conn, &session).unwrap();
+    {
+        let _op = journal::Operation::begin(&conn, "patch -m 15").unwrap();
+        session.offset_minutes += 15;
+        db::commit_session_changes(&conn, &session).unwrap();
+    }
+
+    let history = session_history(&conn, session.id).unwrap();
+    let patch = history.last().unwrap();
+    assert_eq!(history.len(), 4); // 3 non-null fields on insert, 1 changed field on update
+    assert_eq!(patch.field, "offset_minutes");
+    assert_eq!(patch.old_value.as_deref(), Some("0"));
+    assert_eq!(patch.new_value.as_deref(), Some("15"));
+    assert_eq!(patch.command.as_deref(), Some("patch -m 15"));
+
+    assert!(conn.execute("DELETE FROM audit_log", []).is_err());
+    assert!(
+        conn.execute("UPDATE audit_log SET field = 'x'", [])
+            .is_err()
+    );
+}
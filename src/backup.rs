@@ -0,0 +1,212 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use chrono::Local;
+use rusqlite::{Connection, MAIN_DB, OptionalExtension};
+
+/// Backups are named after their database, e.g. `timber-20250716-093000123-daily.db` for
+/// `timber.db`, so databases sharing a backup directory keep to their own backups
+fn prefix(database_path: &Path) -> String {
+    let stem = database_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or_else(|| "timber".into());
+    format!("{stem}-")
+}
+
+/// Snapshot the database into `dir` using SQLite's online backup API, which is safe to run
+/// while the database is in use. `reason` is included in the file name, after the local time.
+pub fn create(conn: &Connection, dir: &Path, reason: &str) -> rusqlite::Result<PathBuf> {
+    fs::create_dir_all(dir).map_err(|_| rusqlite::Error::InvalidPath(dir.to_path_buf()))?;
+    let prefix = prefix(Path::new(conn.path().unwrap_or_default()));
+    let path = loop {
+        let name = format!(
+            "{prefix}{}-{reason}.db",
+            Local::now().format("%Y%m%d-%H%M%S%3f")
+        );
+        let path = dir.join(name);
+        // Two backups within a millisecond wait for the next one rather than overwrite
+        if !path.exists() {
+            break path;
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    conn.backup(MAIN_DB, &path, None)?;
+    Ok(path)
+}
+
+/// Names of the backups of `database_path` in `dir`, oldest first
+pub fn list(dir: &Path, database_path: &Path) -> Vec<String> {
+    let prefix = prefix(database_path);
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|name| {
+                    // The timestamp tells `timber-…` apart from another database's `timber-work-…`
+                    name.strip_prefix(&prefix).is_some_and(|rest| {
+                        rest.len() > 16
+                            && rest.as_bytes()[..8].iter().all(u8::is_ascii_digit)
+                            && rest.as_bytes()[8] == b'-'
+                    }) && name.ends_with(".db")
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort(); // Names start with the timestamp, so this is chronological
+    names
+}
+
+/// Delete the oldest backups of `database_path` so that at most `retention` remain. The snapshots
+/// taken before a restore are left alone, they are the only way back from restoring the wrong one.
+pub fn rotate(dir: &Path, database_path: &Path, retention: usize) {
+    let names: Vec<String> = list(dir, database_path)
+        .into_iter()
+        .filter(|name| !name.ends_with("-pre-restore.db"))
+        .collect();
+    let excess = names.len().saturating_sub(retention);
+    for name in &names[..excess] {
+        let _ = fs::remove_file(dir.join(name));
+    }
+}
+
+/// Take the daily backup if one hasn't been taken today, going by local time like the file names
+pub fn run_daily(
+    conn: &Connection,
+    dir: &Path,
+    retention: usize,
+) -> rusqlite::Result<Option<PathBuf>> {
+    let today = Local::now().date_naive().to_string();
+    let last: Option<String> = conn
+        .query_row(
+            "SELECT value FROM meta WHERE key = 'last_backup'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if last.as_deref() == Some(today.as_str()) {
+        return Ok(None);
+    }
+
+    let path = create(conn, dir, "daily")?;
+    // Only once the backup exists, so a failed one is tried again next time
+    conn.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('last_backup', ?1)",
+        [&today],
+    )?;
+    rotate(dir, Path::new(conn.path().unwrap_or_default()), retention);
+    Ok(Some(path))
+}
+
+/// Replace the contents of the database at `database_path` with the backup `name`.
+/// A snapshot of the current state is taken first, so a restore can itself be reverted.
+pub fn restore(database_path: &Path, dir: &Path, name: &str) -> rusqlite::Result<PathBuf> {
+    let backup_path = dir.join(name);
+    if !list(dir, database_path)
+        .iter()
+        .any(|existing| existing == name)
+    {
+        return Err(rusqlite::Error::InvalidPath(backup_path));
+    }
+
+    let mut conn = Connection::open(database_path)?;
+    let safety = create(&conn, dir, "pre-restore")?;
+    conn.restore(
+        MAIN_DB,
+        &backup_path,
+        None::<fn(rusqlite::backup::Progress)>,
+    )?;
+    Ok(safety)
+}
+
+// TESTS
+
+#[test]
+fn test_backup_restore_and_rotate() {
+    let dir = std::env::temp_dir().join(format!("timber-backup-test-{}", std::process::id()));
+    let db_path = dir.join("timber.db");
+    let backup_dir = dir.join("backups");
+    fs::create_dir_all(&dir).unwrap();
+
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+         CREATE TABLE clients (name TEXT);
+         INSERT INTO clients VALUES ('Alice');",
+    )
+    .unwrap();
+
+    let daily = run_daily(&conn, &backup_dir, 5).unwrap().unwrap();
+    assert!(run_daily(&conn, &backup_dir, 5).unwrap().is_none()); // Only once a day
+    let last_backup: String = conn
+        .query_row(
+            "SELECT value FROM meta WHERE key = 'last_backup'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    let name = daily.file_name().unwrap().to_str().unwrap();
+    assert!(name.starts_with(&format!("timber-{}-", last_backup.replace('-', "")))); // One clock
+
+    conn.execute("DELETE FROM clients", []).unwrap();
+    restore(&db_path, &backup_dir, name).unwrap();
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM clients", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(list(&backup_dir, &db_path).len(), 2); // The daily and the pre-restore snapshot
+
+    // Backups in quick succession don't overwrite each other
+    let first = create(&conn, &backup_dir, "manual").unwrap();
+    let second = create(&conn, &backup_dir, "manual").unwrap();
+    assert_ne!(first, second);
+    assert_eq!(list(&backup_dir, &db_path).len(), 4);
+
+    // Rotation keeps the newest and leaves the pre-restore snapshot alone
+    rotate(&backup_dir, &db_path, 1);
+    let names = list(&backup_dir, &db_path);
+    assert_eq!(names.len(), 2);
+    assert!(names.iter().any(|name| name.ends_with("-pre-restore.db")));
+    assert!(names.contains(&second.file_name().unwrap().to_string_lossy().into_owned()));
+    assert!(restore(&db_path, &backup_dir, "missing.db").is_err());
+
+    drop(conn);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_daily_backup_keeps_to_its_database() {
+    let dir = std::env::temp_dir().join(format!("timber-daily-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let open = |name: &str| {
+        let conn = Connection::open(dir.join(name)).unwrap();
+        conn.execute_batch("CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);")
+            .unwrap();
+        conn
+    };
+    let timber = open("timber.db");
+    let work = open("timber-work.db");
+
+    // A backup that fails is tried again next time
+    let blocked = dir.join("blocked");
+    fs::write(&blocked, "").unwrap();
+    assert!(run_daily(&timber, &blocked, 5).is_err());
+    let backups = dir.join("backups");
+    assert!(run_daily(&timber, &backups, 5).unwrap().is_some());
+
+    create(&timber, &backups, "manual").unwrap();
+    assert!(run_daily(&work, &backups, 1).unwrap().is_some());
+    assert_eq!(list(&backups, &dir.join("timber.db")).len(), 2);
+    assert_eq!(list(&backups, &dir.join("timber-work.db")).len(), 1);
+    rotate(&backups, &dir.join("timber.db"), 1);
+    assert_eq!(list(&backups, &dir.join("timber.db")).len(), 1);
+    assert_eq!(list(&backups, &dir.join("timber-work.db")).len(), 1);
+
+    drop((timber, work));
+    fs::remove_dir_all(&dir).unwrap();
+}
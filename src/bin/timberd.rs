@@ -0,0 +1,49 @@
+//! Keeps the Timber database open and serves it on a Unix socket, see `timber::daemon`
+
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::Parser;
+
+#[derive(Parser)]
+#[command(
+    name = "timberd",
+    about = "Keep the Timber database open for the CLI and GUI, and tell them about changes",
+    after_help = "While timberd runs, `timber status`, `summary`, `switch` and the other everyday commands go through it instead of opening the database, and the GUI refreshes as soon as anything changes."
+)]
+struct Args {
+    #[arg(long, help = "Profile to serve instead of the active one")]
+    profile: Option<String>,
+    #[arg(long = "db", help = "Database to serve, overriding the config file")]
+    database_path: Option<PathBuf>,
+    #[arg(
+        long = "config",
+        help = "Config file to use instead of the default one"
+    )]
+    config_path: Option<PathBuf>,
+}
+
+#[cfg(unix)]
+fn main() -> ExitCode {
+    use timber::config::{Config, Overrides};
+
+    let args = Args::parse();
+    let overrides = Overrides {
+        config_path: args.config_path,
+        profile: args.profile,
+        database_path: args.database_path,
+    };
+    match Config::load(&overrides).and_then(|config| timber::daemon::run(&config)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn main() -> ExitCode {
+    Args::parse();
+    eprintln!("Error: timberd needs Unix domain sockets, which this platform doesn't have");
+    ExitCode::FAILURE
+}
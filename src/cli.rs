@@ -1,21 +1,69 @@
-use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use clap::{ArgAction, Args, Parser, Subcommand};
+use clap_complete::{
+    ArgValueCandidates, ArgValueCompleter, CompletionCandidate,
+    env::{self, EnvCompleter},
+};
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+};
+
+use timber::{
+    Error,
+    clock::Clock,
+    config::{Config, Overrides},
+    models::UserInput,
+    prompt::Snippet,
+    store::{self, Backend, Store},
+    utils,
+};
 
 #[derive(Parser)]
 #[command(
     name = "timber",
     version = "1.0",
     author = "Mason",
-    about = "A simple time tracker"
+    about = "A simple time tracker",
+    after_help = "Exit codes:
+  0  Success
+  1  Any other failure
+  2  Invalid command line usage
+  3  A client, session, alias, profile or backup was not found
+  4  A conflict, e.g. a session is already active
+  5  Invalid input, e.g. a start time in the future
+  6  A database error"
 )]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
-}
-#[derive(Clone)]
-pub enum UserInput {
-    ByName(String),
-    ById(i32),
+    #[arg(
+        long,
+        global = true,
+        help = "Allow changes to sessions in a locked period (recorded in the audit log)"
+    )]
+    pub force: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Profile to use instead of the active one (also read from TIMBER_PROFILE)"
+    )]
+    pub profile: Option<String>,
+    #[arg(
+        long = "db",
+        id = "db",
+        global = true,
+        action = ArgAction::Append,
+        help = "Database to use, overriding the config file and TIMBER_DB (repeat for `timber team report`)"
+    )]
+    pub database_paths: Vec<PathBuf>,
+    #[arg(
+        long = "config",
+        global = true,
+        help = "Config file to use instead of the default one (also read from TIMBER_CONFIG)"
+    )]
+    pub config_path: Option<PathBuf>,
 }
 #[derive(Subcommand)]
 pub enum ClientOptions {
@@ -30,11 +78,118 @@ pub enum ClientOptions {
         about = "Remove the client with the provided id or name (alias: rm)"
     )]
     Remove {
-        #[arg(value_parser = parse_input)]
+        #[arg(value_parser = parse_input, add = ArgValueCompleter::new(client_names))]
         input: UserInput,
     },
     #[command(alias = "ls", about = "List all clients (alias: ls)")]
     List,
+    #[command(about = "Rename the client with the provided id or name")]
+    Rename {
+        #[arg(value_parser = parse_input, add = ArgValueCompleter::new(client_names))]
+        input: UserInput,
+        new_name: String,
+    },
+    #[command(about = "Set the note for a client, or clear it if no note is given")]
+    Note {
+        #[arg(value_parser = parse_input, add = ArgValueCompleter::new(client_names))]
+        input: UserInput,
+        note: Option<String>,
+    },
+    #[command(about = "Add an alternative name the client can be referred to by")]
+    Alias {
+        #[arg(value_parser = parse_input, add = ArgValueCompleter::new(client_names))]
+        input: UserInput,
+        alias: String,
+    },
+    #[command(about = "Remove a client alias")]
+    Unalias { alias: String },
+    #[command(about = "Merge a client into another, moving all of its sessions and removing it")]
+    Merge {
+        #[arg(value_parser = parse_input, add = ArgValueCompleter::new(client_names))]
+        from: UserInput,
+        #[arg(value_parser = parse_input, add = ArgValueCompleter::new(client_names))]
+        into: UserInput,
+    },
+}
+
+/// The store to complete from, as chosen by the environment and the line being completed
+fn completion_store() -> Option<Box<dyn Store>> {
+    // The shell runs `timber -- timber <words so far>`
+    let words = std::env::args_os().skip_while(|arg| arg != "--").skip(2);
+    let config = Config::load(&completion_overrides(words)).ok()?;
+    store::open_read_only(&config).ok().flatten()
+}
+
+/// The `--profile`, `--db` and `--config` among `words`, which clap hasn't parsed yet when it
+/// asks for completions
+fn completion_overrides(words: impl IntoIterator<Item = OsString>) -> Overrides {
+    let mut overrides = Overrides::default();
+    let mut words = words
+        .into_iter()
+        .map(|word| word.to_string_lossy().into_owned());
+    while let Some(word) = words.next() {
+        if word == "--" {
+            break; // Everything after is positional
+        }
+        let (flag, value) = match word.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (word, None),
+        };
+        if !matches!(flag.as_str(), "--profile" | "--db" | "--config") {
+            continue;
+        }
+        let Some(value) = value.or_else(|| words.next()) else {
+            break;
+        };
+        match flag.as_str() {
+            "--profile" => overrides.profile = Some(value),
+            "--db" => overrides.database_path = Some(value.into()),
+            _ => overrides.config_path = Some(value.into()),
+        }
+    }
+    overrides
+}
+
+/// Client names starting with `current` for shell completion, ignoring case like client lookups
+/// do, with their notes as help
+fn client_names(current: &OsStr) -> Vec<CompletionCandidate> {
+    let Some(store) = completion_store() else {
+        return vec![];
+    };
+    let prefix = current.to_string_lossy().to_lowercase();
+    store
+        .list_clients()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|client| client.name.to_lowercase().starts_with(&prefix))
+        .map(|client| CompletionCandidate::new(client.name).help(client.note.map(Into::into)))
+        .collect()
+}
+
+/// The ids of the most recent sessions for shell completion, described by client and start
+fn session_ids() -> Vec<CompletionCandidate> {
+    let Some(store) = completion_store() else {
+        return vec![];
+    };
+    let names: HashMap<i32, String> = store
+        .list_clients()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|client| (client.id, client.name))
+        .collect();
+    store
+        .list_sessions(None)
+        .unwrap_or_default()
+        .into_iter()
+        .take(20)
+        .map(|session| {
+            let client = names.get(&session.client_id).map_or("?", String::as_str);
+            let start = session.start_timestamp.with_timezone(&Local);
+            CompletionCandidate::new(session.id.to_string()).help(Some(
+                format!("{client}, {}", start.format("%b %d %H:%M")).into(),
+            ))
+        })
+        .collect()
 }
 
 fn parse_input(s: &str) -> Result<UserInput, String> {
@@ -45,6 +200,45 @@ fn parse_input(s: &str) -> Result<UserInput, String> {
     }
 }
 
+/// Check the time can be understood, it is only resolved once the clock is known
+fn parse_at(s: &str) -> Result<String, String> {
+    utils::parse_natural_time(s, Utc::now())
+        .map(|_| s.to_string())
+        .ok_or_else(|| format!("unable to understand the time '{s}'"))
+}
+
+fn parse_ago(s: &str) -> Result<Duration, String> {
+    utils::parse_duration(s).ok_or_else(|| format!("unable to understand the duration '{s}'"))
+}
+
+#[derive(Args, Clone)]
+pub struct TimeOptions {
+    #[arg(
+        long,
+        value_parser = parse_at,
+        conflicts_with = "ago",
+        help = "When it happened, e.g. 09:15, 5pm, \"yesterday 17:00\" or \"20m ago\""
+    )]
+    pub at: Option<String>,
+    #[arg(long, value_parser = parse_ago, help = "How long ago it happened, e.g. 20m or 1h30m")]
+    pub ago: Option<Duration>,
+}
+
+impl TimeOptions {
+    /// The requested time, or now if none was given
+    pub fn resolve(&self, clock: &dyn Clock) -> Result<DateTime<Utc>, Error> {
+        let now = clock.now();
+        match (&self.at, self.ago) {
+            (Some(at), _) => utils::parse_natural_time(at, now)
+                .ok_or_else(|| Error::Invalid(format!("Unable to understand the time '{at}'"))),
+            (None, Some(ago)) => now
+                .checked_sub_signed(ago)
+                .ok_or_else(|| Error::Invalid("That is too long ago".into())),
+            (None, None) => Ok(now),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub enum SessionOptions {
     #[command(
@@ -52,23 +246,31 @@ pub enum SessionOptions {
         about = "Start a new time-tracking session (alias: new)"
     )]
     Start {
-        #[arg(value_parser = parse_input)]
+        #[arg(value_parser = parse_input, add = ArgValueCompleter::new(client_names))]
         input: UserInput,
         note: Option<String>,
+        #[command(flatten)]
+        time: TimeOptions,
     },
     #[command(alias = "stop", about = "End the session tracking (alias: stop)")]
-    End,
+    End {
+        #[command(flatten)]
+        time: TimeOptions,
+    },
     #[command(
         alias = "rm",
         about = "Remove the session with the provided id (alias: rm)"
     )]
-    Remove { id: i32 },
+    Remove {
+        #[arg(add = ArgValueCandidates::new(session_ids))]
+        id: i32,
+    },
     #[command(
         alias = "ls",
         about = "List all sessions, optionally specify a specific client (alias: ls)"
     )]
     List {
-        #[arg(value_parser = parse_input)]
+        #[arg(value_parser = parse_input, add = ArgValueCompleter::new(client_names))]
         client: Option<UserInput>,
     },
     #[command(
@@ -76,6 +278,29 @@ pub enum SessionOptions {
         about = "Display the current working session (alias: show)"
     )]
     Current,
+    #[command(about = "Show every recorded change to a session")]
+    History {
+        #[arg(add = ArgValueCandidates::new(session_ids))]
+        id: i32,
+    },
+    #[command(about = "Split a session in two at the given time")]
+    Split {
+        #[arg(add = ArgValueCandidates::new(session_ids))]
+        id: i32,
+        #[arg(long, help = "Where to split, e.g. 14:30 or \"2025-07-16 14:30\"")]
+        at: String,
+        #[arg(long, value_parser = parse_input, add = ArgValueCompleter::new(client_names), help = "Assign the second half to another client")]
+        client: Option<UserInput>,
+        #[arg(short, long, help = "Give the second half a different note")]
+        note: Option<String>,
+    },
+    #[command(about = "Merge two adjacent sessions of the same client into one")]
+    Merge {
+        #[arg(add = ArgValueCandidates::new(session_ids))]
+        first: i32,
+        #[arg(add = ArgValueCandidates::new(session_ids))]
+        second: i32,
+    },
 }
 
 #[derive(clap::Subcommand, clap::ValueEnum, Clone, Debug)]
@@ -88,6 +313,23 @@ pub enum SummaryRange {
     Monthly,
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl CompletionShell {
+    pub fn completer(self) -> &'static dyn EnvCompleter {
+        match self {
+            CompletionShell::Bash => &env::Bash,
+            CompletionShell::Zsh => &env::Zsh,
+            CompletionShell::Fish => &env::Fish,
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 pub enum ConfigCommand {
     /// Set config values
@@ -95,14 +337,78 @@ pub enum ConfigCommand {
         #[arg(long)]
         //// Path to the database
         database_path: Option<PathBuf>,
+        #[arg(long)]
+        /// Number of database backups to keep
+        backup_retention: Option<usize>,
+        #[arg(long, value_enum)]
+        /// How the data at the database path is stored
+        backend: Option<Backend>,
+        #[arg(long)]
+        /// Shared directory to sync with other machines through
+        sync_dir: Option<PathBuf>,
+        #[arg(long)]
+        /// What `timber prompt` shows, e.g. "{client} {elapsed}"
+        prompt_format: Option<String>,
         // Add other config fields here later
     },
     /// Show the current config
     Show,
+    /// Show where the config file is
+    Path,
     /// Reset the config to the default
     Reset,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum BackupCommand {
+    /// Back up the database right now
+    Now,
+    /// List the available backups, oldest first
+    #[command(alias = "ls")]
+    List,
+    /// Replace the database with a backup (the current state is backed up first)
+    Restore { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfileCommand {
+    /// Make a profile the active one, creating it if it doesn't exist
+    Use { name: String },
+    /// List the profiles, marking the active one
+    #[command(alias = "ls")]
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TeamCommand {
+    #[command(
+        about = "Combine the time in several people's databases, e.g. `timber team report --db alice.db --db bob.db`",
+        after_help = "Databases are only read, never changed. Each person is named after their database file, or give the name with `--db alice=path/to/timber.db`. Clients are matched by name, ignoring case."
+    )]
+    Report {
+        #[arg(
+            long,
+            help = "First day to include, defaults to the start of the pay week"
+        )]
+        from: Option<NaiveDate>,
+        #[arg(
+            long,
+            help = "Last day to include (inclusive), defaults to the end of the pay week"
+        )]
+        to: Option<NaiveDate>,
+        #[arg(long, help = "Print one CSV line per client and person instead")]
+        csv: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// Show the schema version of the database and any pending migrations
+    Status,
+    /// Apply any pending migrations now, after backing the database up
+    Migrate,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     #[command(
@@ -123,9 +429,11 @@ pub enum Commands {
     },
     #[command(about = "End current session and switch to a different client / project")]
     Switch {
-        #[arg(value_parser = parse_input)]
+        #[arg(value_parser = parse_input, add = ArgValueCompleter::new(client_names))]
         input: UserInput,
         note: Option<String>,
+        #[command(flatten)]
+        time: TimeOptions,
     },
     #[command(
         alias = "fix",
@@ -142,8 +450,138 @@ pub enum Commands {
         about = "View or modify config options (alias: cfg)"
     )]
     Config(ConfigCommand),
+    #[command(about = "Check sessions for overlaps and other problems, and offer repairs")]
+    Doctor {
+        #[arg(
+            long,
+            help = "Apply the recommended repair for every problem without asking"
+        )]
+        fix: bool,
+    },
+    #[command(about = "Reverse the last command that changed clients or sessions")]
+    Undo,
+    #[command(about = "Re-apply the last undone command")]
+    Redo,
+    #[command(about = "Lock all sessions up to a date against changes, or show the current lock")]
+    Lock {
+        #[arg(long, help = "Last day to lock (inclusive), e.g. 2026-09-30")]
+        until: Option<NaiveDate>,
+    },
+    #[command(subcommand, about = "Create, list or restore database backups")]
+    Backup(BackupCommand),
+    #[command(
+        subcommand,
+        about = "Switch between profiles, each with its own database"
+    )]
+    Profile(ProfileCommand),
+    #[command(subcommand, about = "Inspect the database schema")]
+    Db(DbCommand),
+    #[command(subcommand, about = "Report on the time tracked by a whole team")]
+    Team(TeamCommand),
     #[command(about = "Display short status summary")]
     Status,
+    #[command(
+        about = "Print the script that sets up tab completion for a shell",
+        after_help = "Client names and session ids complete from the database. To enable it, e.g.:\n  bash: echo 'source <(timber completions bash)' >> ~/.bashrc\n  zsh:  echo 'source <(timber completions zsh)' >> ~/.zshrc\n  fish: timber completions fish > ~/.config/fish/completions/timber.fish"
+    )]
+    Completions {
+        #[arg(value_enum)]
+        shell: CompletionShell,
+    },
+    #[command(
+        about = "Print a one-line status for shell prompts and status bars, empty when idle",
+        after_help = "Templates can use {client}, {elapsed}, {minutes} and {today}. Set the default with `timber config set --prompt-format`.\n\nRun with --snippet to print the config for a shell or bar, e.g. `timber prompt --snippet zsh >> ~/.zshrc`."
+    )]
+    Prompt {
+        #[arg(long, help = "Template to use instead of the configured one")]
+        format: Option<String>,
+        #[arg(long, help = "Print JSON for a waybar custom module")]
+        json: bool,
+        #[arg(
+            long,
+            value_enum,
+            help = "Print the config to show the prompt in a shell or status bar instead"
+        )]
+        snippet: Option<Snippet>,
+    },
+    #[command(
+        about = "Exchange changes with other machines through a shared directory",
+        after_help = "Each machine appends its changes to <machine id>.log in the directory and reads what the others added to theirs. The latest change to a field wins, conflicting changes are reported.\n\nTo set up another machine, either sync it from an empty database or copy the database over. A copy that finds its machine id in use takes a new one."
+    )]
+    Sync {
+        #[arg(
+            long,
+            help = "Shared directory to sync through, instead of the configured one"
+        )]
+        dir: Option<PathBuf>,
+    },
+    #[command(
+        about = "Copy every client and session into a new store, e.g. a plain-text log for version control",
+        after_help = "Text logs have one tab-separated record per line:\n  client  <id>  <name>  <note>\n  session <id>  <client id>  <start>  <end or ->  <offset minutes>  <note>"
+    )]
+    Convert {
+        #[arg(long, value_enum, help = "How the new store is kept")]
+        to: Backend,
+        #[arg(help = "Where to create the new store")]
+        path: PathBuf,
+    },
+    #[cfg(feature = "gui")]
     #[command(about = "Launch the Timber GUI")]
     Gui,
+    #[cfg(feature = "serve")]
+    #[command(
+        about = "Serve a JSON API over HTTP for editor plugins and scripts",
+        after_help = "Endpoints: GET /clients, POST /clients, GET /sessions, GET /sessions/active, POST /sessions/start, /stop, /switch and /patch, GET /summary.\n\nChanges must be sent with Content-Type: application/json, and requests naming a Host or Origin other than this machine are refused. There is no authentication, only listen on addresses you trust."
+    )]
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:7777", help = "Address to listen on")]
+        listen: String,
+    },
+}
+
+// TESTS
+
+#[test]
+fn test_resolve_refuses_times_out_of_range() {
+    use timber::clock::FixedClock;
+
+    let clock = FixedClock("2025-07-16T12:00:00Z".parse().unwrap());
+    let ago = |ago| TimeOptions {
+        at: None,
+        ago: utils::parse_duration(ago),
+    };
+    assert_eq!(
+        ago("90m").resolve(&clock).unwrap(),
+        "2025-07-16T10:30:00Z".parse::<DateTime<Utc>>().unwrap()
+    );
+    assert!(matches!(
+        ago("99999999999h").resolve(&clock),
+        Err(Error::Invalid(_))
+    ));
+    let at = TimeOptions {
+        at: Some("9999999999999m ago".into()),
+        ago: None,
+    };
+    assert!(matches!(at.resolve(&clock), Err(Error::Invalid(_))));
+}
+
+#[test]
+fn test_completion_overrides() {
+    let overrides = completion_overrides(
+        [
+            "--profile",
+            "work",
+            "session",
+            "--db=/tmp/ci.db",
+            "remove",
+            "--config",
+        ]
+        .map(OsString::from),
+    );
+    assert_eq!(overrides.profile.as_deref(), Some("work"));
+    assert_eq!(overrides.database_path, Some(PathBuf::from("/tmp/ci.db")));
+    assert_eq!(overrides.config_path, None);
+
+    let positional = completion_overrides(["client", "add", "--", "--db"].map(OsString::from));
+    assert_eq!(positional.database_path, None);
 }
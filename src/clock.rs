@@ -0,0 +1,71 @@
+use chrono::{DateTime, Utc};
+
+use crate::{
+    error::{Error, Result},
+    utils,
+};
+
+/// Where Timber gets the current time from, so that it can be pinned in tests and reports
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The real time, as told by the operating system
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that is always at the same moment
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// The system clock, or a fixed clock at `TIMBER_NOW` when it is set, e.g. to reproduce a report
+pub fn from_env() -> Result<Box<dyn Clock>> {
+    from_env_with(|name| std::env::var(name).ok())
+}
+
+/// Whether `TIMBER_NOW` pins the clock, so that only this process knows what time it is
+pub fn is_pinned() -> bool {
+    pinned_time(|name| std::env::var(name).ok()).is_some()
+}
+
+fn pinned_time(env: impl Fn(&str) -> Option<String>) -> Option<String> {
+    // Empty counts as unset, e.g. `TIMBER_NOW= timber status` in a shell that exported it
+    env("TIMBER_NOW").filter(|now| !now.trim().is_empty())
+}
+
+fn from_env_with(env: impl Fn(&str) -> Option<String>) -> Result<Box<dyn Clock>> {
+    let Some(now) = pinned_time(env) else {
+        return Ok(Box::new(SystemClock));
+    };
+    let now = utils::parse_natural_time(&now, Utc::now()).ok_or_else(|| {
+        Error::Config(format!(
+            "TIMBER_NOW must be a time such as 2025-07-18T17:00:00Z, not '{now}'"
+        ))
+    })?;
+    Ok(Box::new(FixedClock(now)))
+}
+
+// TESTS
+
+#[test]
+fn test_clock_from_env() {
+    let pinned = from_env_with(|_| Some("2025-07-18T17:00:00Z".into())).unwrap();
+    assert_eq!(
+        pinned.now(),
+        "2025-07-18T17:00:00Z".parse::<DateTime<Utc>>().unwrap()
+    );
+    assert!(from_env_with(|_| Some("teatime".into())).is_err());
+    assert!(from_env_with(|_| None).is_ok());
+    let unset = from_env_with(|_| Some(String::new())).unwrap();
+    assert!(unset.now() > "2025-07-18T17:00:00Z".parse::<DateTime<Utc>>().unwrap());
+}
@@ -0,0 +1,337 @@
+use std::{collections::HashSet, fmt};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    error::Result,
+    models::{Client, Session},
+    store::{self, Store},
+};
+
+#[derive(Debug)]
+pub enum Issue {
+    /// A session set aside when timestamps moved to epoch seconds
+    Quarantined {
+        session_id: i32,
+        reason: String,
+    },
+    NegativeDuration {
+        session: Session,
+    },
+    Overlap {
+        first: Session,
+        second: Session,
+    },
+    MultipleOpen {
+        sessions: Vec<Session>,
+    },
+    OrphanedClient {
+        session_id: i32,
+        client_id: i32,
+    },
+}
+
+#[derive(Debug)]
+pub enum Fix {
+    /// Delete the session entirely
+    Delete(i32),
+    /// Set the end of the session, closing it if it is still open
+    Truncate { session_id: i32, end: DateTime<Utc> },
+    /// Cut a gap out of the middle of a session, leaving two sessions
+    Split {
+        session_id: i32,
+        gap_start: DateTime<Utc>,
+        gap_end: DateTime<Utc>,
+    },
+    /// Swap the start and end of a session that ends before it starts
+    Swap(i32),
+    /// Permanently discard a quarantined session
+    Discard(i32),
+    /// Add a placeholder client under a missing client's id, keeping the sessions that refer to it
+    RecreateClient(i32),
+}
+
+pub fn scan(store: &dyn Store) -> Result<Vec<Issue>> {
+    let mut issues = vec![];
+    let sessions = store.list_sessions(None)?;
+
+    let clients: HashSet<i32> = store.list_clients()?.iter().map(|c| c.id).collect();
+    let mut orphans: Vec<&Session> = sessions
+        .iter()
+        .filter(|s| !clients.contains(&s.client_id))
+        .collect();
+    orphans.sort_by_key(|s| s.id);
+    for session in orphans {
+        issues.push(Issue::OrphanedClient {
+            session_id: session.id,
+            client_id: session.client_id,
+        });
+    }
+
+    for (session_id, reason) in store.quarantined_sessions()? {
+        issues.push(Issue::Quarantined { session_id, reason });
+    }
+
+    let mut valid = vec![];
+    for session in sessions {
+        if session
+            .end_timestamp
+            .is_some_and(|end| end < session.start_timestamp)
+        {
+            issues.push(Issue::NegativeDuration { session });
+        } else {
+            valid.push(session);
+        }
+    }
+    valid.sort_by_key(|s| s.start_timestamp);
+
+    let open: Vec<&Session> = valid.iter().filter(|s| s.end_timestamp.is_none()).collect();
+    if open.len() > 1 {
+        issues.push(Issue::MultipleOpen {
+            sessions: open.into_iter().cloned().collect(),
+        });
+    }
+
+    // Sessions are sorted by start, so only the sessions starting before `first` ends can overlap it
+    for (i, first) in valid.iter().enumerate() {
+        for second in &valid[i + 1..] {
+            match first.end_timestamp {
+                Some(end) if end <= second.start_timestamp => break,
+                None if second.end_timestamp.is_none() => continue, // Reported as MultipleOpen
+                _ => issues.push(Issue::Overlap {
+                    first: first.clone(),
+                    second: second.clone(),
+                }),
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+impl Issue {
+    /// Possible repairs for this issue, the recommended one first
+    pub fn fixes(&self) -> Vec<Fix> {
+        match self {
+            Issue::Quarantined { session_id, .. } => vec![Fix::Discard(*session_id)],
+            Issue::NegativeDuration { session } => {
+                vec![Fix::Swap(session.id), Fix::Delete(session.id)]
+            }
+            Issue::Overlap { first, second } => {
+                let mut fixes = vec![Fix::Truncate {
+                    session_id: first.id,
+                    end: second.start_timestamp,
+                }];
+                // When `first` fully contains `second`, keep the time on both sides of it
+                if let (Some(first_end), Some(second_end)) =
+                    (first.end_timestamp, second.end_timestamp)
+                    && second_end < first_end
+                {
+                    fixes.insert(
+                        0,
+                        Fix::Split {
+                            session_id: first.id,
+                            gap_start: second.start_timestamp,
+                            gap_end: second_end,
+                        },
+                    );
+                }
+                fixes.push(Fix::Delete(second.id));
+                fixes.push(Fix::Delete(first.id));
+                fixes
+            }
+            Issue::MultipleOpen { sessions } => {
+                // Close the oldest open session when the next one started
+                let mut fixes = vec![Fix::Truncate {
+                    session_id: sessions[0].id,
+                    end: sessions[1].start_timestamp,
+                }];
+                fixes.extend(sessions.iter().map(|s| Fix::Delete(s.id)));
+                fixes
+            }
+            // Deleting tracked time has to be chosen by hand, never done by `--fix`
+            Issue::OrphanedClient {
+                session_id,
+                client_id,
+            } => vec![Fix::RecreateClient(*client_id), Fix::Delete(*session_id)],
+        }
+    }
+}
+
+pub fn apply(store: &dyn Store, fix: &Fix) -> Result<()> {
+    match fix {
+        Fix::Delete(id) => store.remove_session(*id),
+        Fix::Truncate { session_id, end } => {
+            let mut session = store.get_session(*session_id)?;
+            session.end_timestamp = Some(*end);
+            store.update_session(&session)
+        }
+        Fix::Split {
+            session_id,
+            gap_start,
+            gap_end,
+        } => store::atomically(store, || {
+            let mut session = store.get_session(*session_id)?;
+            let second_half = Session {
+                id: 0,
+                start_timestamp: *gap_end,
+                offset_minutes: 0, // Offsets stay with the original session
+                ..session.clone()
+            };
+            session.end_timestamp = Some(*gap_start);
+            store.update_session(&session)?;
+            store.add_session(&second_half)?;
+            Ok(())
+        }),
+        Fix::Swap(id) => {
+            let mut session = store.get_session(*id)?;
+            if let Some(end) = session.end_timestamp.take() {
+                session.end_timestamp = Some(std::mem::replace(&mut session.start_timestamp, end));
+            }
+            store.update_session(&session)
+        }
+        Fix::Discard(id) => store.discard_quarantined_session(*id),
+        Fix::RecreateClient(id) => store.insert_client(&Client {
+            id: *id,
+            name: format!("Recovered client {id}"),
+            note: Some(
+                "Re-created by `timber doctor` for sessions whose client was missing".into(),
+            ),
+        }),
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Quarantined { session_id, reason } => {
+                write!(
+                    f,
+                    "Session {session_id} could not be migrated and was set aside: {reason}"
+                )
+            }
+            Issue::NegativeDuration { session } => write!(
+                f,
+                "Session {} ends before it starts ({} -> {})",
+                session.id,
+                session.start_timestamp.to_rfc3339(),
+                session
+                    .end_timestamp
+                    .map(|end| end.to_rfc3339())
+                    .unwrap_or_default()
+            ),
+            Issue::Overlap { first, second } => {
+                write!(f, "Sessions {} and {} overlap", first.id, second.id)
+            }
+            Issue::MultipleOpen { sessions } => {
+                let ids: Vec<String> = sessions.iter().map(|s| s.id.to_string()).collect();
+                write!(f, "Several sessions are open at once: {}", ids.join(", "))
+            }
+            Issue::OrphanedClient {
+                session_id,
+                client_id,
+            } => write!(
+                f,
+                "Session {session_id} belongs to client {client_id}, which does not exist"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fix::Delete(id) => write!(f, "Delete session {id}"),
+            Fix::Truncate { session_id, end } => {
+                write!(f, "End session {session_id} at {}", end.to_rfc3339())
+            }
+            Fix::Split {
+                session_id,
+                gap_start,
+                gap_end,
+            } => write!(
+                f,
+                "Split session {session_id} around {} - {}",
+                gap_start.to_rfc3339(),
+                gap_end.to_rfc3339()
+            ),
+            Fix::Swap(id) => write!(f, "Swap the start and end of session {id}"),
+            Fix::Discard(id) => write!(f, "Discard quarantined session {id}"),
+            Fix::RecreateClient(id) => {
+                write!(f, "Re-create client {id} as 'Recovered client {id}'")
+            }
+        }
+    }
+}
+
+// TESTS
+
+#[test]
+fn test_scan_and_fix_overlap() {
+    use crate::db;
+
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    db::init_schema(&conn).unwrap();
+    db::apply_migrations(&conn).unwrap();
+    conn.execute("INSERT INTO clients (name) VALUES ('Alice')", [])
+        .unwrap();
+
+    let session = |start: &str, end: Option<&str>| Session {
+        id: 0,
+        client_id: 1,
+        start_timestamp: start.parse().unwrap(),
+        end_timestamp: end.map(|end| end.parse().unwrap()),
+        note: None,
+        offset_minutes: 0,
+    };
+    let outer = session(
+        "2025-01-01T09:00:00+00:00",
+        Some("2025-01-01T12:00:00+00:00"),
+    );
+    let inner = session(
+        "2025-01-01T10:00:00+00:00",
+        Some("2025-01-01T11:00:00+00:00"),
+    );
+    db::store_session(&conn, &outer).unwrap();
+    db::store_session(&conn, &inner).unwrap();
+
+    let issues = scan(&conn).unwrap();
+    assert_eq!(issues.len(), 1);
+    let fixes = issues[0].fixes();
+    assert!(matches!(fixes[0], Fix::Split { session_id: 1, .. }));
+
+    apply(&conn, &fixes[0]).unwrap();
+    assert!(scan(&conn).unwrap().is_empty());
+    assert_eq!(db::list_sessions(&conn, None).unwrap().len(), 3);
+}
+
+#[test]
+fn test_missing_clients_are_recreated() {
+    use crate::store::MemoryStore;
+
+    let store = MemoryStore::new();
+    for start in ["2025-01-01T09:00:00Z", "2025-01-02T09:00:00Z"] {
+        let id = store.list_sessions(None).unwrap().len() as i32 + 1;
+        store.load_session(Session {
+            id,
+            client_id: 7,
+            start_timestamp: start.parse().unwrap(),
+            end_timestamp: Some(
+                start.parse::<DateTime<Utc>>().unwrap() + chrono::TimeDelta::hours(1),
+            ),
+            note: None,
+            offset_minutes: 0,
+        });
+    }
+
+    let issues = scan(&store).unwrap();
+    assert_eq!(issues.len(), 2);
+    let fixes = issues[0].fixes();
+    assert!(matches!(fixes[0], Fix::RecreateClient(7)));
+    apply(&store, &fixes[0]).unwrap();
+
+    // Both sessions are kept, under a client that can be renamed or merged
+    assert!(scan(&store).unwrap().is_empty());
+    assert_eq!(store.list_sessions(Some(7)).unwrap().len(), 2);
+    assert_eq!(store.get_client(7).unwrap().name, "Recovered client 7");
+}
@@ -1,29 +1,260 @@
-use chrono::{TimeDelta, Utc};
-use rusqlite::Connection;
-
-use crate::db;
-
-pub fn end_session(conn: &Connection) -> Result<Option<TimeDelta>, rusqlite::Error> {
-    match db::get_active_session(conn) {
-        Ok(Some(mut session)) => {
-            session.end_timestamp = Some(Utc::now().to_rfc3339());
-            let _ = db::commit_session_changes(conn, &session);
-            let delta = session.get_timedelta();
-            Ok(Some(delta))
-        }
-        Ok(None) => Ok(None),
-        Err(err) => Err(err),
+use chrono::{DateTime, Local, TimeDelta, Utc};
+
+use crate::{
+    clock::Clock,
+    error::{Error, Result},
+    models::Session,
+    store::{self, Store},
+};
+
+/// Start logging a session for `client_id`, returning the new session's id
+pub fn start_session(
+    store: &dyn Store,
+    clock: &dyn Clock,
+    client_id: i32,
+    note: Option<String>,
+    start: DateTime<Utc>,
+) -> Result<i32> {
+    store.get_client(client_id)?;
+    if start > clock.now() {
+        return Err(Error::Invalid(
+            "A session cannot start in the future".into(),
+        ));
+    }
+    if let Some(last_end) = store.latest_session_end()?
+        && last_end > start
+    {
+        return Err(Error::Invalid(format!(
+            "The previous session ended at {}, after the requested start time",
+            last_end.with_timezone(&Local).format("%b %d, %Y %I:%M %p")
+        )));
+    }
+    if let Some(session) = store.active_session()? {
+        let client_name = store
+            .get_client(session.client_id)
+            .map(|client| client.name)
+            .unwrap_or_else(|_| format!("client {}", session.client_id));
+        return Err(Error::Conflict(format!(
+            "Cannot start a session because you currently have a session for {client_name}"
+        )));
+    }
+    store.add_session(&Session {
+        id: 0, // Will be assigned by the store instead
+        client_id,
+        start_timestamp: start,
+        end_timestamp: None,
+        note,
+        offset_minutes: 0,
+    })
+}
+
+pub fn end_session(
+    store: &dyn Store,
+    clock: &dyn Clock,
+    end: DateTime<Utc>,
+) -> Result<Option<TimeDelta>> {
+    let Some(mut session) = store.active_session()? else {
+        return Ok(None);
+    };
+    if end < session.start_timestamp {
+        return Err(Error::Invalid(
+            "A session cannot end before it started".into(),
+        ));
+    }
+    if end > clock.now() {
+        return Err(Error::Invalid("A session cannot end in the future".into()));
     }
+    session.end_timestamp = Some(end);
+    store.update_session(&session)?;
+    Ok(Some(session.get_timedelta(clock)))
+}
+
+/// The most a session's time can be patched by either way, a week
+pub const MAX_OFFSET_MINUTES: i32 = 7 * 24 * 60;
+
+pub fn patch_session(store: &dyn Store, offset: i32) -> Result<Option<()>> {
+    let Some(mut session) = store.active_session()? else {
+        return Ok(None);
+    };
+    session.offset_minutes = session
+        .offset_minutes
+        .checked_add(offset)
+        .filter(|total| total.abs() <= MAX_OFFSET_MINUTES)
+        .ok_or_else(|| {
+            Error::Invalid(format!(
+                "A session can be patched by at most {MAX_OFFSET_MINUTES} minutes either way"
+            ))
+        })?;
+    store.update_session(&session)?;
+    Ok(Some(()))
+}
+
+/// Break a session in two at `at`, returning the id of the new second half.
+/// The second half can optionally be reassigned to another client or given a different note.
+pub fn split_session(
+    store: &dyn Store,
+    clock: &dyn Clock,
+    id: i32,
+    at: DateTime<Utc>,
+    client_id: Option<i32>,
+    note: Option<String>,
+) -> Result<i32> {
+    store::atomically(store, || {
+        let mut first = store.get_session(id)?;
+
+        let end = first.end_timestamp.unwrap_or_else(|| clock.now());
+        if at <= first.start_timestamp || at >= end {
+            return Err(Error::Invalid(format!(
+                "The split time must fall inside session {id}"
+            )));
+        }
+
+        let second = Session {
+            id: 0,
+            client_id: client_id.unwrap_or(first.client_id),
+            start_timestamp: at,
+            end_timestamp: first.end_timestamp.take(),
+            note: note.or_else(|| first.note.clone()),
+            offset_minutes: 0, // Offsets stay with the original session
+        };
+        first.end_timestamp = Some(at);
+
+        store.update_session(&first)?;
+        store.add_session(&second)
+    })
 }
 
-pub fn patch_session(conn: &Connection, offset: i32) -> Result<Option<()>, rusqlite::Error> {
-    match db::get_active_session(conn) {
-        Ok(Some(mut session)) => {
-            session.offset_minutes += offset;
-            let _ = db::commit_session_changes(conn, &session);
-            Ok(Some(()))
+/// Join two consecutive sessions of the same client into the earlier one, returning the
+/// length of the gap between them that is now counted as worked time.
+pub fn merge_sessions(store: &dyn Store, first_id: i32, second_id: i32) -> Result<TimeDelta> {
+    store::atomically(store, || {
+        let mut first = store.get_session(first_id)?;
+        let mut second = store.get_session(second_id)?;
+        if second.start_timestamp < first.start_timestamp {
+            std::mem::swap(&mut first, &mut second);
         }
-        Ok(None) => Ok(None),
-        Err(e) => Err(e),
+
+        if first.id == second.id {
+            return Err(Error::Invalid("Cannot merge a session with itself".into()));
+        }
+        if first.client_id != second.client_id {
+            return Err(Error::Invalid(
+                "Only sessions for the same client can be merged".into(),
+            ));
+        }
+        let Some(first_end) = first.end_timestamp else {
+            return Err(Error::Invalid(format!(
+                "Session {} is still running and overlaps session {}",
+                first.id, second.id
+            )));
+        };
+        let gap = second.start_timestamp - first_end;
+        if gap < TimeDelta::zero() {
+            return Err(Error::Invalid(format!(
+                "Sessions {} and {} overlap, run `timber doctor` to repair them",
+                first.id, second.id
+            )));
+        }
+        let in_between =
+            store.sessions_within_range(&first.start_timestamp, &second.start_timestamp)?;
+        if in_between
+            .iter()
+            .any(|s| s.id != first.id && s.id != second.id)
+        {
+            return Err(Error::Invalid(format!(
+                "Sessions {} and {} are not adjacent",
+                first.id, second.id
+            )));
+        }
+
+        first.end_timestamp = second.end_timestamp.take();
+        first.offset_minutes += second.offset_minutes;
+        first.note = match (first.note.take(), second.note.take()) {
+            (Some(a), Some(b)) if a != b => Some(format!("{a}; {b}")),
+            (a, b) => a.or(b),
+        };
+
+        store.update_session(&first)?;
+        store.remove_session(second.id)?;
+        Ok(gap)
+    })
+}
+
+// TESTS
+
+#[test]
+fn test_split_then_merge_session() {
+    use crate::clock::SystemClock;
+
+    let store = store::MemoryStore::new();
+    store
+        .add_client(&crate::models::Client {
+            id: 0,
+            name: "Alice".into(),
+            note: None,
+        })
+        .unwrap();
+    let id = store
+        .add_session(&Session {
+            id: 0,
+            client_id: 1,
+            start_timestamp: "2025-01-01T09:00:00+00:00".parse().unwrap(),
+            end_timestamp: "2025-01-01T12:00:00+00:00".parse().ok(),
+            note: Some("planning".into()),
+            offset_minutes: 5,
+        })
+        .unwrap();
+
+    let at = "2025-01-01T10:30:00+00:00".parse().unwrap();
+    let second_id = split_session(&store, &SystemClock, id, at, None, None).unwrap();
+    let first = store.get_session(id).unwrap();
+    let second = store.get_session(second_id).unwrap();
+    assert_eq!(first.get_timedelta(&SystemClock), TimeDelta::minutes(95));
+    assert_eq!(second.get_timedelta(&SystemClock), TimeDelta::minutes(90));
+
+    let outside = "2025-01-01T13:00:00+00:00".parse().unwrap();
+    assert!(matches!(
+        split_session(&store, &SystemClock, id, outside, None, None),
+        Err(Error::Invalid(_))
+    ));
+
+    assert_eq!(
+        merge_sessions(&store, second_id, id).unwrap(),
+        TimeDelta::zero()
+    );
+    let merged = store.get_session(id).unwrap();
+    assert_eq!(merged.get_timedelta(&SystemClock), TimeDelta::minutes(185));
+    assert_eq!(merged.note.as_deref(), Some("planning"));
+    assert!(matches!(
+        store.get_session(second_id),
+        Err(Error::NotFound(_))
+    ));
+}
+
+#[test]
+fn test_patch_offset_is_bounded() {
+    use crate::clock::SystemClock;
+
+    let store = store::MemoryStore::new();
+    store
+        .add_client(&crate::models::Client {
+            id: 0,
+            name: "Alice".into(),
+            note: None,
+        })
+        .unwrap();
+    start_session(&store, &SystemClock, 1, None, SystemClock.now()).unwrap();
+
+    patch_session(&store, -30).unwrap();
+    for offset in [i32::MAX, i32::MIN, MAX_OFFSET_MINUTES + 31] {
+        assert!(matches!(
+            patch_session(&store, offset),
+            Err(Error::Invalid(_))
+        ));
     }
+    patch_session(&store, MAX_OFFSET_MINUTES - 30).unwrap();
+    assert_eq!(
+        store.active_session().unwrap().unwrap().offset_minutes,
+        MAX_OFFSET_MINUTES - 60
+    );
 }
@@ -1,59 +1,404 @@
 use std::{
+    collections::BTreeMap,
     fmt,
     io::{BufWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+use crate::{
+    error::{Error, Result},
+    prompt,
+    store::Backend,
+};
+
+/// Name of the profile whose settings sit at the top level of `config.toml`
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Settings for a single profile
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
+    /// The profile these settings belong to
+    #[serde(skip)]
+    pub profile: String,
+    /// The config file these settings were loaded from
+    #[serde(skip)]
+    pub config_path: PathBuf,
+    /// Where backups of the database are written
+    #[serde(skip)]
+    pub backup_dir: PathBuf,
     pub database_path: PathBuf,
+    /// How the data at `database_path` is stored
+    #[serde(default)]
+    pub backend: Backend,
+    /// Number of database backups to keep
+    #[serde(default = "default_backup_retention")]
+    pub backup_retention: usize,
+    /// Shared directory (e.g. a Syncthing or network folder) to sync with other machines through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_dir: Option<PathBuf>,
+    /// What `timber prompt` shows while a session is active
+    #[serde(default = "default_prompt_format")]
+    pub prompt_format: String,
+}
+
+fn default_backup_retention() -> usize {
+    14
+}
+
+fn default_prompt_format() -> String {
+    prompt::DEFAULT_FORMAT.to_string()
+}
+
+/// Where Timber keeps its data, or `.timber` in the working directory if the platform has no
+/// data directory (e.g. a container without a home directory)
+fn data_dir() -> PathBuf {
+    platform_dirs::AppDirs::new(Some("Timber"), true)
+        .map(|dirs| dirs.data_dir)
+        .unwrap_or_else(|| PathBuf::from(".timber"))
+}
+
+/// Settings given on the command line, which take precedence over everything else
+#[derive(Default)]
+pub struct Overrides {
+    pub config_path: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub database_path: Option<PathBuf>,
+}
+
+impl Overrides {
+    /// The config file in use: `--config`, then `TIMBER_CONFIG`, then the default location
+    pub fn config_file(&self) -> PathBuf {
+        self.config_file_with(|name| std::env::var(name).ok())
+    }
+
+    fn config_file_with(&self, env: impl Fn(&str) -> Option<String>) -> PathBuf {
+        self.config_path
+            .clone()
+            .or_else(|| env("TIMBER_CONFIG").map(PathBuf::from))
+            .unwrap_or_else(Config::default_config_path)
+    }
+}
+
+/// The contents of `config.toml`. The default profile is kept at the top level so that config
+/// files written before profiles existed still load, named profiles live under `[profiles.<name>]`.
+#[derive(Serialize, Deserialize)]
+pub struct ConfigFile {
+    /// Where this file is read from and saved to
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// The profile used when neither `--profile` nor `TIMBER_PROFILE` is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    #[serde(flatten)]
+    pub default: Config,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Config>,
+}
+
+impl ConfigFile {
+    /// Read the config file at `path`, a missing file gives the defaults
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let mut file = match std::fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).map_err(|e| {
+                Error::Config(format!(
+                    "Unable to read the config file {}: {e}",
+                    path.display()
+                ))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ConfigFile {
+                path: PathBuf::new(),
+                active_profile: None,
+                default: Config::default(),
+                profiles: BTreeMap::new(),
+            },
+            Err(e) => {
+                return Err(Error::Config(format!(
+                    "Unable to read the config file {}: {e}",
+                    path.display()
+                )));
+            }
+        };
+        file.path = path;
+        Ok(file)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let write = || -> std::io::Result<()> {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let file = std::fs::File::create(&self.path)?;
+            let mut writer = BufWriter::new(file);
+            writer.write_all(
+                toml::to_string(&self)
+                    .map_err(std::io::Error::other)?
+                    .as_bytes(),
+            )
+        };
+        write().map_err(|e| Error::Config(format!("Unable to save {}: {e}", self.path.display())))
+    }
+
+    /// The settings of profile `name` as stored in the file, if it exists
+    pub fn profile(&self, name: &str) -> Option<Config> {
+        let mut config = match name {
+            DEFAULT_PROFILE => self.default.clone(),
+            _ => self.profiles.get(name)?.clone(),
+        };
+        config.profile = name.to_string();
+        config.config_path = self.path.clone();
+        config.backup_dir = Config::profile_backup_dir(name);
+        Some(config)
+    }
+
+    /// Store `config` as the settings of its profile
+    pub fn set_profile(&mut self, config: Config) {
+        if config.profile == DEFAULT_PROFILE {
+            self.default = config;
+        } else {
+            self.profiles.insert(config.profile.clone(), config);
+        }
+    }
+
+    /// Names of every profile, the default first
+    pub fn profile_names(&self) -> Vec<String> {
+        std::iter::once(DEFAULT_PROFILE.to_string())
+            .chain(self.profiles.keys().cloned())
+            .collect()
+    }
 }
 
 impl Config {
-    pub fn load() -> Option<Self> {
-        let app_dirs = platform_dirs::AppDirs::new(Some("Timber"), true)?;
-        let config_path = app_dirs.data_dir.join("config.toml");
-        let content = std::fs::read_to_string(config_path).ok()?;
-        toml::from_str(&content).ok()
+    /// Load the settings in effect, layered from lowest to highest precedence: defaults, the config
+    /// file, `TIMBER_*` environment variables and finally `overrides` from the command line
+    pub fn load(overrides: &Overrides) -> Result<Self> {
+        Config::load_with(overrides, |name| std::env::var(name).ok())
+    }
+
+    fn load_with(overrides: &Overrides, env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let file = ConfigFile::load(overrides.config_file_with(&env))?;
+        let name = overrides
+            .profile
+            .clone()
+            .or_else(|| env("TIMBER_PROFILE"))
+            .or_else(|| file.active_profile.clone())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+        let mut config = file.profile(&name).ok_or_else(|| {
+            Error::NotFound(format!(
+                "No profile named '{name}' exists, create it with `timber profile use {name}`"
+            ))
+        })?;
+
+        let profile_database = config.database_path.clone();
+        if let Some(database_path) = env("TIMBER_DB") {
+            config.database_path = database_path.into();
+        }
+        if let Some(retention) = env("TIMBER_BACKUP_RETENTION") {
+            config.backup_retention = retention.parse().map_err(|_| {
+                Error::Config(format!(
+                    "TIMBER_BACKUP_RETENTION must be a number, not '{retention}'"
+                ))
+            })?;
+        }
+        if let Some(database_path) = &overrides.database_path {
+            config.database_path = database_path.clone();
+        }
+        if config.database_path != profile_database {
+            // Keep backups of a substituted database (e.g. a CI fixture) away from the profile's,
+            // and from those of other databases in the same directory
+            let stem = config
+                .database_path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "timber".into());
+            config.backup_dir = config
+                .database_path
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join(format!("{stem}-backups"));
+        }
+        Ok(config)
     }
 
-    pub fn save(&self) {
-        let file = std::fs::File::create(Config::config_path()).expect("Failed to save config");
-        let mut writer = BufWriter::new(file);
-        let _ = writer.write_all(toml::to_string(&self).expect("").as_bytes());
+    /// Default settings for a new named profile, which gets a database of its own
+    pub fn for_profile(name: &str) -> Result<Self> {
+        if name == DEFAULT_PROFILE {
+            return Ok(Config::default());
+        }
+        // The name becomes a directory under `profiles`, which must not lead anywhere else
+        if matches!(name, "" | "." | "..") || name.contains(['/', '\\']) {
+            return Err(Error::Invalid(format!(
+                "'{name}' can't be a profile name, it would not get a directory of its own"
+            )));
+        }
+        Ok(Config {
+            profile: name.to_string(),
+            database_path: Config::profile_dir(name).join("timber.db"),
+            backup_dir: Config::profile_backup_dir(name),
+            ..Config::default()
+        })
     }
 
-    pub fn config_path() -> PathBuf {
-        let app_dirs = platform_dirs::AppDirs::new(Some("Timber"), true)
-            .expect("Error: Could not find data directory");
-        app_dirs.data_dir.join("config.toml")
+    fn profile_dir(name: &str) -> PathBuf {
+        data_dir().join("profiles").join(name)
+    }
+
+    fn profile_backup_dir(name: &str) -> PathBuf {
+        if name == DEFAULT_PROFILE {
+            data_dir().join("backups")
+        } else {
+            Config::profile_dir(name).join("backups")
+        }
+    }
+
+    /// The config file used when neither `--config` nor `TIMBER_CONFIG` is given
+    pub fn default_config_path() -> PathBuf {
+        data_dir().join("config.toml")
     }
 }
 
 impl Default for Config {
     fn default() -> Self {
-        // Get appdata dir
-        let app_dirs =
-            platform_dirs::AppDirs::new(Some("Timber"), true).expect("Failed to get directories");
-        let db_path = app_dirs.data_dir.join("timber.db");
-
         Config {
-            database_path: db_path,
+            profile: DEFAULT_PROFILE.to_string(),
+            config_path: Config::default_config_path(),
+            backup_dir: Config::profile_backup_dir(DEFAULT_PROFILE),
+            database_path: data_dir().join("timber.db"),
+            backend: Backend::default(),
+            backup_retention: default_backup_retention(),
+            sync_dir: None,
+            prompt_format: default_prompt_format(),
         }
     }
 }
 
 impl std::fmt::Display for Config {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", toml::to_string_pretty(&self).unwrap())
+        write!(
+            f,
+            "{}",
+            toml::to_string_pretty(&self).map_err(|_| fmt::Error)?
+        )
     }
 }
 
-pub fn reset_config() -> Config {
-    // Reset the config on disk and return a new config
-    let config = Config::default();
-    config.save();
-    config
+/// Change the stored settings of `config`'s profile. Only the file is updated, so values
+/// overridden by the environment or command line are never written to it.
+pub fn update_config(config: &Config, change: impl FnOnce(&mut Config)) -> Result<()> {
+    let mut file = ConfigFile::load(config.config_path.clone())?;
+    let mut stored = match file.profile(&config.profile) {
+        Some(stored) => stored,
+        None => Config::for_profile(&config.profile)?,
+    };
+    change(&mut stored);
+    file.set_profile(stored);
+    file.save()
+}
+
+pub fn reset_config(config: &Config) -> Result<()> {
+    // Reset the profile on disk
+    let defaults = Config::for_profile(&config.profile)?;
+    update_config(config, |stored| *stored = defaults)
+}
+
+// TESTS
+
+#[test]
+fn test_profiles_in_config_file() {
+    // Written before profiles existed
+    let legacy: ConfigFile = toml::from_str("database_path = \"/data/timber.db\"").unwrap();
+    let default = legacy.profile(DEFAULT_PROFILE).unwrap();
+    assert_eq!(default.database_path, PathBuf::from("/data/timber.db"));
+    assert_eq!(default.backup_retention, default_backup_retention());
+    assert!(legacy.profile("work").is_none());
+
+    let file: ConfigFile = toml::from_str(
+        "active_profile = \"work\"
+        database_path = \"/data/timber.db\"
+
+        [profiles.work]
+        database_path = \"/data/work.db\"
+        backup_retention = 3",
+    )
+    .unwrap();
+    let work = file.profile("work").unwrap();
+    assert_eq!(work.profile, "work");
+    assert_eq!(work.database_path, PathBuf::from("/data/work.db"));
+    assert_eq!(file.profile_names(), vec!["default", "work"]);
+}
+
+#[test]
+fn test_config_layers() {
+    let dir = std::env::temp_dir().join(format!("timber-config-test-{}", std::process::id()));
+    let path = dir.join("config.toml");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        &path,
+        "database_path = \"/data/timber.db\"\nbackup_retention = 3",
+    )
+    .unwrap();
+    let overrides = Overrides {
+        config_path: Some(path),
+        ..Overrides::default()
+    };
+    let env = |vars: &'static [(&'static str, &'static str)]| {
+        move |name: &str| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        }
+    };
+
+    let file_only = Config::load_with(&overrides, env(&[])).unwrap();
+    assert_eq!(file_only.database_path, PathBuf::from("/data/timber.db"));
+    assert_eq!(file_only.backup_retention, 3);
+
+    let from_env = Config::load_with(&overrides, env(&[("TIMBER_DB", "/tmp/ci.db")])).unwrap();
+    assert_eq!(from_env.database_path, PathBuf::from("/tmp/ci.db"));
+    assert_eq!(from_env.backup_retention, 3);
+    assert_eq!(from_env.backup_dir, PathBuf::from("/tmp/ci-backups"));
+
+    let flagged = Overrides {
+        database_path: Some("/tmp/flag.db".into()),
+        ..overrides
+    };
+    let from_flag = Config::load_with(&flagged, env(&[("TIMBER_DB", "/tmp/ci.db")])).unwrap();
+    assert_eq!(from_flag.database_path, PathBuf::from("/tmp/flag.db"));
+    assert_eq!(from_flag.backup_dir, PathBuf::from("/tmp/flag-backups"));
+
+    assert!(Config::load_with(&flagged, env(&[("TIMBER_BACKUP_RETENTION", "lots")])).is_err());
+
+    // Only a missing file gives the defaults, an unreadable one is reported
+    let missing = ConfigFile::load(dir.join("missing.toml")).unwrap();
+    assert_eq!(
+        missing.default.database_path,
+        Config::default().database_path
+    );
+    assert!(matches!(
+        ConfigFile::load(dir.clone()),
+        Err(Error::Config(_))
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_profile_names_stay_in_their_directory() {
+    let work = Config::for_profile("work").unwrap();
+    assert_eq!(
+        work.database_path,
+        data_dir().join("profiles/work/timber.db")
+    );
+    assert_eq!(
+        Config::for_profile(DEFAULT_PROFILE).unwrap().database_path,
+        Config::default().database_path
+    );
+
+    // `..` would share the default profile's database, the others leave `profiles`
+    for name in ["", ".", "..", "../work", "a/b", "/tmp", "a\\b"] {
+        assert!(
+            matches!(Config::for_profile(name), Err(Error::Invalid(_))),
+            "{name:?}"
+        );
+    }
 }
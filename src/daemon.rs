@@ -0,0 +1,396 @@
+//! `timberd`, a background process that keeps the database open so other processes don't have to.
+//! It listens on a Unix socket beside the database (`timber.sock` for `timber.db`) and speaks
+//! one JSON object per line. Requests take the routes of [`crate::serve`]:
+//!
+//! ```text
+//! > {"method": "POST", "path": "/sessions/start", "body": {"client": "Acme"}}
+//! < {"status": 200, "body": {"id": 12}}
+//! ```
+//!
+//! After `{"method": "SUBSCRIBE"}` the connection instead receives `{"event": "changed"}` whenever
+//! the database changes, whether through the daemon or any other process. Subscribers that stop
+//! reading are disconnected rather than holding up everyone else.
+
+use std::{
+    fs::Permissions,
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+
+use serde_json::{Value, json};
+
+use crate::{
+    Tracker, backup, clock,
+    config::Config,
+    error::{Error, Result},
+    models::Client,
+    serve,
+    store::Backend,
+    views::Summary,
+};
+
+/// How often the daemon checks for changes made by other processes while anyone is subscribed,
+/// and whether the daily backup is due. Its own changes are announced straight away.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a subscriber may take to accept an event before it is disconnected
+const SUBSCRIBER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The socket of the daemon for `config`'s database
+pub fn socket_path(config: &Config) -> PathBuf {
+    config.database_path.with_extension("sock")
+}
+
+enum Message {
+    Request { line: String, reply: Sender<String> },
+    Subscribe(UnixStream),
+    Tick,
+}
+
+/// Open the database of `config` and answer requests on its socket until the process is stopped
+pub fn run(config: &Config) -> Result<()> {
+    if config.backend != Backend::Sqlite {
+        return Err(Error::Invalid(
+            "timberd needs the SQLite backend, see `timber convert`".into(),
+        ));
+    }
+    let path = socket_path(config);
+    if UnixStream::connect(&path).is_ok() {
+        return Err(Error::Conflict(format!(
+            "timberd is already running on {}",
+            path.display()
+        )));
+    }
+    // Left behind by a daemon that didn't shut down cleanly
+    let _ = std::fs::remove_file(&path);
+
+    let tracker = Tracker::open(config)?.with_clock(clock::from_env()?);
+    let listener = UnixListener::bind(&path)?;
+    // Anyone who can connect can change the database, so keep the socket to its owner
+    std::fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+    println!(
+        "timberd is serving {} on {}",
+        config.database_path.display(),
+        path.display()
+    );
+
+    let (sender, messages) = mpsc::channel();
+    let accepting = sender.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = accepting.clone();
+            thread::spawn(move || read_requests(stream, sender));
+        }
+    });
+    thread::spawn(move || {
+        while sender.send(Message::Tick).is_ok() {
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+
+    let conn = tracker.connection();
+    let version = || -> Result<(i64, u64)> {
+        // data_version only moves for other connections' commits, total_changes for ours
+        let data_version = conn.query_row("PRAGMA data_version", [], |row| row.get(0))?;
+        Ok((data_version, conn.total_changes()))
+    };
+    let mut last_version = version()?;
+    let mut subscribers: Vec<SyncSender<()>> = vec![];
+    for message in messages {
+        match message {
+            Message::Request { line, reply } => {
+                let _ = reply.send(answer(&tracker, &line));
+            }
+            Message::Subscribe(stream) => subscribers.push(notify(stream)),
+            Message::Tick => {
+                // Commands sent here don't open the database, so they never take the backup
+                if let Err(e) = backup::run_daily(conn, &config.backup_dir, config.backup_retention)
+                {
+                    eprintln!("Warning: Daily backup failed: {e}");
+                }
+                if subscribers.is_empty() {
+                    continue;
+                }
+            }
+        }
+        // A failed check is tried again with the next message rather than stopping the daemon
+        let current = match version() {
+            Ok(current) => current,
+            Err(e) => {
+                eprintln!("Warning: Unable to check the database for changes: {e}");
+                continue;
+            }
+        };
+        if current != last_version {
+            last_version = current;
+            // A full queue already holds an event the subscriber hasn't seen
+            subscribers.retain(|events| {
+                !matches!(events.try_send(()), Err(TrySendError::Disconnected(_)))
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Write change events to `stream` from a thread of its own, so a subscriber that stops reading
+/// only holds up itself. The thread ends, dropping the subscriber, when a write fails or times out.
+fn notify(mut stream: UnixStream) -> SyncSender<()> {
+    let (events, changes) = mpsc::sync_channel(1);
+    thread::spawn(move || {
+        if stream.set_write_timeout(Some(SUBSCRIBER_TIMEOUT)).is_err() {
+            return;
+        }
+        let event = format!("{}\n", json!({ "event": "changed" }));
+        for () in changes {
+            if stream.write_all(event.as_bytes()).is_err() {
+                // Also ends the thread reading its requests
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                return;
+            }
+        }
+    });
+    events
+}
+
+/// Pass each line of a connection to the daemon's thread and write back the answer
+fn read_requests(stream: UnixStream, sender: Sender<Message>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = if is_subscription(&line) {
+            let Ok(events) = writer.try_clone() else {
+                return;
+            };
+            if sender.send(Message::Subscribe(events)).is_err() {
+                return;
+            }
+            json!({ "status": 200, "body": {} }).to_string()
+        } else {
+            let (reply, answered) = mpsc::channel();
+            if sender.send(Message::Request { line, reply }).is_err() {
+                return;
+            }
+            let Ok(response) = answered.recv() else {
+                return;
+            };
+            response
+        };
+        if writeln!(writer, "{response}").is_err() {
+            return;
+        }
+    }
+}
+
+fn is_subscription(line: &str) -> bool {
+    serde_json::from_str::<Value>(line).is_ok_and(|request| request["method"] == "SUBSCRIBE")
+}
+
+fn answer(tracker: &Tracker, line: &str) -> String {
+    let (status, body) = match serde_json::from_str::<Value>(line) {
+        Ok(request) => {
+            let method = request["method"].as_str().unwrap_or_default();
+            let path = request["path"].as_str().unwrap_or_default();
+            let body = match &request["body"] {
+                Value::Null => String::new(),
+                body => body.to_string(),
+            };
+            serve::handle(tracker, method, path, &body)
+        }
+        Err(e) => (
+            400,
+            json!({ "error": format!("The request is not JSON: {e}") }),
+        ),
+    };
+    json!({ "status": status, "body": body }).to_string()
+}
+
+/// A connection to a running `timberd`
+pub struct Daemon {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Daemon {
+    /// Connect to the daemon for `config`'s database, or `None` if none is running
+    pub fn connect(config: &Config) -> Option<Self> {
+        let stream = UnixStream::connect(socket_path(config)).ok()?;
+        // Never leave a command hanging on a daemon that has stopped answering
+        stream.set_read_timeout(Some(Duration::from_secs(2))).ok()?;
+        Some(Daemon {
+            writer: stream.try_clone().ok()?,
+            reader: BufReader::new(stream),
+        })
+    }
+
+    /// Make a request with the method, path and body of the HTTP API, returning the body
+    pub fn request(&mut self, method: &str, path: &str, body: Value) -> Result<Value> {
+        writeln!(
+            self.writer,
+            "{}",
+            json!({ "method": method, "path": path, "body": body })
+        )?;
+        let response = self.read_line()?;
+        let body = response["body"].clone();
+        let message = || body["error"].as_str().unwrap_or_default().to_string();
+        match response["status"].as_u64() {
+            Some(200) => Ok(body),
+            Some(404) => Err(Error::NotFound(message())),
+            Some(409) => Err(Error::Conflict(message())),
+            Some(400) => Err(Error::Invalid(message())),
+            _ => Err(Error::Io(std::io::Error::other(format!(
+                "timberd: {}",
+                message()
+            )))),
+        }
+    }
+
+    /// The active session's client and minutes so far, and today's summary, for `timber status`
+    pub fn status(&mut self) -> Result<(Option<(String, i64)>, Summary)> {
+        let active = self.request("GET", "/sessions/active", Value::Null)?;
+        let active = active["client_name"]
+            .as_str()
+            .map(|name| (name.to_string(), active["minutes"].as_i64().unwrap_or(0)));
+        Ok((active, self.summary("daily")?))
+    }
+
+    /// The minutes per client over the current `range`, `daily`, `weekly` or `monthly`
+    pub fn summary(&mut self, range: &str) -> Result<Summary> {
+        let summary = self.request("GET", &format!("/summary?range={range}"), Value::Null)?;
+        let clients = summary["clients"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|client| {
+                let name = client["name"].as_str().unwrap_or_default().to_string();
+                (name, client["minutes"].as_i64().unwrap_or(0))
+            })
+            .collect();
+        Ok(Summary { clients })
+    }
+
+    /// The clients, or with `name` the ones it could refer to, as by `Store::find_clients_by_name`
+    pub fn clients(&mut self, name: Option<&str>) -> Result<Vec<Client>> {
+        let path = match name {
+            Some(name) => format!("/clients?name={}", encode(name)),
+            None => "/clients".into(),
+        };
+        let clients = self.request("GET", &path, Value::Null)?;
+        Ok(clients
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|client| Client {
+                id: client["id"].as_i64().unwrap_or_default() as i32,
+                name: client["name"].as_str().unwrap_or_default().to_string(),
+                note: client["note"].as_str().map(str::to_string),
+            })
+            .collect())
+    }
+
+    /// Receive a message whenever the database changes, until the daemon stops
+    pub fn subscribe(mut self) -> Result<Receiver<()>> {
+        writeln!(self.writer, "{}", json!({ "method": "SUBSCRIBE" }))?;
+        self.read_line()?;
+        self.reader.get_ref().set_read_timeout(None)?;
+        let (sender, changes) = mpsc::channel();
+        thread::spawn(move || {
+            for line in self.reader.lines() {
+                if line.is_err() || sender.send(()).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(changes)
+    }
+
+    fn read_line(&mut self) -> Result<Value> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(Error::Io(std::io::Error::other(
+                "timberd closed the connection",
+            )));
+        }
+        serde_json::from_str(&line)
+            .map_err(|e| Error::Io(std::io::Error::other(format!("timberd: {e}"))))
+    }
+}
+
+/// Percent-encode `param` for a query string
+fn encode(param: &str) -> String {
+    param
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+// TESTS
+
+#[test]
+fn test_daemon_answers_and_notifies() {
+    let dir = std::env::temp_dir().join(format!("timber-daemon-test-{}", std::process::id()));
+    let config = Config {
+        database_path: dir.join("timber.db"),
+        backup_dir: dir.join("backups"),
+        ..Config::default()
+    };
+    let daemon_config = config.clone();
+    thread::spawn(move || run(&daemon_config));
+    let connect = || {
+        (0..50)
+            .find_map(|_| {
+                thread::sleep(Duration::from_millis(20));
+                Daemon::connect(&config)
+            })
+            .expect("timberd to start")
+    };
+
+    let changes = connect().subscribe().unwrap();
+    let socket = std::fs::metadata(socket_path(&config)).unwrap();
+    assert_eq!(socket.permissions().mode() & 0o777, 0o600);
+    let mut daemon = connect();
+    let created = daemon
+        .request("POST", "/clients", json!({ "name": "Acme" }))
+        .unwrap();
+    assert_eq!(created["id"], 1);
+    assert!(matches!(
+        daemon.request("POST", "/clients", json!({ "name": "Acme" })),
+        Err(Error::Conflict(_))
+    ));
+    changes.recv_timeout(3 * POLL_INTERVAL).unwrap();
+
+    // Changes made without the daemon are noticed too
+    thread::sleep(Duration::from_millis(100));
+    while changes.try_recv().is_ok() {}
+    crate::db::open_db(&config)
+        .unwrap()
+        .execute("INSERT INTO clients (name) VALUES ('Globex')", [])
+        .unwrap();
+    changes.recv_timeout(3 * POLL_INTERVAL).unwrap();
+
+    daemon
+        .request("POST", "/sessions/start", json!({ "client": "acme" }))
+        .unwrap();
+    let (active, summary) = daemon.status().unwrap();
+    assert_eq!(active, Some(("Acme".to_string(), 0)));
+    assert_eq!(summary.clients, vec![("Acme".to_string(), 0)]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
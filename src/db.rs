@@ -1,64 +1,470 @@
-use std::fs;
+use std::{fs, path::Path};
 
 use chrono::{DateTime, Utc};
-use rusqlite::{Connection, OptionalExtension, Result, params};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Result, params};
 
 use crate::{
+    audit, backup,
     config::Config,
+    error::{self, Error},
+    journal, lock,
     models::{Client, Session},
+    sync,
 };
 
-pub fn init_db(config: &Config) -> Connection {
-    fs::create_dir_all(
-        config
-            .database_path
-            .parent()
-            .expect("Invalid database path"),
+pub fn init_db(config: &Config) -> error::Result<Connection> {
+    let existed = config.database_path.exists();
+    let conn = open_db(config)?;
+    init_schema(&conn)?;
+    let version = get_schema_version(&conn);
+    if version > SCHEMA_VERSION {
+        return Err(MigrationError::TooNew(version).into());
+    }
+    if existed && version < SCHEMA_VERSION {
+        // Keep a copy of the old schema in case the migration goes wrong
+        if let Err(e) = backup::create(&conn, &config.backup_dir, "pre-migration") {
+            eprintln!("Warning: Unable to back up the database before migrating: {e}");
+        }
+    }
+    apply_migrations(&conn)?;
+    if let Err(e) = backup::run_daily(&conn, &config.backup_dir, config.backup_retention) {
+        eprintln!("Warning: Unable to take the daily backup: {e}");
+    }
+
+    Ok(conn)
+}
+
+/// Open the database without touching its schema
+pub fn open_db(config: &Config) -> error::Result<Connection> {
+    // Create the database directory if it doesn't exist
+    if let Some(dir) = config.database_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let conn = Connection::open(&config.database_path).map_err(|e| {
+        Error::Config(format!(
+            "Unable to open the database at {}: {e}",
+            config.database_path.display()
+        ))
+    })?;
+    register_functions(&conn)?;
+    Ok(conn)
+}
+
+/// Open someone else's database for reading, e.g. a teammate's for `timber team report`. The
+/// schema is never migrated, so it has to be recent enough to be read as it is.
+pub fn open_read_only(path: &Path) -> error::Result<Connection> {
+    if !path.exists() {
+        return Err(Error::NotFound(format!(
+            "No database was found at {}",
+            path.display()
+        )));
+    }
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let version = get_schema_version(&conn);
+    if version > SCHEMA_VERSION {
+        return Err(MigrationError::TooNew(version).into());
+    }
+    if version == 0 {
+        return Err(Error::Invalid(format!(
+            "{} is not a Timber database",
+            path.display()
+        )));
+    }
+    if version < EPOCH_TIMESTAMPS_VERSION {
+        return Err(Error::Invalid(format!(
+            "{} is at schema version {version}, run `timber --db {} db migrate` to upgrade it",
+            path.display(),
+            path.display()
+        )));
+    }
+    Ok(conn)
+}
+
+/// Create the table holding the schema version, everything else is created by `MIGRATIONS`.
+/// Also registers the functions the triggers call, as every connection that writes comes here.
+pub fn init_schema(conn: &Connection) -> Result<()> {
+    register_functions(conn)?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS meta (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );",
     )
-    .expect("Error: Failed to create database dir"); // Create the database directory if it doesn't exist
-    let conn = Connection::open(&config.database_path).unwrap_or_else(|_| {
-        let default = Config::default();
-        println!(
-            "Error: Unable to find or build database at path [{}], using [{}]",
-            &config.database_path.to_str().unwrap(),
-            default.database_path.to_str().unwrap()
-        );
-        Connection::open(default.database_path).unwrap()
-    });
-    init_schema(&conn);
-    apply_migrations(&conn).expect("Failed to apply migrations");
+}
+
+// Flags that must only affect one connection, like the `--force` lock override, can't live in a
+// table: other processes would see them, and a crash would leave them set. Instead each connection
+// keeps them behind its own `timber_flag(key)` SQL function, which triggers call and which
+// disappears with the connection.
+
+/// Register the SQL functions Timber's triggers call on `conn`, clearing its flags
+pub fn register_functions(conn: &Connection) -> Result<()> {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex, PoisonError},
+    };
+
+    use rusqlite::functions::FunctionFlags;
+
+    let flags: Arc<Mutex<HashMap<String, String>>> = Arc::default();
+    let read = Arc::clone(&flags);
+    conn.create_scalar_function(
+        "timber_flag",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_INNOCUOUS,
+        move |ctx| {
+            let key: String = ctx.get(0)?;
+            let flags = read.lock().unwrap_or_else(PoisonError::into_inner);
+            Ok(flags.get(&key).cloned())
+        },
+    )?;
+    // Only from Timber itself, never from a trigger or view
+    conn.create_scalar_function(
+        "timber_set_flag",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DIRECTONLY,
+        move |ctx| {
+            let key: String = ctx.get(0)?;
+            let mut flags = flags.lock().unwrap_or_else(PoisonError::into_inner);
+            match ctx.get::<Option<String>>(1)? {
+                Some(value) => flags.insert(key, value),
+                None => flags.remove(&key),
+            };
+            Ok(true)
+        },
+    )
+}
+
+/// Set a flag seen only by this connection, replacing its previous value
+pub fn set_connection_flag(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.query_row(
+        "SELECT timber_set_flag(?1, ?2)",
+        params![key, value],
+        |_| Ok(()),
+    )
+}
+
+pub fn clear_connection_flag(conn: &Connection, key: &str) -> Result<()> {
+    conn.query_row("SELECT timber_set_flag(?1, NULL)", [key], |_| Ok(()))
+}
+
+/// Holds a connection flag until dropped
+pub struct ConnectionFlag<'a> {
+    conn: &'a Connection,
+    key: &'a str,
+}
+
+impl<'a> ConnectionFlag<'a> {
+    pub fn set(conn: &'a Connection, key: &'a str, value: &str) -> Result<Self> {
+        set_connection_flag(conn, key, value)?;
+        Ok(ConnectionFlag { conn, key })
+    }
+}
+
+impl Drop for ConnectionFlag<'_> {
+    fn drop(&mut self) {
+        let _ = clear_connection_flag(self.conn, self.key);
+    }
+}
+
+/// SQL giving the value of this connection's flag `key`, or NULL if it isn't set
+pub fn connection_flag(key: &str) -> String {
+    format!("timber_flag('{key}')")
+}
+
+pub struct Migration {
+    pub description: &'static str,
+    apply: fn(&Connection) -> Result<()>,
+}
+
+/// Every schema change in order, migration `i` brings the database to version `i + 1`.
+/// Never edit or reorder an existing entry, append a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "Create clients and sessions",
+        apply: |conn| {
+            // IF NOT EXISTS as databases created before versioning already have these tables
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS clients (
+                    id    INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name  TEXT NOT NULL UNIQUE,
+                    note  TEXT
+                );
+
+                CREATE TABLE IF NOT EXISTS sessions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    client_id INTEGER NOT NULL,
+                    start_timestamp TEXT NOT NULL,
+                    end_timestamp TEXT, 
+                    note TEXT,
+                    FOREIGN KEY (client_id) REFERENCES clients(id)
+                );",
+            )
+        },
+    },
+    Migration {
+        description: "Add session offsets",
+        apply: |conn| {
+            conn.execute(
+                "ALTER TABLE sessions ADD COLUMN offset_minutes INTEGER NOT NULL DEFAULT 0",
+                [],
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        description: "Add client aliases",
+        apply: |conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS client_aliases (
+                    alias TEXT PRIMARY KEY COLLATE NOCASE,
+                    client_id INTEGER NOT NULL,
+                    FOREIGN KEY (client_id) REFERENCES clients(id) ON DELETE CASCADE
+                )",
+                [],
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        description: "Add the undo / redo journal",
+        apply: journal::create_tables,
+    },
+    Migration {
+        description: "Add the audit log",
+        apply: audit::create_tables,
+    },
+    Migration {
+        description: "Lock sessions in closed periods",
+        apply: lock::create_triggers,
+    },
+    Migration {
+        description: "Store session times as indexed epoch seconds",
+        apply: migrate_to_epoch_timestamps,
+    },
+    Migration {
+        description: "Give clients and sessions global ids for syncing",
+        apply: |conn| {
+            sync::create_tables(conn)?;
+            journal::add_uids(conn)
+        },
+    },
+];
+
+/// The schema version `apply_migrations` brings a database to
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    /// The database was created by a newer version of Timber
+    TooNew(u32),
+    Failed {
+        version: u32,
+        source: rusqlite::Error,
+    },
+}
+
+impl std::fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationError::TooNew(version) => write!(
+                f,
+                "The database is at schema version {version}, newer than the supported {SCHEMA_VERSION}"
+            ),
+            MigrationError::Failed { version, source } => {
+                write!(f, "Migration to version {version} failed: {source}")
+            }
+        }
+    }
+}
 
-    conn // we assume conn is always valid here
+impl std::error::Error for MigrationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MigrationError::TooNew(_) => None,
+            MigrationError::Failed { source, .. } => Some(source),
+        }
+    }
 }
 
-pub fn init_schema(conn: &Connection) {
+/// The first schema version `open_read_only` can read, with sessions in epoch seconds
+const EPOCH_TIMESTAMPS_VERSION: u32 = 7;
+
+/// Rebuild `sessions` with integer timestamps, which compare correctly whatever offset they were
+/// recorded with. Rows that cannot be carried over (unreadable timestamps, missing clients) are
+/// moved to `quarantined_sessions` for `timber doctor` to report.
+fn migrate_to_epoch_timestamps(conn: &Connection) -> Result<()> {
+    use rusqlite::types::Value;
+
     conn.execute_batch(
-        "CREATE TABLE IF NOT EXISTS clients (
-            id    INTEGER PRIMARY KEY AUTOINCREMENT,
-            name  TEXT NOT NULL UNIQUE,
-            note  TEXT
+        "CREATE TABLE quarantined_sessions (
+            id INTEGER PRIMARY KEY,
+            client_id INTEGER,
+            start_timestamp TEXT,
+            end_timestamp TEXT,
+            note TEXT,
+            offset_minutes INTEGER,
+            reason TEXT NOT NULL
         );
-        
-        CREATE TABLE IF NOT EXISTS sessions (
+
+        CREATE TABLE sessions_new (
             id INTEGER PRIMARY KEY AUTOINCREMENT,
             client_id INTEGER NOT NULL,
-            start_timestamp TEXT NOT NULL,
-            end_timestamp TEXT, 
+            start_timestamp INTEGER NOT NULL,
+            end_timestamp INTEGER,
             note TEXT,
+            offset_minutes INTEGER NOT NULL DEFAULT 0,
             FOREIGN KEY (client_id) REFERENCES clients(id)
-        );
+        );",
+    )?;
 
-        CREATE TABLE IF NOT EXISTS meta (
-            key TEXT PRIMARY KEY,
-            value TEXT NOT NULL
-        );
+    let to_epoch = |value: &Value| match value {
+        Value::Text(text) => text.parse::<DateTime<Utc>>().ok().map(|t| t.timestamp()),
+        Value::Integer(secs) => Some(*secs),
+        _ => None,
+    };
+    let mut stmt = conn.prepare(
+        "SELECT s.id, s.client_id, s.start_timestamp, s.end_timestamp, s.note, s.offset_minutes,
+            c.id IS NOT NULL
+         FROM sessions s LEFT JOIN clients c ON c.id = s.client_id",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let start: Value = row.get(2)?;
+        let end: Value = row.get(3)?;
+        let reason = if !row.get::<_, bool>(6)? {
+            Some(format!("client {} does not exist", row.get::<_, i64>(1)?))
+        } else {
+            [&start, &end]
+                .into_iter()
+                .find(|value| **value != Value::Null && to_epoch(value).is_none())
+                .map(|value| format!("unreadable timestamp {value:?}"))
+        };
 
-        INSERT OR IGNORE INTO meta (key, value) VALUES ('schema_version', '1');",
-    )
-    .expect("Scema init failed");
+        match reason {
+            None => conn.execute(
+                "INSERT INTO sessions_new
+                    (id, client_id, start_timestamp, end_timestamp, note, offset_minutes)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    to_epoch(&start),
+                    to_epoch(&end),
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, i64>(5)?
+                ],
+            )?,
+            Some(reason) => conn.execute(
+                "INSERT INTO quarantined_sessions
+                    (id, client_id, start_timestamp, end_timestamp, note, offset_minutes, reason)
+                 SELECT id, client_id, start_timestamp, end_timestamp, note, offset_minutes, ?2
+                 FROM sessions WHERE id = ?1",
+                params![row.get::<_, i64>(0)?, reason],
+            )?,
+        };
+    }
+    drop(rows);
+    drop(stmt);
+
+    // Keep ids of deleted sessions from being reused, as the journal and audit log refer to them
+    let last_id: i64 = conn
+        .query_row(
+            "SELECT seq FROM sqlite_sequence WHERE name = 'sessions'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0);
+    conn.execute_batch(
+        "DROP TABLE sessions;
+        ALTER TABLE sessions_new RENAME TO sessions;
+
+        CREATE INDEX sessions_start ON sessions (start_timestamp);
+        CREATE INDEX sessions_end ON sessions (end_timestamp);
+        CREATE INDEX sessions_client ON sessions (client_id, start_timestamp);",
+    )?;
+
+    // Convert the timestamps in journal snapshots too, so earlier commands can still be undone.
+    // Operations on sessions that were quarantined can't be, and are dropped.
+    let mut stmt = conn.prepare(
+        "SELECT id, operation_id,
+            json_extract(before, '$.start_timestamp'), json_extract(before, '$.end_timestamp'),
+            json_extract(after, '$.start_timestamp'), json_extract(after, '$.end_timestamp')
+         FROM operation_changes WHERE table_name = 'sessions'",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        for (idx, snapshot, field) in [
+            (2, "before", "start_timestamp"),
+            (3, "before", "end_timestamp"),
+            (4, "after", "start_timestamp"),
+            (5, "after", "end_timestamp"),
+        ] {
+            let value: Value = row.get(idx)?;
+            if value == Value::Null {
+                continue;
+            }
+            match to_epoch(&value) {
+                Some(secs) => conn.execute(
+                    &format!(
+                        "UPDATE operation_changes SET {snapshot} = json_set({snapshot}, '$.{field}', ?2)
+                         WHERE id = ?1"
+                    ),
+                    params![id, secs],
+                )?,
+                None => conn.execute(
+                    "DELETE FROM operations WHERE id = ?1",
+                    [row.get::<_, i64>(1)?],
+                )?,
+            };
+        }
+    }
+    drop(rows);
+    drop(stmt);
+    // Without foreign keys enforced, deleting an operation leaves its changes behind
+    conn.execute(
+        "DELETE FROM operation_changes WHERE operation_id NOT IN (SELECT id FROM operations)",
+        [],
+    )?;
+    let last_id: i64 = conn.query_row(
+        "SELECT MAX(?1, (SELECT COALESCE(MAX(id), 0) FROM quarantined_sessions))",
+        [last_id],
+        |row| row.get(0),
+    )?;
+    if conn.execute(
+        "UPDATE sqlite_sequence SET seq = MAX(seq, ?1) WHERE name = 'sessions'",
+        [last_id],
+    )? == 0
+    {
+        conn.execute(
+            "INSERT INTO sqlite_sequence (name, seq) VALUES ('sessions', ?1)",
+            [last_id],
+        )?;
+    }
+
+    let locked_before: Option<String> = conn
+        .query_row(
+            "SELECT value FROM meta WHERE key = 'locked_before'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(locked_before) = locked_before.and_then(|t| t.parse::<DateTime<Utc>>().ok()) {
+        conn.execute(
+            "UPDATE meta SET value = ?1 WHERE key = 'locked_before'",
+            [locked_before.timestamp().to_string()],
+        )?;
+    }
+
+    // Dropping the old table dropped its triggers
+    journal::create_triggers(conn)?;
+    audit::create_triggers(conn)?;
+    lock::create_triggers(conn)
 }
 
-fn get_schema_version(conn: &Connection) -> u32 {
+pub fn get_schema_version(conn: &Connection) -> u32 {
     conn.query_row(
         "SELECT value FROM meta WHERE key = 'schema_version'",
         [],
@@ -72,48 +478,98 @@ fn get_schema_version(conn: &Connection) -> u32 {
 
 fn update_schema_version(conn: &Connection, new_version: u32) -> rusqlite::Result<()> {
     conn.execute(
-        "UPDATE meta SET value = ?1 WHERE key = 'schema_version'",
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('schema_version', ?1)",
         [new_version.to_string()],
     )?;
     Ok(())
 }
 
-pub fn apply_migrations(conn: &Connection) -> rusqlite::Result<()> {
-    let mut version = get_schema_version(conn);
+pub fn apply_migrations(conn: &Connection) -> Result<(), MigrationError> {
+    migrate_to(conn, SCHEMA_VERSION)
+}
 
-    if version < 2 {
-        // Example migration: add 'tag' column to sessions
-        conn.execute(
-            "ALTER TABLE sessions ADD COLUMN offset_minutes INTEGER NOT NULL DEFAULT 0",
-            [],
-        )?;
-        version = 2;
-        update_schema_version(conn, version)?;
+/// Apply pending migrations up to `target`, each in its own transaction so that a failure
+/// leaves the database at the last version that succeeded
+fn migrate_to(conn: &Connection, target: u32) -> Result<(), MigrationError> {
+    let current = get_schema_version(conn);
+    if current > SCHEMA_VERSION {
+        return Err(MigrationError::TooNew(current));
+    }
+    for version in current + 1..=target {
+        let migration = &MIGRATIONS[version as usize - 1];
+        let failed = |source| MigrationError::Failed { version, source };
+        let tx = conn.unchecked_transaction().map_err(failed)?;
+        (migration.apply)(&tx).map_err(failed)?;
+        update_schema_version(&tx, version).map_err(failed)?;
+        tx.commit().map_err(failed)?;
     }
     Ok(())
 }
 
+/// Columns read by `session_from_row`
+const SESSION_COLUMNS: &str = "id, client_id, start_timestamp, end_timestamp, note, offset_minutes";
+
+fn from_epoch(row: &rusqlite::Row, idx: usize) -> Result<Option<DateTime<Utc>>> {
+    match row.get::<_, Option<i64>>(idx)? {
+        Some(secs) => DateTime::from_timestamp(secs, 0)
+            .map(Some)
+            .ok_or(rusqlite::Error::IntegralValueOutOfRange(idx, secs)),
+        None => Ok(None),
+    }
+}
+
+/// The one place sessions are converted from their stored form
+fn session_from_row(row: &rusqlite::Row) -> Result<Session> {
+    Ok(Session {
+        id: row.get(0)?,
+        client_id: row.get(1)?,
+        start_timestamp: from_epoch(row, 2)?.ok_or(rusqlite::Error::InvalidColumnType(
+            2,
+            "start_timestamp".into(),
+            rusqlite::types::Type::Null,
+        ))?,
+        end_timestamp: from_epoch(row, 3)?,
+        note: row.get(4)?,
+        offset_minutes: row.get(5)?,
+    })
+}
+
 pub fn store_session(conn: &Connection, session: &Session) -> Result<i32, rusqlite::Error> {
     let _ = conn.execute(
         "INSERT INTO sessions (client_id, start_timestamp, end_timestamp, note, offset_minutes) VALUES (?1, ?2, ?3, ?4, ?5)",
-        params![session.client_id, session.start_timestamp, session.end_timestamp, session.note, session.offset_minutes],
+        params![
+            session.client_id,
+            session.start_timestamp.timestamp(),
+            session.end_timestamp.map(|end| end.timestamp()),
+            session.note,
+            session.offset_minutes
+        ],
     )?;
     Ok(conn.last_insert_rowid() as i32)
 }
 
+/// Like [`store_session`], but keeping the session's id
+pub fn insert_session(conn: &Connection, session: &Session) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO sessions (id, client_id, start_timestamp, end_timestamp, note, offset_minutes) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            session.id,
+            session.client_id,
+            session.start_timestamp.timestamp(),
+            session.end_timestamp.map(|end| end.timestamp()),
+            session.note,
+            session.offset_minutes
+        ],
+    )?;
+    Ok(())
+}
+
 pub fn get_session_by_id(conn: &Connection, id: i32) -> Result<Session, rusqlite::Error> {
-    let mut stmt = conn.prepare("SELECT id, client_id, start_timestamp, end_timestamp, note, offset_minutes FROM sessions WHERE id = ?1 LIMIT 1")?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {SESSION_COLUMNS} FROM sessions WHERE id = ?1 LIMIT 1"
+    ))?;
 
-    stmt.query_row(params![id], |row| {
-        Ok(Session {
-            id: row.get(0)?,
-            client_id: row.get(1)?,
-            start_timestamp: row.get(2)?,
-            end_timestamp: row.get::<_, Option<String>>(3)?,
-            note: row.get::<_, Option<String>>(4)?,
-            offset_minutes: row.get(5)?,
-        })
-    })
+    stmt.query_row(params![id], session_from_row)
 }
 
 pub fn get_session_id_by_name(
@@ -126,6 +582,18 @@ pub fn get_session_id_by_name(
     .optional()
 }
 
+/// Sessions that could not be migrated to epoch timestamps, as (session id, reason) pairs
+pub fn list_quarantined_sessions(conn: &Connection) -> Result<Vec<(i32, String)>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT id, reason FROM quarantined_sessions ORDER BY id")?;
+    let sessions = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    sessions.collect()
+}
+
+pub fn remove_quarantined_session(conn: &Connection, id: i32) -> Result<(), rusqlite::Error> {
+    conn.execute("DELETE FROM quarantined_sessions WHERE id = ?1", [id])?;
+    Ok(())
+}
+
 pub fn remove_session(conn: &Connection, id: i32) -> Result<(), rusqlite::Error> {
     conn.execute("DELETE FROM sessions WHERE id == ?1", [id])?;
     Ok(())
@@ -136,45 +604,17 @@ pub fn list_sessions(
     client_id: Option<i32>,
 ) -> Result<Vec<Session>, rusqlite::Error> {
     if let Some(id) = client_id {
-        let mut stmt = conn.prepare(
-            "SELECT id, 
-        client_id, 
-        start_timestamp, 
-        end_timestamp, 
-        note,
-        offset_minutes FROM sessions WHERE client_id = ?1 ORDER BY start_timestamp DESC",
-        )?;
-        let session_iter = stmt.query_map([id], |row| {
-            Ok(Session {
-                id: row.get(0)?,
-                client_id: row.get(1)?,
-                start_timestamp: row.get(2)?,
-                end_timestamp: row.get(3)?,
-                note: row.get(4)?,
-                offset_minutes: row.get(5)?,
-            })
-        })?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {SESSION_COLUMNS} FROM sessions WHERE client_id = ?1 ORDER BY start_timestamp DESC"
+        ))?;
+        let session_iter = stmt.query_map([id], session_from_row)?;
         Ok(session_iter.collect::<Result<Vec<Session>, _>>()?)
     } else {
-        let mut stmt = conn.prepare(
-            "SELECT id, 
-        client_id, 
-        start_timestamp, 
-        end_timestamp, 
-        note,
-        offset_minutes FROM sessions ORDER BY start_timestamp DESC",
-        )?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {SESSION_COLUMNS} FROM sessions ORDER BY start_timestamp DESC"
+        ))?;
 
-        let session_iter = stmt.query_map([], |row| {
-            Ok(Session {
-                id: row.get(0)?,
-                client_id: row.get(1)?,
-                start_timestamp: row.get(2)?,
-                end_timestamp: row.get(3)?,
-                note: row.get(4)?,
-                offset_minutes: row.get(5)?,
-            })
-        })?;
+        let session_iter = stmt.query_map([], session_from_row)?;
         Ok(session_iter.collect::<Result<Vec<Session>, _>>()?)
     }
 }
@@ -188,7 +628,7 @@ pub fn store_client(conn: &Connection, client: &Client) -> Result<Option<i32>, r
         Err(rusqlite::Error::SqliteFailure(err, _))
             if err.code == rusqlite::ErrorCode::ConstraintViolation =>
         {
-            println!("Client already exists! Insert ignored.");
+            // The caller reports the conflict
             return Ok(Option::None);
         }
         Err(e) => return Err(e),
@@ -196,6 +636,15 @@ pub fn store_client(conn: &Connection, client: &Client) -> Result<Option<i32>, r
     Ok(Some(conn.last_insert_rowid() as i32)) // ! Do we want to return this if an error is encountered above?
 }
 
+/// Like [`store_client`], but keeping the client's id
+pub fn insert_client(conn: &Connection, client: &Client) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO clients (id, name, note) VALUES (?1, ?2, ?3)",
+        params![client.id, client.name, client.note],
+    )?;
+    Ok(())
+}
+
 pub fn get_client_by_id(conn: &Connection, id: i32) -> Result<Client, rusqlite::Error> {
     let mut stmt = conn.prepare("SELECT id, name, note FROM clients WHERE id = ?1 LIMIT 1")?;
 
@@ -218,6 +667,88 @@ pub fn get_client_id_by_name(
     .optional()
 }
 
+/// Resolve a user-typed client name, trying progressively looser matches:
+/// exact name, case-insensitive name, alias, then unique name/alias prefix.
+/// The first stage with any match wins, so more than one result means the input is ambiguous.
+pub fn find_clients_by_name(conn: &Connection, name: &str) -> Result<Vec<Client>, rusqlite::Error> {
+    if let Some(id) = get_client_id_by_name(conn, name.to_string())? {
+        return Ok(vec![get_client_by_id(conn, id)?]);
+    }
+
+    let escaped = name
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    let stages = [
+        (
+            "SELECT id, name, note FROM clients WHERE name = ?1 COLLATE NOCASE ORDER BY name",
+            name,
+        ),
+        (
+            "SELECT c.id, c.name, c.note FROM clients c
+             JOIN client_aliases a ON a.client_id = c.id
+             WHERE a.alias = ?1",
+            name,
+        ),
+        (
+            "SELECT DISTINCT c.id, c.name, c.note FROM clients c
+             LEFT JOIN client_aliases a ON a.client_id = c.id
+             WHERE c.name LIKE ?1 || '%' ESCAPE '\\' OR a.alias LIKE ?1 || '%' ESCAPE '\\'
+             ORDER BY c.name",
+            escaped.as_str(),
+        ),
+    ];
+    for (sql, param) in stages {
+        let mut stmt = conn.prepare(sql)?;
+        let clients = stmt
+            .query_map([param], |row| {
+                Ok(Client {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    note: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<Client>, _>>()?;
+        if !clients.is_empty() {
+            return Ok(clients);
+        }
+    }
+    Ok(vec![])
+}
+
+pub fn add_client_alias(
+    conn: &Connection,
+    client_id: i32,
+    alias: &str,
+) -> Result<Option<()>, rusqlite::Error> {
+    match conn.execute(
+        "INSERT INTO client_aliases (alias, client_id) VALUES (?1, ?2)",
+        params![alias, client_id],
+    ) {
+        Ok(_) => Ok(Some(())),
+        Err(rusqlite::Error::SqliteFailure(err, _))
+            if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub fn remove_client_alias(conn: &Connection, alias: &str) -> Result<bool, rusqlite::Error> {
+    Ok(conn.execute("DELETE FROM client_aliases WHERE alias = ?1", [alias])? > 0)
+}
+
+pub fn list_client_aliases(
+    conn: &Connection,
+    client_id: i32,
+) -> Result<Vec<String>, rusqlite::Error> {
+    let mut stmt =
+        conn.prepare("SELECT alias FROM client_aliases WHERE client_id = ?1 ORDER BY alias")?;
+    let aliases = stmt.query_map([client_id], |row| row.get(0))?;
+    aliases.collect()
+}
+
 pub fn remove_client(conn: &Connection, id: i32) -> Result<(), rusqlite::Error> {
     conn.execute("DELETE FROM clients WHERE id = ?1", [id])?;
     Ok(())
@@ -237,24 +768,15 @@ pub fn list_clients(conn: &Connection) -> Result<Vec<Client>, rusqlite::Error> {
 }
 
 pub fn get_active_session(conn: &Connection) -> Result<Option<Session>, rusqlite::Error> {
-    let mut stmt = conn.prepare(
-        "SELECT id, client_id, start_timestamp, end_timestamp, note, offset_minutes
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {SESSION_COLUMNS}
          FROM sessions
          WHERE end_timestamp IS NULL
          ORDER BY start_timestamp DESC
-         LIMIT 1",
-    )?;
+         LIMIT 1"
+    ))?;
 
-    let result = stmt.query_row([], |row| {
-        Ok(Session {
-            id: row.get(0)?,
-            client_id: row.get(1)?,
-            start_timestamp: row.get(2)?,
-            end_timestamp: None,
-            note: row.get(4)?,
-            offset_minutes: row.get(5)?,
-        })
-    });
+    let result = stmt.query_row([], session_from_row);
 
     match result {
         Ok(session) => Ok(Some(session)),
@@ -263,6 +785,15 @@ pub fn get_active_session(conn: &Connection) -> Result<Option<Session>, rusqlite
     }
 }
 
+/// The latest end time of any finished session
+pub fn get_latest_session_end(conn: &Connection) -> Result<Option<DateTime<Utc>>, rusqlite::Error> {
+    let latest: Option<i64> =
+        conn.query_row("SELECT MAX(end_timestamp) FROM sessions", [], |row| {
+            row.get(0)
+        })?;
+    Ok(latest.and_then(|end| DateTime::from_timestamp(end, 0)))
+}
+
 pub fn commit_session_changes(conn: &Connection, session: &Session) -> Result<(), rusqlite::Error> {
     match conn.execute(
         "UPDATE sessions
@@ -270,8 +801,8 @@ pub fn commit_session_changes(conn: &Connection, session: &Session) -> Result<()
         WHERE id=?6",
         params![
             session.client_id,
-            session.start_timestamp,
-            session.end_timestamp,
+            session.start_timestamp.timestamp(),
+            session.end_timestamp.map(|end| end.timestamp()),
             session.note,
             session.offset_minutes,
             session.id
@@ -294,29 +825,46 @@ pub fn commit_client_changes(conn: &Connection, client: &Client) -> Result<(), r
     }
 }
 
+pub fn merge_clients(
+    conn: &Connection,
+    from_id: i32,
+    into_id: i32,
+) -> Result<usize, rusqlite::Error> {
+    // Both statements run in one transaction so a failure never leaves sessions split between clients
+    let tx = conn.unchecked_transaction()?;
+    let moved = tx.execute(
+        "UPDATE sessions SET client_id = ?1 WHERE client_id = ?2",
+        params![into_id, from_id],
+    )?;
+    tx.execute(
+        "UPDATE client_aliases SET client_id = ?1 WHERE client_id = ?2",
+        params![into_id, from_id],
+    )?;
+    // Keep the old name resolvable, since merges are usually cleaning up typos
+    tx.execute(
+        "INSERT OR IGNORE INTO client_aliases (alias, client_id)
+         SELECT name, ?1 FROM clients WHERE id = ?2",
+        params![into_id, from_id],
+    )?;
+    tx.execute("DELETE FROM clients WHERE id = ?1", [from_id])?;
+    tx.commit()?;
+    Ok(moved)
+}
+
 pub fn get_sessions_within_range(
     conn: &Connection,
     start: &DateTime<Utc>,
     end: &DateTime<Utc>,
 ) -> Result<Vec<Session>, rusqlite::Error> {
-    let mut stmt = conn.prepare(
-        "SELECT id, client_id, start_timestamp, end_timestamp, note, offset_minutes
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {SESSION_COLUMNS}
          FROM sessions
          WHERE start_timestamp >= ?1 AND start_timestamp <= ?2
-         ORDER BY start_timestamp ASC",
-    )?;
+         ORDER BY start_timestamp ASC"
+    ))?;
 
     let sessions = stmt
-        .query_map([start.to_rfc3339(), end.to_rfc3339()], |row| {
-            Ok(Session {
-                id: row.get(0)?,
-                client_id: row.get(1)?,
-                start_timestamp: row.get(2)?,
-                end_timestamp: row.get(3)?,
-                note: row.get(4)?,
-                offset_minutes: row.get(5)?,
-            })
-        })?
+        .query_map([start.timestamp(), end.timestamp()], session_from_row)?
         .collect::<Result<Vec<_>>>()?;
 
     Ok(sessions)
@@ -324,10 +872,42 @@ pub fn get_sessions_within_range(
 
 // TESTS
 
+#[test]
+fn test_connection_flags() {
+    let conn = Connection::open_in_memory().unwrap();
+    init_schema(&conn).unwrap();
+    let flag = |conn: &Connection| -> Option<String> {
+        conn.query_row(&format!("SELECT {}", connection_flag("key")), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    };
+
+    // Unlike an ATTACH, setting a flag works inside a transaction
+    let tx = conn.unchecked_transaction().unwrap();
+    {
+        let _flag = ConnectionFlag::set(&tx, "key", "value").unwrap();
+        assert_eq!(flag(&tx).as_deref(), Some("value"));
+    }
+    assert_eq!(flag(&tx), None);
+    tx.commit().unwrap();
+
+    // Triggers can read flags but never set them
+    assert!(
+        conn.execute_batch(
+            "CREATE TABLE t (x);
+            CREATE TRIGGER t_insert AFTER INSERT ON t BEGIN SELECT timber_set_flag('key', 'x'); END;
+            INSERT INTO t VALUES (1);"
+        )
+        .is_err()
+    );
+}
+
 #[test]
 fn test_store_client() {
     let conn = rusqlite::Connection::open_in_memory().unwrap();
-    init_schema(&conn); // maybe split schema creation into its own fn
+    init_schema(&conn).unwrap(); // maybe split schema creation into its own fn
+    apply_migrations(&conn).unwrap();
 
     let client_id = insert_test_client(&conn);
     assert!(client_id > 0);
@@ -336,7 +916,8 @@ fn test_store_client() {
 #[test]
 fn test_get_client() {
     let conn = rusqlite::Connection::open_in_memory().unwrap();
-    init_schema(&conn); // maybe split schema creation into its own fn
+    init_schema(&conn).unwrap(); // maybe split schema creation into its own fn
+    apply_migrations(&conn).unwrap();
 
     let client_id = insert_test_client(&conn);
     assert!(get_client_by_id(&conn, client_id).unwrap().id == client_id) // Assert we pull the right client
@@ -345,10 +926,10 @@ fn test_get_client() {
 #[test]
 fn test_store_session() {
     let conn = rusqlite::Connection::open_in_memory().unwrap();
-    init_schema(&conn); // maybe split schema creation into its own fn
+    init_schema(&conn).unwrap(); // maybe split schema creation into its own fn
+    apply_migrations(&conn).unwrap();
 
-    insert_test_client(&conn);
-    let session_id = insert_test_session(&conn);
+    let session_id = insert_test_session(&conn); // also inserts the client
 
     assert!(session_id > 0);
 }
@@ -356,7 +937,8 @@ fn test_store_session() {
 #[test]
 fn test_get_session() {
     let conn = rusqlite::Connection::open_in_memory().unwrap();
-    init_schema(&conn); // maybe split schema creation into its own fn
+    init_schema(&conn).unwrap(); // maybe split schema creation into its own fn
+    apply_migrations(&conn).unwrap();
 
     let session_id = insert_test_session(&conn);
     assert!(get_client_by_id(&conn, session_id).unwrap().id == session_id) // Assert we pull the right client
@@ -365,7 +947,8 @@ fn test_get_session() {
 #[test]
 fn test_get_unfinished_session_empty_db() {
     let conn = rusqlite::Connection::open_in_memory().unwrap();
-    init_schema(&conn); // maybe split schema creation into its own fn
+    init_schema(&conn).unwrap(); // maybe split schema creation into its own fn
+    apply_migrations(&conn).unwrap();
 
     get_active_session(&conn).unwrap();
 }
@@ -373,7 +956,8 @@ fn test_get_unfinished_session_empty_db() {
 #[test]
 fn test_get_unfinished_session() {
     let conn = rusqlite::Connection::open_in_memory().unwrap();
-    init_schema(&conn); // maybe split schema creation into its own fn
+    init_schema(&conn).unwrap(); // maybe split schema creation into its own fn
+    apply_migrations(&conn).unwrap();
     let session_id = insert_test_session(&conn);
     match get_active_session(&conn) {
         Ok(sesh) => assert!(sesh.unwrap().id == session_id),
@@ -381,9 +965,67 @@ fn test_get_unfinished_session() {
     };
 }
 
-fn insert_test_client(conn: &Connection) -> i32 {
-    init_schema(conn); // maybe split schema creation into its own fn
+#[test]
+fn test_merge_clients() {
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    init_schema(&conn).unwrap();
+    apply_migrations(&conn).unwrap();
+
+    insert_test_session(&conn);
+    let into_id = store_client(
+        &conn,
+        &Client {
+            id: 0,
+            name: "Alice Corp".into(),
+            note: None,
+        },
+    )
+    .unwrap()
+    .unwrap();
 
+    assert_eq!(merge_clients(&conn, 1, into_id).unwrap(), 1);
+    assert!(get_client_by_id(&conn, 1).is_err()); // Source client is gone
+    assert_eq!(list_sessions(&conn, Some(into_id)).unwrap().len(), 1);
+    assert_eq!(find_clients_by_name(&conn, "alice").unwrap()[0].id, into_id); // Old name is now an alias
+}
+
+#[test]
+fn test_find_clients_by_name() {
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    init_schema(&conn).unwrap();
+    apply_migrations(&conn).unwrap();
+
+    let alice_id = insert_test_client(&conn);
+    for name in ["Acme", "Acorn"] {
+        store_client(
+            &conn,
+            &Client {
+                id: 0,
+                name: name.into(),
+                note: None,
+            },
+        )
+        .unwrap();
+    }
+    add_client_alias(&conn, alice_id, "wonderland").unwrap();
+
+    let ids = |query: &str| -> Vec<i32> {
+        find_clients_by_name(&conn, query)
+            .unwrap()
+            .iter()
+            .map(|c| c.id)
+            .collect()
+    };
+    assert_eq!(ids("ALICE"), vec![alice_id]); // Case-insensitive
+    assert_eq!(ids("al"), vec![alice_id]); // Unique prefix
+    assert_eq!(ids("Wonder"), vec![alice_id]); // Alias prefix
+    assert_eq!(ids("ac").len(), 2); // Ambiguous
+    assert_eq!(ids("acm").len(), 1);
+    assert!(ids("%").is_empty()); // LIKE wildcards are matched literally
+}
+
+#[cfg(test)]
+fn insert_test_client(conn: &Connection) -> i32 {
     let client = Client {
         id: 0,
         name: "Alice".into(),
@@ -395,16 +1037,190 @@ fn insert_test_client(conn: &Connection) -> i32 {
         .expect("This is a test and should not fail")
 }
 
+#[cfg(test)]
 fn insert_test_session(conn: &Connection) -> i32 {
     insert_test_client(conn); // We need a client to insert a session
     let session = Session {
         client_id: 1,
         id: 0,
-        start_timestamp: Utc::now().to_rfc3339(),
+        start_timestamp: Utc::now(),
         end_timestamp: Option::None,
         note: Option::Some("testing".to_string()),
         offset_minutes: 5,
     };
 
-    store_session(&conn, &session).unwrap()
+    store_session(conn, &session).unwrap()
+}
+
+#[test]
+fn test_migrate_from_every_version() {
+    let schema = |conn: &Connection| -> Vec<String> {
+        let mut stmt = conn
+            .prepare("SELECT sql FROM sqlite_master WHERE sql IS NOT NULL ORDER BY name")
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+    };
+    let fresh = Connection::open_in_memory().unwrap();
+    init_schema(&fresh).unwrap();
+    apply_migrations(&fresh).unwrap();
+
+    for version in 0..SCHEMA_VERSION {
+        // A fixture database as it was left by a Timber at `version`
+        let conn = Connection::open_in_memory().unwrap();
+        init_schema(&conn).unwrap();
+        migrate_to(&conn, version).unwrap();
+        if version >= 1 {
+            // Times have been stored as epoch seconds since version 7
+            let (start, end) = if version >= 7 {
+                ("1735722000", "1735725600")
+            } else {
+                ("'2025-01-01T09:00:00+00:00'", "'2025-01-01T10:00:00+00:00'")
+            };
+            conn.execute_batch(&format!(
+                "INSERT INTO clients (name) VALUES ('Alice');
+                INSERT INTO sessions (client_id, start_timestamp, end_timestamp)
+                VALUES (1, {start}, {end});"
+            ))
+            .unwrap();
+        }
+
+        apply_migrations(&conn).unwrap();
+        assert_eq!(get_schema_version(&conn), SCHEMA_VERSION);
+        assert_eq!(schema(&conn), schema(&fresh), "from version {version}");
+        if version >= 1 {
+            let session = get_session_by_id(&conn, 1).unwrap();
+            assert_eq!(session.offset_minutes, 0);
+            assert_eq!(
+                get_client_by_id(&conn, session.client_id).unwrap().name,
+                "Alice"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_migrate_real_databases() {
+    // Dumps of databases written by each released version of Timber
+    const FIXTURES: [(u32, &str); 1] = [(2, include_str!("../tests/fixtures/v2.sql"))];
+    // Tables created before versioning keep their original formatting
+    let schema = |conn: &Connection| -> Vec<String> {
+        let mut stmt = conn
+            .prepare("SELECT sql FROM sqlite_master WHERE sql IS NOT NULL ORDER BY name")
+            .unwrap();
+        stmt.query_map([], |row| row.get::<_, String>(0))
+            .unwrap()
+            .map(|sql| {
+                sql.unwrap()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect()
+    };
+    let fresh = Connection::open_in_memory().unwrap();
+    init_schema(&fresh).unwrap();
+    apply_migrations(&fresh).unwrap();
+
+    for (version, dump) in FIXTURES {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(dump).unwrap();
+        assert_eq!(get_schema_version(&conn), version);
+
+        init_schema(&conn).unwrap();
+        apply_migrations(&conn).unwrap();
+        assert_eq!(schema(&conn), schema(&fresh), "from version {version}");
+
+        let design = get_session_by_id(&conn, 1).unwrap();
+        assert_eq!(design.note.as_deref(), Some("Design review"));
+        assert!(design.end_timestamp.is_some());
+        assert_eq!(
+            get_client_by_id(&conn, design.client_id).unwrap().name,
+            "Acme"
+        );
+        assert_eq!(get_session_by_id(&conn, 2).unwrap().offset_minutes, 15);
+        assert!(get_client_by_id(&conn, 2).unwrap().note.is_none());
+    }
+}
+
+#[test]
+fn test_refuses_newer_database() {
+    let conn = Connection::open_in_memory().unwrap();
+    init_schema(&conn).unwrap();
+    apply_migrations(&conn).unwrap();
+    update_schema_version(&conn, SCHEMA_VERSION + 1).unwrap();
+
+    assert!(matches!(
+        apply_migrations(&conn),
+        Err(MigrationError::TooNew(version)) if version == SCHEMA_VERSION + 1
+    ));
+}
+
+#[test]
+fn test_epoch_timestamp_migration() {
+    let conn = Connection::open_in_memory().unwrap();
+    init_schema(&conn).unwrap();
+    migrate_to(&conn, 6).unwrap();
+    conn.execute_batch(
+        "INSERT INTO clients (name) VALUES ('Alice');
+        INSERT INTO sessions (client_id, start_timestamp) VALUES
+            (1, '2025-01-01T09:00:00+00:00'),
+            (1, '2025-01-01T10:30:00+02:00'),
+            (1, 'last tuesday');",
+    )
+    .unwrap();
+    apply_migrations(&conn).unwrap();
+
+    // 10:30+02:00 sorts after 09:00Z as text, but is 08:30Z
+    let from = "2025-01-01T08:00:00Z".parse().unwrap();
+    let to = "2025-01-01T08:45:00Z".parse().unwrap();
+    let sessions = get_sessions_within_range(&conn, &from, &to).unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].id, 2);
+
+    let quarantined = list_quarantined_sessions(&conn).unwrap();
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].0, 3);
+    // The quarantined id is not reused
+    let mut session = sessions[0].clone();
+    session.id = 0;
+    assert_eq!(store_session(&conn, &session).unwrap(), 4);
+}
+
+#[test]
+fn test_epoch_timestamp_migration_keeps_the_journal() {
+    let conn = Connection::open_in_memory().unwrap();
+    init_schema(&conn).unwrap();
+    migrate_to(&conn, 6).unwrap();
+    conn.execute("INSERT INTO clients (name) VALUES ('Alice')", [])
+        .unwrap();
+    for (command, sql) in [
+        (
+            "session start Alice",
+            "INSERT INTO sessions (client_id, start_timestamp, end_timestamp)
+             VALUES (1, '2025-01-01T09:00:00+00:00', '2025-01-01T10:00:00+00:00')",
+        ),
+        (
+            "session start Alice",
+            "INSERT INTO sessions (client_id, start_timestamp) VALUES (1, 'last tuesday')",
+        ),
+        ("session rm 1", "DELETE FROM sessions WHERE id = 1"),
+    ] {
+        let _op = journal::Operation::begin(&conn, command).unwrap();
+        conn.execute(sql, []).unwrap();
+    }
+    apply_migrations(&conn).unwrap();
+
+    assert_eq!(journal::undo(&conn).unwrap(), "session rm 1");
+    let session = get_session_by_id(&conn, 1).unwrap();
+    assert_eq!(session.end_timestamp, "2025-01-01T10:00:00Z".parse().ok());
+    // The session with an unreadable start was quarantined, so its start can't be undone
+    assert_eq!(journal::undo(&conn).unwrap(), "session start Alice");
+    assert!(get_session_by_id(&conn, 1).is_err());
+    assert!(matches!(
+        journal::undo(&conn),
+        Err(journal::UndoError::NothingToDo)
+    ));
 }
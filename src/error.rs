@@ -0,0 +1,232 @@
+use std::fmt;
+
+use crate::{db::MigrationError, journal::UndoError, lock, models::Client};
+
+/// Everything that can go wrong in Timber, with messages meant for the person at the terminal
+#[derive(Debug)]
+pub enum Error {
+    /// A session, client or backup that doesn't exist
+    NotFound(String),
+    /// Something already exists or is in the way, e.g. starting a session while one is active
+    Conflict(String),
+    /// A request that can't be carried out, e.g. a session ending before it started
+    Invalid(String),
+    /// A client name matching several clients, when there is no one to pick between them
+    Ambiguous {
+        input: String,
+        candidates: Vec<Client>,
+    },
+    /// The config file or an environment variable is unusable
+    Config(String),
+    Migration(MigrationError),
+    Io(std::io::Error),
+    Db(rusqlite::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The process exit code for this error, so scripts can tell failures apart:
+    ///
+    /// | Code | Meaning                                                |
+    /// |------|--------------------------------------------------------|
+    /// | 0    | Success                                                |
+    /// | 1    | Any other failure, e.g. an unusable config file        |
+    /// | 2    | The command line could not be parsed (from clap)       |
+    /// | 3    | A client, session, alias, profile or backup is missing |
+    /// | 4    | A conflict, e.g. a session is already active           |
+    /// | 5    | Invalid input, e.g. a start time in the future         |
+    /// | 6    | The database could not be read, written or migrated    |
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::NotFound(_) => 3,
+            Error::Conflict(_) => 4,
+            Error::Invalid(_) | Error::Ambiguous { .. } => 5,
+            Error::Db(_) | Error::Migration(_) => 6,
+            Error::Config(_) | Error::Io(_) => 1,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        match err {
+            // A trigger refusing to change a locked period, which is in the way rather than broken
+            rusqlite::Error::SqliteFailure(_, Some(message)) if message == lock::LOCKED_MESSAGE => {
+                Error::Conflict(message)
+            }
+            err => Error::Db(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<MigrationError> for Error {
+    fn from(err: MigrationError) -> Self {
+        Error::Migration(err)
+    }
+}
+
+impl From<UndoError> for Error {
+    fn from(err: UndoError) -> Self {
+        match err {
+            UndoError::NothingToDo => Error::NotFound(err.to_string()),
+            UndoError::Diverged(_) => Error::Conflict(err.to_string()),
+            UndoError::Db(err) => Error::from(err),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(what)
+            | Error::Conflict(what)
+            | Error::Invalid(what)
+            | Error::Config(what) => {
+                write!(f, "{what}")
+            }
+            Error::Ambiguous { input, candidates } => {
+                write!(f, "'{input}' matches several clients:")?;
+                for client in candidates {
+                    write!(f, "\n  {} (id {})", client.name, client.id)?;
+                }
+                Ok(())
+            }
+            Error::Migration(err @ MigrationError::TooNew(_)) => {
+                write!(f, "{err}, please upgrade Timber")
+            }
+            Error::Migration(err) => write!(
+                f,
+                "{err}. A backup was taken before migrating, see `timber backup list`"
+            ),
+            Error::Io(err) => write!(f, "{err}"),
+            Error::Db(rusqlite::Error::SqliteFailure(err, message)) => match err.code {
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked => write!(
+                    f,
+                    "The database is in use by another program, please try again"
+                ),
+                rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase => write!(
+                    f,
+                    "The database file is damaged, restore a backup with `timber backup restore`"
+                ),
+                rusqlite::ErrorCode::CannotOpen => write!(f, "Unable to open the database file"),
+                rusqlite::ErrorCode::ReadOnly => write!(f, "The database file is read-only"),
+                // Trigger messages (e.g. locked periods) are already meant for people
+                _ => match message {
+                    Some(message) => write!(f, "{message}"),
+                    None => write!(f, "Database error: {err}"),
+                },
+            },
+            Error::Db(err) => write!(f, "Database error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Migration(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::Db(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// TESTS
+
+#[test]
+fn test_friendly_messages() {
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    conn.execute_batch(
+        "CREATE TABLE t (x);
+        CREATE TRIGGER t_locked BEFORE INSERT ON t BEGIN
+            SELECT RAISE(ABORT, 'This session is locked');
+        END;",
+    )
+    .unwrap();
+    let raised = conn.execute("INSERT INTO t VALUES (1)", []).unwrap_err();
+    assert_eq!(Error::from(raised).to_string(), "This session is locked");
+
+    let corrupt = rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CORRUPT),
+        None,
+    );
+    assert!(
+        Error::from(corrupt)
+            .to_string()
+            .contains("timber backup restore")
+    );
+}
+
+#[test]
+fn test_exit_codes() {
+    assert_eq!(Error::NotFound(String::new()).exit_code(), 3);
+    let ambiguous = Error::Ambiguous {
+        input: "ac".into(),
+        candidates: vec![],
+    };
+    assert_eq!(ambiguous.exit_code(), 5);
+    assert_eq!(Error::from(UndoError::NothingToDo).exit_code(), 3);
+    assert_eq!(
+        Error::from(UndoError::Diverged("sessions".into())).exit_code(),
+        4
+    );
+    assert_eq!(
+        Error::from(rusqlite::Error::QueryReturnedNoRows).exit_code(),
+        6
+    );
+}
+
+#[test]
+fn test_locked_periods_are_conflicts() {
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    crate::db::init_schema(&conn).unwrap();
+    crate::db::apply_migrations(&conn).unwrap();
+    conn.execute("INSERT INTO clients (name) VALUES ('Alice')", [])
+        .unwrap();
+    lock::set_lock(&conn, "2025-01-31".parse().unwrap()).unwrap();
+
+    let refused = conn
+        .execute(
+            "INSERT INTO sessions (client_id, start_timestamp) VALUES (1, 1736499600)",
+            [],
+        )
+        .unwrap_err();
+    let err = Error::from(refused);
+    assert_eq!(err.exit_code(), 4);
+    assert_eq!(err.to_string(), lock::LOCKED_MESSAGE);
+}
+
+#[test]
+fn test_errors_chain_to_their_source() {
+    use std::error::Error as _;
+
+    let missing = || -> std::result::Result<(), Box<dyn std::error::Error>> {
+        Err(Error::from(std::io::Error::from(
+            std::io::ErrorKind::NotFound,
+        )))?
+    };
+    let err = missing().unwrap_err();
+    assert!(
+        err.source()
+            .is_some_and(|source| source.is::<std::io::Error>())
+    );
+    assert!(Error::NotFound("No such client".into()).source().is_none());
+    let failed = Error::from(MigrationError::Failed {
+        version: 3,
+        source: rusqlite::Error::QueryReturnedNoRows,
+    });
+    assert!(
+        failed
+            .source()
+            .and_then(|source| source.source())
+            .is_some_and(|source| source.is::<rusqlite::Error>())
+    );
+}
@@ -1,56 +1,97 @@
-use std::time::Instant;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
+    },
+    time::Instant,
+};
 
 use crate::{
-    commands,
-    config::Config,
-    db,
-    models::{Client, Session},
+    Tracker,
+    error::Result,
+    models::Client,
+    store::Store,
     utils,
-    views::SessionView,
+    views::{SessionView, Summary},
 };
-use chrono::Utc;
 use eframe::egui;
-use rusqlite::Connection;
 
-struct TimberApp {
-    conn: Connection,
+struct TimberApp<S: Store> {
+    tracker: Tracker<S>,
     clients: Vec<Client>,
     selected_client: Option<i32>,
     current_session: Option<SessionView>,
     new_client_name: String,
     status_message: String,
+    error: Option<String>,            // Shown in a banner until dismissed
     last_refresh: std::time::Instant, // Track refresh time
+    /// Set when timberd reports a change, cleared by the refresh
+    changed: Arc<AtomicBool>,
+    /// Whether timberd is reporting changes, otherwise the GUI polls
+    watching: Arc<AtomicBool>,
 }
 
-impl TimberApp {
+impl<S: Store> TimberApp<S> {
     fn refresh_clients(&mut self) {
-        self.clients = db::list_clients(&self.conn).unwrap_or_default();
+        match self.tracker.clients() {
+            Ok(clients) => self.clients = clients,
+            Err(e) => self.report(e),
+        }
     }
 
     fn refresh_current_session(&mut self) {
-        match db::get_active_session(&self.conn).expect("Failed to get active session") {
-            Some(session) => {
-                self.current_session = Some(
-                    SessionView::from_session(&self.conn, session).expect("This should never fail"),
-                )
-            }
-            None => self.current_session = Option::None,
+        match self.tracker.active_session() {
+            Ok(session) => self.current_session = session,
+            Err(e) => self.report(e),
         }
     }
-}
 
-impl Default for TimberApp {
-    fn default() -> Self {
-        let conn = db::init_db(&Config::default());
+    fn report(&mut self, error: crate::error::Error) {
+        self.error = Some(error.to_string());
+    }
+
+    /// Minutes per client and the most recent sessions for today
+    fn todays_sessions(&self) -> Result<(Summary, Vec<SessionView>)> {
+        let (start, end) = utils::current_day_range(self.tracker.clock());
+        let summary = self.tracker.summarize(&start, &end)?;
 
+        let store = self.tracker.store();
+        let recent = store
+            .sessions_within_range(&start, &end)?
+            .into_iter()
+            .rev()
+            .take(5)
+            .map(|session| SessionView::from_session(store, self.tracker.clock(), session))
+            .collect::<Result<_>>()?;
+        Ok((summary, recent))
+    }
+
+    fn new(tracker: Tracker<S>, ctx: &egui::Context, changes: Option<Receiver<()>>) -> Self {
+        let changed = Arc::new(AtomicBool::new(false));
+        let watching = Arc::new(AtomicBool::new(changes.is_some()));
+        if let Some(changes) = changes {
+            let (ctx, changed, watching) = (ctx.clone(), changed.clone(), watching.clone());
+            std::thread::spawn(move || {
+                for () in changes {
+                    changed.store(true, Ordering::Relaxed);
+                    ctx.request_repaint();
+                }
+                // timberd stopped, go back to polling
+                watching.store(false, Ordering::Relaxed);
+            });
+        }
         let mut app = Self {
-            conn,
+            tracker,
             clients: vec![],
             current_session: None,
             new_client_name: String::new(),
             status_message: String::new(),
+            error: None,
             selected_client: None,        // will set below if clients exist
             last_refresh: Instant::now(),
+            changed,
+            watching,
         };
 
         app.refresh_clients();
@@ -65,11 +106,14 @@ impl Default for TimberApp {
     }
 }
 
-impl eframe::App for TimberApp {
+impl<S: Store> eframe::App for TimberApp<S> {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Periodically refresh the session and clients
-        // This is relevant in the case of CLI commands that might change the state
-        if self.last_refresh.elapsed().as_secs() > 5 {
+        // Refresh the session and clients when CLI commands might have changed them: as soon as
+        // timberd says so, or periodically without it
+        let polling = !self.watching.load(Ordering::Relaxed);
+        if self.changed.swap(false, Ordering::Relaxed)
+            || (polling && self.last_refresh.elapsed().as_secs() > 5)
+        {
             self.refresh_current_session();
             self.refresh_clients();
             self.last_refresh = std::time::Instant::now();
@@ -77,29 +121,36 @@ impl eframe::App for TimberApp {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("🌲 Timber Time Tracker");
 
+            // ERROR BANNER
+            if let Some(error) = &self.error {
+                let mut dismissed = false;
+                egui::Frame::new()
+                    .fill(egui::Color32::from_rgb(120, 30, 30))
+                    .inner_margin(6.0)
+                    .show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.colored_label(egui::Color32::WHITE, error);
+                            dismissed = ui.button("Dismiss").clicked();
+                        });
+                    });
+                if dismissed {
+                    self.error = None;
+                }
+            }
+
             ui.separator();
 
             // CLIENT LIST
             ui.horizontal(|ui| {
                 ui.label("Clients:");
-                if ui.button("➕ New").clicked() {
-                    if !self.new_client_name.trim().is_empty() {
-                        match db::store_client(
-                            &self.conn,
-                            &Client {
-                                name: self.new_client_name.clone(),
-                                id: 0,
-                                note: Option::None,
-                            },
-                        ) {
-                            Ok(_) => {
-                                self.status_message =
-                                    format!("Added client: {}", self.new_client_name);
-                                self.new_client_name.clear();
-                                self.refresh_clients();
-                            }
-                            Err(_) => self.status_message = "Failed to add client".into(),
+                if ui.button("➕ New").clicked() && !self.new_client_name.trim().is_empty() {
+                    match self.tracker.add_client(&self.new_client_name, None) {
+                        Ok(_) => {
+                            self.status_message = format!("Added client: {}", self.new_client_name);
+                            self.new_client_name.clear();
+                            self.refresh_clients();
                         }
+                        Err(e) => self.report(e),
                     }
                 }
                 ui.text_edit_singleline(&mut self.new_client_name);
@@ -143,7 +194,7 @@ impl eframe::App for TimberApp {
 
             match &self.current_session {
                 Some(session) => {
-                    let elapsed = session.session.get_timedelta();
+                    let elapsed = session.session.get_timedelta(self.tracker.clock());
                     let (hours, minutes) = utils::split_minutes(elapsed.num_minutes());
 
                     ui.label(format!(
@@ -152,8 +203,8 @@ impl eframe::App for TimberApp {
                     ));
 
                     if ui.button("⏹ Stop Session").clicked() {
-                        if let Err(_) = commands::session::end_session(&self.conn) {
-                            self.status_message = "Failed to stop session".into();
+                        if let Err(e) = self.tracker.stop(self.tracker.clock().now()) {
+                            self.report(e);
                         } else {
                             self.status_message = "Stopped current session".into();
                             self.refresh_current_session();
@@ -166,35 +217,18 @@ impl eframe::App for TimberApp {
                     if ui.button("▶ Start Session").clicked() {
                         if let Some(client_id) = self.selected_client {
                             if let Some(client) = self.clients.iter().find(|c| c.id == client_id) {
-                                match db::get_active_session(&self.conn) {
-                                    Ok(Some(_)) => {
+                                match self.tracker.start(
+                                    client_id,
+                                    None,
+                                    self.tracker.clock().now(),
+                                ) {
+                                    Ok(_) => {
                                         self.status_message =
-                                            "There is already an active session".into();
-                                        self.refresh_current_session();
-                                    }
-                                    Ok(None) => {
-                                        if let Err(_) = db::store_session(
-                                            &self.conn,
-                                            &Session {
-                                                id: 0,
-                                                client_id,
-                                                start_timestamp: Utc::now().to_rfc3339(),
-                                                end_timestamp: None,
-                                                note: None,
-                                                offset_minutes: 0,
-                                            },
-                                        ) {
-                                            self.status_message = "Failed to start session".into();
-                                        } else {
-                                            self.status_message =
-                                                format!("Started session for {}", client.name);
-                                            self.refresh_current_session();
-                                        }
-                                    }
-                                    Err(e) => {
-                                        self.status_message = format!("Failed to query db: {}", e);
+                                            format!("Started session for {}", client.name);
                                     }
+                                    Err(e) => self.report(e),
                                 }
+                                self.refresh_current_session();
                             }
                         } else {
                             self.status_message = "Please select a client first".into();
@@ -207,29 +241,19 @@ impl eframe::App for TimberApp {
 
             // DAILY TOTALS
             ui.heading("Daily Totals");
-            let (start, end) = utils::current_day_range();
-            let sessions_today = db::get_sessions_within_range(&self.conn, &start, &end)
-                .expect("An error occurred while fetching the daily sessions");
-
-            let mut totals: std::collections::HashMap<i32, i64> = std::collections::HashMap::new();
-            for s in sessions_today {
-                let delta = s.get_timedelta();
-                *totals.entry(s.client_id).or_insert(0) += delta.num_minutes();
-            }
-
-            let mut ids: Vec<i32> = totals.keys().copied().collect();
-            ids.sort();
-            for cid in ids {
-                let minutes = totals[&cid];
-                let client_name = db::get_client_by_id(&self.conn, cid)
-                    .map(|c| c.name)
-                    .unwrap_or_else(|_| "Unknown".into());
-                let (h, m) = utils::split_minutes(minutes);
+            let (summary, recent) = match self.todays_sessions() {
+                Ok(today) => today,
+                Err(e) => {
+                    self.report(e);
+                    Default::default()
+                }
+            };
+            for (client_name, minutes) in &summary.clients {
+                let (h, m) = utils::split_minutes(*minutes);
                 ui.label(format!("{}: {}h {}m", client_name, h, m));
             }
 
-            let total_minutes: i64 = totals.values().sum();
-            let (h, m) = utils::split_minutes(total_minutes);
+            let (h, m) = utils::split_minutes(summary.total_minutes());
             ui.label(format!("Total: {}h {}m", h, m));
 
             // Recent sessions
@@ -239,15 +263,8 @@ impl eframe::App for TimberApp {
                 egui::ScrollArea::vertical()
                     .max_height(100.0)
                     .show(ui, |ui| {
-                        for session in db::get_sessions_within_range(&self.conn, &start, &end)
-                            .expect("Failed to query db?")
-                            .into_iter()
-                            .rev()
-                            .take(5)
-                        {
-                            let view = SessionView::from_session(&self.conn, session)
-                                .expect("Failed to create session view");
-                            let time = view.session.get_timedelta();
+                        for view in recent {
+                            let time = view.duration;
                             ui.label(format!(
                                 "{}: {}h {}m",
                                 view.client_name,
@@ -267,18 +284,20 @@ impl eframe::App for TimberApp {
     }
 }
 
-// Main function
-pub fn main(conn: Connection) -> eframe::Result<()> {
+/// Run the GUI until its window is closed. Each message on `changes` (see
+/// `daemon::Daemon::subscribe`) refreshes it, without them it polls the store every few seconds.
+pub fn main<S: Store + 'static>(
+    tracker: Tracker<S>,
+    changes: Option<Receiver<()>>,
+) -> eframe::Result<()> {
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size(egui::Vec2 { x: 400.0, y: 500.0 }),
         ..Default::default()
     };
-    let mut app = Box::new(TimberApp::default());
-    app.conn = conn; // Replace database connection
     eframe::run_native(
         "Timber",
         options,
-        Box::new(|_cc| Ok(Box::new(TimberApp::default()))),
+        Box::new(|cc| Ok(Box::new(TimberApp::new(tracker, &cc.egui_ctx, changes)))),
     )
 }
@@ -0,0 +1,578 @@
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::db;
+
+// Every mutating command is recorded as an operation. Triggers on the journaled tables store a
+// JSON snapshot of each affected row before and after the change, tagged with the operation
+// the connection holds in its `journal_operation` flag, so concurrent processes never mix their
+// changes. Undo and redo replay those snapshots.
+
+/// Tables whose changes can be undone, with the columns needed to restore a row. The sync `uid`
+/// comes last, as snapshots taken before it was journaled have it appended by `add_uids`.
+const JOURNALED_TABLES: [(&str, &[&str]); 3] = [
+    ("clients", &["id", "name", "note", "uid"]),
+    (
+        "sessions",
+        &[
+            "id",
+            "client_id",
+            "start_timestamp",
+            "end_timestamp",
+            "note",
+            "offset_minutes",
+            "uid",
+        ],
+    ),
+    ("client_aliases", &["alias", "client_id"]),
+];
+
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS operations (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            command TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            undone INTEGER NOT NULL DEFAULT 0
+        );
+
+        CREATE TABLE IF NOT EXISTS operation_changes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            operation_id INTEGER NOT NULL,
+            table_name TEXT NOT NULL,
+            row_id INTEGER NOT NULL,
+            before TEXT,
+            after TEXT,
+            FOREIGN KEY (operation_id) REFERENCES operations(id) ON DELETE CASCADE
+        );",
+    )?;
+    create_triggers(conn)
+}
+
+/// (Re)create the journaling triggers, needed whenever a journaled table's columns change
+pub fn create_triggers(conn: &Connection) -> rusqlite::Result<()> {
+    let operation = db::connection_flag("journal_operation");
+    for (table, columns) in JOURNALED_TABLES {
+        // Only the columns the table has so far, as earlier migrations create these triggers too
+        let existing = conn
+            .prepare("SELECT name FROM pragma_table_info(?1)")?
+            .query_map([table], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let has_uid = existing.iter().any(|name| name == "uid");
+        let snapshot = |row: &str| {
+            let fields: Vec<String> = std::iter::once("rowid")
+                .chain(columns.iter().copied())
+                .filter(|col| *col == "rowid" || existing.iter().any(|name| name == col))
+                .map(|col| match (col, row) {
+                    // As stored, the uid may be given by a trigger after the insert
+                    ("uid", "NEW") => {
+                        format!("'uid', (SELECT uid FROM {table} WHERE rowid = NEW.rowid)")
+                    }
+                    _ => format!("'{col}', {row}.{col}"),
+                })
+                .collect();
+            format!("json_object({})", fields.join(", "))
+        };
+        // Give new rows their uid before the snapshot, whichever of this and the sync trigger
+        // runs first, and leave that out of the journal as it isn't a change of its own
+        let (assign_uid, uid_given) = if has_uid {
+            (
+                format!(
+                    "UPDATE {table} SET uid = lower(hex(randomblob(16)))
+                    WHERE rowid = NEW.rowid AND uid IS NULL;"
+                ),
+                " AND OLD.uid IS NOT NULL",
+            )
+        } else {
+            (String::new(), "")
+        };
+        for (event, row_id, before, after, prepare, condition) in [
+            (
+                "INSERT",
+                "NEW.rowid",
+                "NULL".to_string(),
+                snapshot("NEW"),
+                assign_uid.as_str(),
+                "",
+            ),
+            (
+                "UPDATE",
+                "NEW.rowid",
+                snapshot("OLD"),
+                snapshot("NEW"),
+                "",
+                uid_given,
+            ),
+            (
+                "DELETE",
+                "OLD.rowid",
+                snapshot("OLD"),
+                "NULL".to_string(),
+                "",
+                "",
+            ),
+        ] {
+            let name = format!("journal_{table}_{}", event.to_lowercase());
+            conn.execute_batch(&format!(
+                "DROP TRIGGER IF EXISTS {name};
+                CREATE TRIGGER {name} AFTER {event} ON {table}
+                WHEN {operation} IS NOT NULL{condition}
+                BEGIN
+                    {prepare}
+                    INSERT INTO operation_changes (operation_id, table_name, row_id, before, after)
+                    VALUES (
+                        CAST({operation} AS INTEGER),
+                        '{table}', {row_id}, {before}, {after}
+                    );
+                END;"
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Start journaling the sync `uid` of clients and sessions, so undoing a removal brings back the
+/// row other machines know rather than a new one. Existing snapshots get the uid of their row, or
+/// for a row that is gone one new uid shared by all its snapshots.
+pub fn add_uids(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TEMP TABLE journal_uids AS
+        SELECT table_name, row_id, lower(hex(randomblob(16))) AS uid FROM operation_changes
+        WHERE table_name IN ('clients', 'sessions')
+        GROUP BY table_name, row_id",
+    )?;
+    for table in ["clients", "sessions"] {
+        for snapshot in ["before", "after"] {
+            conn.execute(
+                &format!(
+                    "UPDATE operation_changes
+                    SET {snapshot} = json_set({snapshot}, '$.uid', COALESCE(
+                        (SELECT uid FROM {table} WHERE rowid = operation_changes.row_id),
+                        (SELECT uid FROM temp.journal_uids
+                         WHERE table_name = '{table}' AND row_id = operation_changes.row_id)
+                    ))
+                    WHERE table_name = '{table}' AND {snapshot} IS NOT NULL"
+                ),
+                [],
+            )?;
+        }
+    }
+    conn.execute_batch("DROP TABLE temp.journal_uids")?;
+    create_triggers(conn)
+}
+
+/// How many operations can be undone, older ones are forgotten as new ones are recorded
+pub const HISTORY_LIMIT: usize = 1000;
+
+/// Drop all but the latest `keep` operations and their changes
+fn forget_old_operations(conn: &Connection, keep: usize) -> rusqlite::Result<()> {
+    let oldest_forgotten: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM operations ORDER BY id DESC LIMIT 1 OFFSET ?1",
+            [keep as i64],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(id) = oldest_forgotten {
+        conn.execute(
+            "DELETE FROM operation_changes WHERE operation_id <= ?1",
+            [id],
+        )?;
+        conn.execute("DELETE FROM operations WHERE id <= ?1", [id])?;
+    }
+    Ok(())
+}
+
+/// Records all changes made while it is alive as a single undoable operation
+pub struct Operation<'a> {
+    conn: &'a Connection,
+    id: i64,
+}
+
+impl<'a> Operation<'a> {
+    pub fn begin(conn: &'a Connection, command: &str) -> rusqlite::Result<Self> {
+        conn.execute(
+            "INSERT INTO operations (command, created_at) VALUES (?1, ?2)",
+            params![command, Utc::now().to_rfc3339()],
+        )?;
+        let id = conn.last_insert_rowid();
+        db::set_connection_flag(conn, "journal_operation", &id.to_string())?;
+        Ok(Operation { conn, id })
+    }
+
+    fn finish(&self) -> rusqlite::Result<()> {
+        db::clear_connection_flag(self.conn, "journal_operation")?;
+        let changed: bool = self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM operation_changes WHERE operation_id = ?1)",
+            [self.id],
+            |row| row.get(0),
+        )?;
+        if changed {
+            // A new change invalidates anything that could have been redone
+            self.conn
+                .execute("DELETE FROM operations WHERE undone = 1", [])?;
+            forget_old_operations(self.conn, HISTORY_LIMIT)?;
+        } else {
+            self.conn
+                .execute("DELETE FROM operations WHERE id = ?1", [self.id])?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Operation<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            eprintln!("Warning: Failed to record this command for undo: {e}");
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UndoError {
+    NothingToDo,
+    /// A row was changed outside of the journal since the operation ran
+    Diverged(String),
+    Db(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for UndoError {
+    fn from(err: rusqlite::Error) -> Self {
+        UndoError::Db(err)
+    }
+}
+
+impl std::fmt::Display for UndoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UndoError::NothingToDo => write!(f, "There is nothing to undo or redo"),
+            UndoError::Diverged(table) => write!(
+                f,
+                "A row in '{table}' has changed since this command ran, so it can't be safely reversed"
+            ),
+            UndoError::Db(err) => write!(f, "Database error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for UndoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UndoError::Db(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Reverse the latest operation, returning the command that was undone
+pub fn undo(conn: &Connection) -> Result<String, UndoError> {
+    replay(conn, true)
+}
+
+/// Re-apply the most recently undone operation, returning its command
+pub fn redo(conn: &Connection) -> Result<String, UndoError> {
+    replay(conn, false)
+}
+
+fn replay(conn: &Connection, undo: bool) -> Result<String, UndoError> {
+    let query = if undo {
+        "SELECT id, command FROM operations WHERE undone = 0 ORDER BY id DESC LIMIT 1"
+    } else {
+        "SELECT id, command FROM operations WHERE undone = 1 ORDER BY id ASC LIMIT 1"
+    };
+    let tx = conn.unchecked_transaction()?;
+    // Rows are restored one at a time, so references are only checked once everything is back
+    tx.execute_batch("PRAGMA defer_foreign_keys = ON")?;
+    let (operation_id, command): (i64, String) = tx
+        .query_row(query, [], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()?
+        .ok_or(UndoError::NothingToDo)?;
+    let verb = if undo { "undo" } else { "redo" };
+    let _audit = db::ConnectionFlag::set(conn, "audit_command", &format!("{verb} {command}"))?;
+
+    let order = if undo { "DESC" } else { "ASC" };
+    let mut stmt = tx.prepare(&format!(
+        "SELECT table_name, row_id, before, after FROM operation_changes
+         WHERE operation_id = ?1 ORDER BY id {order}"
+    ))?;
+    let changes = stmt
+        .query_map([operation_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    drop(stmt);
+
+    for (table, row_id, before, after) in changes {
+        let (expected, target) = if undo {
+            (after, before)
+        } else {
+            (before, after)
+        };
+        let Some((_, columns)) = JOURNALED_TABLES.iter().find(|(name, _)| *name == table) else {
+            continue;
+        };
+        let current = current_snapshot(&tx, &table, columns, row_id)?;
+        if current == target {
+            continue; // Already in place, e.g. an alias removed by a cascading client delete
+        }
+        if current != expected {
+            return Err(UndoError::Diverged(table));
+        }
+        restore_row(
+            &tx,
+            &table,
+            columns,
+            row_id,
+            current.is_some(),
+            target.as_deref(),
+        )?;
+    }
+
+    tx.execute(
+        "UPDATE operations SET undone = ?1 WHERE id = ?2",
+        params![undo, operation_id],
+    )?;
+    tx.commit()?;
+    Ok(command)
+}
+
+fn current_snapshot(
+    conn: &Connection,
+    table: &str,
+    columns: &[&str],
+    row_id: i64,
+) -> rusqlite::Result<Option<String>> {
+    let fields: Vec<String> = std::iter::once("rowid")
+        .chain(columns.iter().copied())
+        .map(|col| format!("'{col}', {col}"))
+        .collect();
+    conn.query_row(
+        &format!(
+            "SELECT json_object({}) FROM {table} WHERE rowid = ?1",
+            fields.join(", ")
+        ),
+        [row_id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Bring a row to the state in `snapshot`, deleting it if there is none.
+/// Existing rows are updated in place so that deletes don't cascade to rows referencing them.
+fn restore_row(
+    conn: &Connection,
+    table: &str,
+    columns: &[&str],
+    row_id: i64,
+    exists: bool,
+    snapshot: Option<&str>,
+) -> rusqlite::Result<()> {
+    let extract = |col: &str| format!("json_extract(?1, '$.{col}')");
+    match snapshot {
+        None => conn.execute(&format!("DELETE FROM {table} WHERE rowid = ?1"), [row_id])?,
+        Some(snapshot) if exists => {
+            let assignments: Vec<String> = columns
+                .iter()
+                .map(|col| format!("{col} = {}", extract(col)))
+                .collect();
+            conn.execute(
+                &format!(
+                    "UPDATE {table} SET {} WHERE rowid = ?2",
+                    assignments.join(", ")
+                ),
+                params![snapshot, row_id],
+            )?
+        }
+        Some(snapshot) => {
+            let names: Vec<&str> = std::iter::once("rowid")
+                .chain(columns.iter().copied())
+                .collect();
+            let values: Vec<String> = names.iter().map(|col| extract(col)).collect();
+            conn.execute(
+                &format!(
+                    "INSERT INTO {table} ({}) VALUES ({})",
+                    names.join(", "),
+                    values.join(", ")
+                ),
+                [snapshot],
+            )?
+        }
+    };
+    Ok(())
+}
+
+// TESTS
+
+#[test]
+fn test_undo_redo_session_removal() {
+    use crate::{db, models::Session};
+
+    let conn = Connection::open_in_memory().unwrap();
+    db::init_schema(&conn).unwrap();
+    db::apply_migrations(&conn).unwrap();
+
+    {
+        let _op = Operation::begin(&conn, "client add Alice").unwrap();
+        conn.execute("INSERT INTO clients (name) VALUES ('Alice')", [])
+            .unwrap();
+    }
+    let session_id = {
+        let _op = Operation::begin(&conn, "session start Alice").unwrap();
+        db::store_session(
+            &conn,
+            &Session {
+                id: 0,
+                client_id: 1,
+                start_timestamp: "2025-01-01T09:00:00+00:00".parse().unwrap(),
+                end_timestamp: None,
+                note: Some("it's \"quoted\"".into()),
+                offset_minutes: -5,
+            },
+        )
+        .unwrap()
+    };
+    {
+        let _op = Operation::begin(&conn, "session rm 1").unwrap();
+        db::remove_session(&conn, session_id).unwrap();
+    }
+    {
+        // Operations without changes are not kept
+        let _op = Operation::begin(&conn, "session ls").unwrap();
+    }
+
+    assert_eq!(undo(&conn).unwrap(), "session rm 1");
+    let restored = db::get_session_by_id(&conn, session_id).unwrap();
+    assert_eq!(restored.note.as_deref(), Some("it's \"quoted\""));
+    assert_eq!(restored.offset_minutes, -5);
+
+    assert_eq!(redo(&conn).unwrap(), "session rm 1");
+    assert!(db::get_session_by_id(&conn, session_id).is_err());
+    assert!(matches!(redo(&conn), Err(UndoError::NothingToDo)));
+
+    // Undoing a client removal brings back the sessions and aliases that referenced it
+    undo(&conn).unwrap();
+    {
+        let _op = Operation::begin(&conn, "client alias Alice wonderland").unwrap();
+        db::add_client_alias(&conn, 1, "wonderland").unwrap();
+    }
+    assert!(matches!(redo(&conn), Err(UndoError::NothingToDo))); // New changes clear the redo stack
+    {
+        let _op = Operation::begin(&conn, "client rm Alice").unwrap();
+        conn.execute("DELETE FROM sessions", []).unwrap();
+        db::remove_client(&conn, 1).unwrap();
+    }
+    undo(&conn).unwrap();
+    assert_eq!(
+        db::list_client_aliases(&conn, 1).unwrap(),
+        vec!["wonderland"]
+    );
+    assert_eq!(db::list_sessions(&conn, Some(1)).unwrap().len(), 1);
+}
+
+#[test]
+fn test_operations_belong_to_their_connection() {
+    use crate::db;
+
+    let path = std::env::temp_dir().join(format!("timber-journal-test-{}.db", std::process::id()));
+    let conn = Connection::open(&path).unwrap();
+    db::init_schema(&conn).unwrap();
+    db::apply_migrations(&conn).unwrap();
+    let other = Connection::open(&path).unwrap();
+    db::register_functions(&other).unwrap();
+
+    {
+        let _op = Operation::begin(&conn, "client add Alice").unwrap();
+        // Another process writing meanwhile, outside of any operation
+        other
+            .execute("INSERT INTO clients (name) VALUES ('Bob')", [])
+            .unwrap();
+        conn.execute("INSERT INTO clients (name) VALUES ('Alice')", [])
+            .unwrap();
+    }
+    undo(&conn).unwrap();
+    let names: Vec<String> = conn
+        .prepare("SELECT name FROM clients")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    assert_eq!(names, vec!["Bob"]);
+
+    // A process that dies mid-operation leaves nothing that would capture later changes
+    std::mem::forget(Operation::begin(&other, "client add Carol").unwrap());
+    drop(other);
+    conn.execute("INSERT INTO clients (name) VALUES ('Dave')", [])
+        .unwrap();
+    let changes: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM operation_changes c JOIN operations o ON o.id = c.operation_id
+             WHERE o.command = 'client add Carol'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(changes, 0);
+    drop(conn);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_undo_restores_sync_ids() {
+    use crate::db;
+
+    let conn = Connection::open_in_memory().unwrap();
+    db::init_schema(&conn).unwrap();
+    db::apply_migrations(&conn).unwrap();
+    {
+        let _op = Operation::begin(&conn, "client add Alice").unwrap();
+        conn.execute("INSERT INTO clients (name) VALUES ('Alice')", [])
+            .unwrap();
+    }
+    let uid = || -> Option<String> {
+        conn.query_row("SELECT uid FROM clients WHERE id = 1", [], |row| row.get(0))
+            .optional()
+            .unwrap()
+    };
+    let original = uid().unwrap();
+    {
+        let _op = Operation::begin(&conn, "client rm Alice").unwrap();
+        db::remove_client(&conn, 1).unwrap();
+    }
+
+    undo(&conn).unwrap();
+    assert_eq!(uid(), Some(original.clone()));
+    undo(&conn).unwrap();
+    assert_eq!(uid(), None);
+    redo(&conn).unwrap();
+    assert_eq!(uid(), Some(original));
+}
+
+#[test]
+fn test_old_operations_are_forgotten() {
+    let conn = Connection::open_in_memory().unwrap();
+    db::init_schema(&conn).unwrap();
+    db::apply_migrations(&conn).unwrap();
+    for name in ["Alice", "Bob", "Carol"] {
+        let _op = Operation::begin(&conn, &format!("client add {name}")).unwrap();
+        conn.execute("INSERT INTO clients (name) VALUES (?1)", [name])
+            .unwrap();
+    }
+
+    forget_old_operations(&conn, 2).unwrap();
+    let count = |table: &str| -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    };
+    assert_eq!((count("operations"), count("operation_changes")), (2, 2));
+    assert_eq!(undo(&conn).unwrap(), "client add Carol");
+    assert_eq!(undo(&conn).unwrap(), "client add Bob");
+    assert!(matches!(undo(&conn), Err(UndoError::NothingToDo)));
+}
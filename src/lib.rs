@@ -0,0 +1,33 @@
+//! Timber is a simple time tracker. This crate holds everything behind the `timber` command line
+//! tool and GUI, so other tools can log and report time against the same database.
+//!
+//! Most uses only need [`Tracker`], which covers starting, stopping and summarising sessions. The
+//! modules below it are available for anything more specialised.
+
+pub mod audit;
+pub mod backup;
+pub mod clock;
+pub mod commands;
+pub mod config;
+#[cfg(all(unix, feature = "daemon"))]
+pub mod daemon;
+pub mod db;
+pub mod error;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod journal;
+pub mod lock;
+pub mod models;
+pub mod prompt;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod store;
+pub mod sync;
+pub mod team;
+pub mod tracker;
+pub mod utils;
+pub mod views;
+
+pub use error::{Error, Result};
+pub use store::Store;
+pub use tracker::Tracker;
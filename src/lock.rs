@@ -0,0 +1,204 @@
+use chrono::{Days, Local, NaiveDate, TimeZone, Utc};
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::{audit, db};
+
+// Sessions starting before `meta.locked_before` belong to a closed (invoiced) period. Triggers
+// refuse to insert, change or delete them unless the connection holds the `lock_override` flag
+// set by `--force`, in which case every overridden change is written to the audit log.
+
+pub(crate) const LOCKED_MESSAGE: &str =
+    "This session is in a locked period, use --force to change it";
+
+pub fn create_triggers(conn: &Connection) -> rusqlite::Result<()> {
+    let locked_before = "CAST((SELECT value FROM meta WHERE key = 'locked_before') AS INTEGER)";
+    let lock_override = db::connection_flag("lock_override");
+    let command = audit::current_command();
+    for (event, row, condition) in [
+        (
+            "INSERT",
+            "NEW",
+            format!("NEW.start_timestamp < {locked_before}"),
+        ),
+        (
+            "UPDATE",
+            "OLD",
+            format!(
+                "OLD.start_timestamp < {locked_before} OR NEW.start_timestamp < {locked_before}"
+            ),
+        ),
+        (
+            "DELETE",
+            "OLD",
+            format!("OLD.start_timestamp < {locked_before}"),
+        ),
+    ] {
+        let name = format!("lock_sessions_{}", event.to_lowercase());
+        conn.execute_batch(&format!(
+            "DROP TRIGGER IF EXISTS {name};
+            CREATE TRIGGER {name} BEFORE {event} ON sessions
+            WHEN {condition}
+            BEGIN
+                SELECT RAISE(ABORT, '{LOCKED_MESSAGE}')
+                WHERE {lock_override} IS NULL;
+
+                INSERT INTO audit_log
+                    (timestamp, table_name, row_id, action, field, old_value, new_value, command)
+                VALUES (
+                    strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'sessions', {row}.id, 'override',
+                    'lock', (SELECT value FROM meta WHERE key = 'lock_until'), NULL,
+                    {command}
+                );
+            END;"
+        ))?;
+    }
+    Ok(())
+}
+
+/// The last locked day, if any period is locked
+pub fn get_lock(conn: &Connection) -> rusqlite::Result<Option<NaiveDate>> {
+    let until: Option<String> = conn
+        .query_row(
+            "SELECT value FROM meta WHERE key = 'lock_until'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(until.and_then(|until| until.parse().ok()))
+}
+
+/// Lock every session starting on or before `until` (local time)
+pub fn set_lock(conn: &Connection, until: NaiveDate) -> rusqlite::Result<()> {
+    let next_day = until.checked_add_days(Days::new(1)).unwrap_or(until);
+    let locked_before = Local
+        .from_local_datetime(&next_day.and_hms_opt(0, 0, 0).unwrap())
+        .earliest()
+        .map(|local| local.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&next_day.and_hms_opt(0, 0, 0).unwrap()));
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        &format!(
+            "INSERT INTO audit_log
+                (timestamp, table_name, row_id, action, field, old_value, new_value, command)
+            VALUES (
+                strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'meta', 0, 'update', 'lock_until',
+                (SELECT value FROM meta WHERE key = 'lock_until'), ?1, {}
+            )",
+            audit::current_command()
+        ),
+        [until.to_string()],
+    )?;
+    tx.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('lock_until', ?1)",
+        [until.to_string()],
+    )?;
+    tx.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('locked_before', ?1)",
+        [locked_before.timestamp().to_string()],
+    )?;
+    tx.commit()
+}
+
+/// Allows changes to locked sessions through this connection while it is alive
+pub struct Override<'a> {
+    conn: &'a Connection,
+}
+
+impl<'a> Override<'a> {
+    pub fn begin(conn: &'a Connection) -> rusqlite::Result<Self> {
+        db::set_connection_flag(conn, "lock_override", "1")?;
+        Ok(Override { conn })
+    }
+}
+
+impl Drop for Override<'_> {
+    fn drop(&mut self) {
+        let _ = db::clear_connection_flag(self.conn, "lock_override");
+    }
+}
+
+// TESTS
+
+#[test]
+fn test_locked_sessions_refuse_changes() {
+    use crate::{db, models::Session};
+
+    let conn = Connection::open_in_memory().unwrap();
+    db::init_schema(&conn).unwrap();
+    db::apply_migrations(&conn).unwrap();
+    conn.execute("INSERT INTO clients (name) VALUES ('Alice')", [])
+        .unwrap();
+    let session = |start: &str| Session {
+        id: 0,
+        client_id: 1,
+        start_timestamp: start.parse().unwrap(),
+        end_timestamp: None,
+        note: None,
+        offset_minutes: 0,
+    };
+    let old_id = db::store_session(&conn, &session("2025-01-10T09:00:00+00:00")).unwrap();
+
+    set_lock(&conn, "2025-01-31".parse().unwrap()).unwrap();
+    let is_locked_error = |err: rusqlite::Error| err.to_string() == LOCKED_MESSAGE;
+    assert!(is_locked_error(
+        db::remove_session(&conn, old_id).unwrap_err()
+    ));
+    assert!(is_locked_error(
+        db::store_session(&conn, &session("2025-01-20T09:00:00+00:00")).unwrap_err()
+    ));
+    // Sessions after the lock are unaffected
+    db::store_session(&conn, &session("2025-02-10T09:00:00+00:00")).unwrap();
+
+    {
+        let _force = Override::begin(&conn).unwrap();
+        db::remove_session(&conn, old_id).unwrap();
+    }
+    let overrides: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM audit_log WHERE action = 'override' AND row_id = ?1",
+            [old_id],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(overrides, 1);
+}
+
+#[test]
+fn test_override_is_held_by_one_connection() {
+    use crate::{db, models::Session};
+
+    let path = std::env::temp_dir().join(format!("timber-lock-test-{}.db", std::process::id()));
+    let conn = Connection::open(&path).unwrap();
+    db::init_schema(&conn).unwrap();
+    db::apply_migrations(&conn).unwrap();
+    conn.execute("INSERT INTO clients (name) VALUES ('Alice')", [])
+        .unwrap();
+    let session = Session {
+        id: 0,
+        client_id: 1,
+        start_timestamp: "2025-01-10T09:00:00+00:00".parse().unwrap(),
+        end_timestamp: None,
+        note: None,
+        offset_minutes: 0,
+    };
+    set_lock(&conn, "2025-01-31".parse().unwrap()).unwrap();
+
+    let other = Connection::open(&path).unwrap();
+    db::register_functions(&other).unwrap();
+    {
+        let _force = Override::begin(&conn).unwrap();
+        db::store_session(&conn, &session).unwrap();
+        assert_eq!(
+            db::store_session(&other, &session).unwrap_err().to_string(),
+            LOCKED_MESSAGE
+        );
+    }
+    // Nothing is left behind once the override ends
+    assert_eq!(
+        db::store_session(&conn, &session).unwrap_err().to_string(),
+        LOCKED_MESSAGE
+    );
+    drop((conn, other));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -1,232 +1,963 @@
-use chrono::Utc;
-use clap::Parser;
-use cli::{Cli, Commands};
-use rusqlite::Connection;
-
-use crate::{
-    cli::{ClientOptions, SessionOptions, UserInput},
-    config::Config,
-    models::{Client, Session},
-    views::{SessionView, display_daily_time_summary},
+use std::{collections::HashSet, io::IsTerminal, path::PathBuf, process::ExitCode};
+
+use chrono::TimeDelta;
+use clap::{CommandFactory, Parser};
+use cli::{Cli, ClientOptions, Commands, SessionOptions};
+use timber::{
+    Error, Tracker, audit, backup, clock, commands,
+    config::{self, Config},
+    db, error, journal, lock,
+    models::{Client, UserInput},
+    store::{self, Backend, Store},
+    sync, team, utils, views,
 };
+
+#[cfg(all(unix, feature = "daemon"))]
+use timber::daemon;
+
 mod cli;
-mod commands;
-mod config;
-mod db;
-mod gui;
-mod models;
-mod utils;
-mod views;
-fn main() {
-    let mut config = Config::load().unwrap_or_default();
+fn main() -> ExitCode {
+    // Answers the shell when it asks for completions (COMPLETE is set), and exits
+    clap_complete::CompleteEnv::with_factory(Cli::command).complete();
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
 
-    let conn = db::init_db(&config); // make sure the database exists
+fn run(cli: Cli) -> error::Result<()> {
+    if let Commands::Completions { shell } = cli.command {
+        shell.completer().write_registration(
+            "COMPLETE",
+            "timber",
+            "timber",
+            "timber",
+            &mut std::io::stdout(),
+        )?;
+        return Ok(());
+    }
+    if let Commands::Team(command) = cli.command {
+        // Reads the teammates' databases given with --db instead of the configured one
+        return run_team_command(&cli.database_paths, command);
+    }
+    let database_path = match cli.database_paths.as_slice() {
+        [] => None,
+        [path] => Some(path.clone()),
+        _ => {
+            return Err(Error::Invalid(
+                "--db can only be given once, except for `timber team report`".into(),
+            ));
+        }
+    };
+    let overrides = config::Overrides {
+        config_path: cli.config_path.clone(),
+        profile: cli.profile.clone(),
+        database_path,
+    };
 
-    let cli = Cli::parse();
+    if let Commands::Profile(command) = cli.command {
+        return run_profile_command(&overrides, command);
+    }
+
+    let config = Config::load(&overrides)?;
+
+    if let Commands::Config(command) = cli.command {
+        // Before opening the store, so a broken backend or path can still be fixed
+        return run_config_command(&config, command);
+    }
+
+    if let Commands::Db(command) = &cli.command {
+        let name = match command {
+            cli::DbCommand::Status => "db status",
+            cli::DbCommand::Migrate => "db migrate",
+        };
+        if config.backend != Backend::Sqlite {
+            return Err(needs_sqlite(name));
+        }
+        // Inspect the database before init_db gets a chance to migrate it
+        let conn = db::open_db(&config)?;
+        match command {
+            cli::DbCommand::Status => show_schema_status(&conn)?,
+            cli::DbCommand::Migrate => {
+                db::init_schema(&conn)?;
+                let before = db::get_schema_version(&conn);
+                drop(conn);
+                db::init_db(&config)?;
+                if before == db::SCHEMA_VERSION {
+                    println!("The database is up to date.");
+                } else {
+                    println!(
+                        "Migrated the database from schema version {before} to {}",
+                        db::SCHEMA_VERSION
+                    );
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    if let Commands::Prompt {
+        format,
+        json,
+        snippet,
+    } = cli.command
+    {
+        // Before opening the store, prompts run too often to migrate or back up anything
+        return run_prompt(&config, format, json, snippet);
+    }
+
+    // Recorded with everything this command changes, for undo and the audit log
+    let command_line = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+
+    // timberd already has the database open and migrated, and takes the daily backup itself.
+    // Overrides and pinned clocks belong to this process, so those commands open it themselves.
+    #[cfg(all(unix, feature = "daemon"))]
+    if !cli.force
+        && !clock::is_pinned()
+        && let Some(mut daemon) = daemon::Daemon::connect(&config)
+        && run_with_daemon(&mut daemon, &cli.command, &command_line)?
+    {
+        return Ok(());
+    }
+
+    let tracker = Tracker::new(store::open(&config)?).with_clock(clock::from_env()?); // make sure the store exists
+
+    #[cfg(feature = "gui")]
+    if let Commands::Gui = cli.command {
+        // Refresh as soon as timberd reports a change, instead of polling the database
+        #[cfg(all(unix, feature = "daemon"))]
+        let changes = daemon::Daemon::connect(&config).and_then(|d| d.subscribe().ok());
+        #[cfg(not(all(unix, feature = "daemon")))]
+        let changes = None;
+        return timber::gui::main(tracker, changes)
+            .map_err(|e| Error::Io(std::io::Error::other(e.to_string())));
+    }
+    #[cfg(feature = "serve")]
+    if let Commands::Serve { listen } = &cli.command {
+        // Each request is recorded for undo on its own
+        return timber::serve::serve(&tracker, listen);
+    }
+    let store: &dyn Store = tracker.store().as_ref();
+    // Undo, locks, aliases and backups are only kept in SQLite databases
+    let sqlite = store.as_connection();
+    let clock = tracker.clock();
+
+    // Record everything this command changes so it can be undone
+    let _operation = match sqlite {
+        // Refuse rather than tell the user to undo something that was never recorded
+        Some(conn) if is_mutating(&cli.command) => {
+            Some(journal::Operation::begin(conn, &command_line)?)
+        }
+        _ => None,
+    };
+
+    let _override = match sqlite {
+        Some(conn) if cli.force => Some(lock::Override::begin(conn)?),
+        _ => None,
+    };
+    let undo_hint = if sqlite.is_some() {
+        " Run `timber undo` to restore it."
+    } else {
+        ""
+    };
 
     match cli.command {
         Commands::Client(client_cmd) => match client_cmd {
             ClientOptions::Add { name, note } => {
-                match db::store_client(&conn, &Client { id: 0, name, note }) {
-                    Ok(Some(id)) => println!("Client added with id {id}"),
-                    Ok(None) => println!("The client already exists!"),
-                    Err(_) => println!("Failed to add new client"),
-                }
+                let id = tracker.add_client(&name, note)?;
+                println!("Client added with id {id}")
             }
             ClientOptions::Remove { input } => {
-                let client_id: i32 = match input {
-                    cli::UserInput::ByName(name) => match db::get_client_id_by_name(&conn, name) {
-                        Ok(Some(id)) => id,
-                        Ok(None) => {
-                            println!("Provided client could not be found!");
-                            return;
-                        }
-                        Err(_) => {
-                            println!("Error: Unable to start session");
-                            return;
-                        }
-                    },
-                    cli::UserInput::ById(id) => id,
+                let Some(client_id) = utils::handle_user_client_input(store, Some(input))? else {
+                    return Err(client_not_found());
                 };
 
-                match db::remove_client(&conn, client_id) {
-                    Ok(_) => println!("Successfully removed client {client_id}"),
-                    Err(rusqlite::Error::SqliteFailure(_err, _)) => {
-                        println!(
-                            "This client is referenced in some of your sessions! Removal is not yet supported."
-                        );
+                match store.remove_client(client_id) {
+                    Ok(_) => println!("Successfully removed client {client_id}.{undo_hint}"),
+                    Err(Error::Conflict(_)) => {
+                        return Err(Error::Conflict(
+                            "This client is referenced in some of your sessions! Removal is not yet supported.".into(),
+                        ));
                     }
-                    Err(e) => println!("Failed to remove client {client_id}, {e}"),
+                    Err(e) => return Err(e),
                 }
             }
             ClientOptions::List => {
-                let client_list =
-                    db::list_clients(&conn).expect("Error encountered getting client list");
+                let client_list = store.list_clients()?;
                 if client_list.is_empty() {
                     println!("No clients to display!");
-                    return;
+                    return Ok(());
                 }
                 println!("Clients (Name, Id):");
                 for client in client_list {
-                    println!("({}, {})", client.name, client.id);
+                    match sqlite.map(|conn| db::list_client_aliases(conn, client.id)) {
+                        Some(Ok(aliases)) if !aliases.is_empty() => println!(
+                            "({}, {}) aka {}",
+                            client.name,
+                            client.id,
+                            aliases.join(", ")
+                        ),
+                        _ => println!("({}, {})", client.name, client.id),
+                    }
+                }
+            }
+            ClientOptions::Rename { input, new_name } => {
+                let mut client = find_client(store, input)?;
+                let old_name = std::mem::replace(&mut client.name, new_name);
+                store.update_client(&client)?;
+                println!("Renamed client '{old_name}' to '{}'", client.name)
+            }
+            ClientOptions::Note { input, note } => {
+                let mut client = find_client(store, input)?;
+                client.note = note;
+                store.update_client(&client)?;
+                if client.note.is_some() {
+                    println!("Updated the note for '{}'", client.name)
+                } else {
+                    println!("Cleared the note for '{}'", client.name)
                 }
             }
+            ClientOptions::Alias { input, alias } => {
+                let conn = sqlite.ok_or_else(|| needs_sqlite("client alias"))?;
+                let client = find_client(store, input)?;
+                match db::add_client_alias(conn, client.id, &alias)? {
+                    Some(_) => println!("'{alias}' now refers to '{}'", client.name),
+                    None => {
+                        return Err(Error::Conflict(format!(
+                            "The alias '{alias}' is already in use!"
+                        )));
+                    }
+                }
+            }
+            ClientOptions::Unalias { alias } => {
+                let conn = sqlite.ok_or_else(|| needs_sqlite("client unalias"))?;
+                if !db::remove_client_alias(conn, &alias)? {
+                    return Err(Error::NotFound(format!(
+                        "No alias named '{alias}' was found!"
+                    )));
+                }
+                println!("Removed alias '{alias}'")
+            }
+            ClientOptions::Merge { from, into } => {
+                let conn = sqlite.ok_or_else(|| needs_sqlite("client merge"))?;
+                let (from, into) = (find_client(store, from)?, find_client(store, into)?);
+                if from.id == into.id {
+                    return Err(Error::Invalid("Cannot merge a client into itself!".into()));
+                }
+                let moved = db::merge_clients(conn, from.id, into.id)?;
+                println!(
+                    "Merged '{}' into '{}' ({moved} sessions moved, '{}' kept as an alias)",
+                    from.name, into.name, from.name
+                );
+            }
         },
         Commands::Session(session_cmd) => match session_cmd {
-            SessionOptions::Start { input, note } => start_session(&conn, input, note),
-            SessionOptions::End => {
-                end_session(&conn);
+            SessionOptions::Start { input, note, time } => {
+                let client_id = resolve_client(store, input)?;
+                let id = tracker.start(client_id, note, time.resolve(clock)?)?;
+                println!(
+                    "Started logging session {id} for {}",
+                    client_name(store, client_id)
+                );
             }
+            SessionOptions::End { time } => match tracker.stop(time.resolve(clock)?)? {
+                Some(delta) => print_finished(delta),
+                None => {
+                    return Err(Error::NotFound(
+                        "No active session was found to end!".into(),
+                    ));
+                }
+            },
             SessionOptions::Remove { id } => {
-                let _ = db::remove_session(&conn, id);
+                store.get_session(id)?;
+                store.remove_session(id)?;
+                println!("Removed session {id}.{undo_hint}")
             }
             SessionOptions::List { client } => {
-                let client_id = Option::None;
-                if client.is_some() {
-                    let client_id: Option<i32> = utils::handle_user_client_input(&conn, client);
-                    if client_id.is_none() {
-                        println!("Provided client could not be found!");
-                        return;
-                    }
+                let client_id = utils::handle_user_client_input(store, client.clone())?;
+                if client.is_some() && client_id.is_none() {
+                    return Err(client_not_found());
                 }
-                let sessions =
-                    db::list_sessions(&conn, client_id).expect("Failed to list sessions");
+                let sessions = store.list_sessions(client_id)?;
 
                 if sessions.is_empty() {
                     println!("No sessions to display!");
-                    return;
+                    return Ok(());
                 }
-                views::display_sessions(&conn, sessions);
+                views::display_sessions(store, clock, sessions);
             }
 
             SessionOptions::Current => {
-                views::display_active_session(&conn);
+                views::display_active_session(store, clock)?;
             }
-        },
-        Commands::Summary { range } => match range {
-            cli::SummaryRange::Daily => {
-                let (start, end) = utils::current_day_range();
-                views::display_client_time_summaries(&conn, &start, &end);
+            SessionOptions::History { id } => {
+                let conn = sqlite.ok_or_else(|| needs_sqlite("session history"))?;
+                let entries = audit::session_history(conn, id)?;
+                if entries.is_empty() {
+                    println!("No recorded history for session {id}!")
+                } else {
+                    views::display_session_history(id, &entries)
+                }
             }
-            cli::SummaryRange::Weekly => {
-                let (start, end) = utils::current_week_range();
-                views::display_client_time_summaries(&conn, &start, &end);
+            SessionOptions::Split {
+                id,
+                at,
+                client,
+                note,
+            } => {
+                let client_id = utils::handle_user_client_input(store, client.clone())?;
+                if client.is_some() && client_id.is_none() {
+                    return Err(client_not_found());
+                }
+                let reference = store
+                    .get_session(id)
+                    .ok()
+                    .map(|s| s.start_timestamp)
+                    .unwrap_or_else(|| clock.now());
+                let Some(at) = utils::parse_natural_time(&at, reference) else {
+                    return Err(Error::Invalid(format!(
+                        "Unable to understand the time '{at}'"
+                    )));
+                };
+                let new_id =
+                    commands::session::split_session(store, clock, id, at, client_id, note)?;
+                println!("Split session {id}, the second half is session {new_id}")
             }
-            cli::SummaryRange::Monthly => {
-                let (start, end) = utils::current_month_range();
-                views::display_client_time_summaries(&conn, &start, &end);
+            SessionOptions::Merge { first, second } => {
+                let gap = commands::session::merge_sessions(store, first, second)?;
+                if gap.num_minutes() > 0 {
+                    println!(
+                        "Merged sessions {first} and {second} ({}m gap now included)",
+                        gap.num_minutes()
+                    )
+                } else {
+                    println!("Merged sessions {first} and {second}")
+                }
             }
         },
-        Commands::Switch { input, note } => {
-            end_session(&conn);
-            start_session(&conn, input, note);
+        Commands::Summary { range } => {
+            let (start, end) = match range {
+                cli::SummaryRange::Daily => utils::current_day_range(clock),
+                cli::SummaryRange::Weekly => utils::current_week_range(clock),
+                cli::SummaryRange::Monthly => utils::current_month_range(clock),
+            };
+            println!("{}", tracker.summarize(&start, &end)?);
         }
-        Commands::Patch { minutes } => match commands::session::patch_session(&conn, minutes) {
-            Ok(Some(_)) => {
-                println!("Successfully patched active session with {minutes} minutes!")
+        Commands::Switch { input, note, time } => {
+            let client_id = resolve_client(store, input)?;
+            let (ended, id) = tracker.switch(client_id, note, time.resolve(clock)?)?;
+            match ended {
+                Some(delta) => print_finished(delta),
+                None => eprintln!("Warning: No active session was found to end!"),
             }
-            Ok(None) => {
-                println!("Error: No active session was found to patch!")
+            println!(
+                "Started logging session {id} for {}",
+                client_name(store, client_id)
+            );
+        }
+        Commands::Patch { minutes } => {
+            tracker.patch(minutes)?;
+            println!("Successfully patched active session with {minutes} minutes!")
+        }
+        Commands::Config(_) => {} // Handled above, config doesn't need a database
+        Commands::Doctor { fix } => run_doctor(store, fix)?,
+        Commands::Lock { until: None } => {
+            match lock::get_lock(sqlite.ok_or_else(|| needs_sqlite("lock"))?)? {
+                Some(until) => println!("Sessions up to and including {until} are locked"),
+                None => println!("No period is locked"),
             }
-            Err(e) => eprintln!("Error: Failed to patch active session: {e}"),
-        },
-        Commands::Config(command) => {
+        }
+        Commands::Lock { until: Some(until) } => {
+            let conn = sqlite.ok_or_else(|| needs_sqlite("lock"))?;
+            if let Some(current) = lock::get_lock(conn)?
+                && until < current
+                && !cli.force
+            {
+                return Err(Error::Conflict(format!(
+                    "Sessions up to {current} are already locked, use --force to unlock part of that period"
+                )));
+            }
+            lock::set_lock(conn, until)?;
+            println!("Sessions up to and including {until} are now locked")
+        }
+        Commands::Backup(command) => {
+            let conn = sqlite.ok_or_else(|| needs_sqlite("backup"))?;
+            let dir = &config.backup_dir;
             match command {
-                cli::ConfigCommand::Set { database_path } => {
-                    if let Some(database_path) = database_path {
-                        config.database_path = database_path;
-                        config.save(); // Save update to disk
-                        println!(
-                            "Successfully updated database path to: {}",
-                            config.database_path.to_str().unwrap()
-                        );
+                cli::BackupCommand::Now => {
+                    let path = backup::create(conn, dir, "manual")?;
+                    backup::rotate(dir, &config.database_path, config.backup_retention);
+                    println!("Backed up the database to {}", path.display())
+                }
+                cli::BackupCommand::List => {
+                    let names = backup::list(dir, &config.database_path);
+                    if names.is_empty() {
+                        println!("No backups to display!");
+                    }
+                    for name in names {
+                        println!("{name}");
                     }
                 }
-                cli::ConfigCommand::Show => println!("{config}"),
-                cli::ConfigCommand::Reset => {
-                    config::reset_config(); // ! Should I do anything with the old config?
-                    println!("Your config has been reset!")
+                cli::BackupCommand::Restore { name } => {
+                    let safety = match backup::restore(&config.database_path, dir, &name) {
+                        Ok(safety) => safety,
+                        Err(rusqlite::Error::InvalidPath(_)) => {
+                            return Err(Error::NotFound(format!(
+                                "No backup named '{name}' was found!"
+                            )));
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+                    // The backup may predate the current schema
+                    db::apply_migrations(conn)?;
+                    println!(
+                        "Restored {name}. The previous state was saved as {}",
+                        safety.file_name().unwrap_or_default().to_string_lossy()
+                    )
                 }
             }
         }
+        Commands::Undo => {
+            let conn = sqlite.ok_or_else(|| needs_sqlite("undo"))?;
+            println!("Undid `timber {}`", journal::undo(conn)?)
+        }
+        Commands::Redo => {
+            let conn = sqlite.ok_or_else(|| needs_sqlite("redo"))?;
+            println!("Redid `timber {}`", journal::redo(conn)?)
+        }
         Commands::Status => {
-            match db::get_active_session(&conn) {
-                Ok(Some(session)) => {
-                    let view =
-                        SessionView::from_session(&conn, session).expect("Unable to open session");
-                    let (hours, minutes) =
-                        utils::split_minutes(view.session.get_timedelta().num_minutes());
-                    println!(
-                        "Active session: {} ({}h {}m)",
-                        view.client_name, hours, minutes
-                    );
-                }
-                Ok(None) => println!("Active session: None!"),
-                Err(_) => {
-                    println!("Error: Unable to retrieve active session");
+            let active = tracker
+                .active_session()?
+                .map(|view| (view.client_name, view.duration.num_minutes()));
+            let (start, end) = utils::current_day_range(clock);
+            print_status(active, &tracker.summarize(&start, &end)?);
+        }
+        Commands::Sync { dir } => {
+            let conn = sqlite.ok_or_else(|| needs_sqlite("sync"))?;
+            let Some(dir) = dir.or(config.sync_dir.clone()) else {
+                return Err(Error::Config(
+                    "No sync directory is set, pass --dir or run `timber config set --sync-dir <DIR>`"
+                        .into(),
+                ));
+            };
+            let report = sync::sync(conn, &dir)?;
+            if let Some(machine) = &report.new_machine {
+                eprintln!(
+                    "Warning: Another database, e.g. a copy of this one, was syncing as this machine. This one now syncs as machine {machine}"
+                );
+            }
+            for conflict in &report.conflicts {
+                eprintln!("Conflict: {conflict}");
+            }
+            if report.unreadable > 0 {
+                eprintln!(
+                    "Warning: Skipped {} unreadable line(s) in other machines' logs",
+                    report.unreadable
+                );
+            }
+            println!(
+                "Synced with {} other machine(s): {} change(s) in, {} out",
+                report.machines, report.imported, report.exported
+            );
+        }
+        Commands::Convert { to, path } => {
+            if path.exists() {
+                return Err(Error::Conflict(format!(
+                    "{} already exists, pick a new path to convert into",
+                    path.display()
+                )));
+            }
+            let (clients, sessions) = store::copy(store, &*store::open_path(to, &path)?)?;
+            println!(
+                "Copied {clients} clients and {sessions} sessions to {}",
+                path.display()
+            );
+            let backend = match to {
+                Backend::Sqlite => "sqlite",
+                Backend::Text => "text",
+            };
+            println!(
+                "Run `timber config set --backend {backend} --database-path {}` to start using it",
+                path.display()
+            );
+        }
+        Commands::Db(_) => {}      // Handled above, before any migration runs
+        Commands::Profile(_) => {} // Handled above, profiles don't need a database
+        Commands::Team(_) => {}    // Handled above, reports read other databases
+        Commands::Prompt { .. } => {} // Handled above, before the store is opened
+        Commands::Completions { .. } => {} // Handled above, completions don't need a database
+        #[cfg(feature = "gui")]
+        Commands::Gui => {} // Handled above, the GUI takes ownership of the tracker
+        #[cfg(feature = "serve")]
+        Commands::Serve { .. } => {} // Handled above, requests are journaled one by one
+    }
+    Ok(())
+}
+
+fn run_doctor(store: &dyn Store, fix: bool) -> error::Result<()> {
+    let interactive = !fix && std::io::stdin().is_terminal();
+    let mut skipped: HashSet<String> = HashSet::new();
+    let mut found_any = false;
+
+    // Rescan after every repair, since fixing one problem can resolve or reshape others
+    loop {
+        let issues = commands::doctor::scan(store)?;
+        let Some(issue) = issues
+            .into_iter()
+            .find(|issue| !skipped.contains(&issue.to_string()))
+        else {
+            break;
+        };
+        found_any = true;
+        println!("{issue}");
+
+        let fixes = issue.fixes();
+        let chosen = if fix {
+            Some(0)
+        } else if interactive {
+            let mut options: Vec<String> = fixes.iter().map(|f| f.to_string()).collect();
+            options.push("Skip".into());
+            utils::prompt_choice("Repair", &options).filter(|&i| i < fixes.len())
+        } else {
+            None
+        };
+
+        match chosen {
+            Some(i) => match commands::doctor::apply(store, &fixes[i]) {
+                Ok(_) => println!("  Fixed: {}", fixes[i]),
+                Err(e) => {
+                    eprintln!("  Error: Failed to apply the repair: {e}");
+                    skipped.insert(issue.to_string());
                 }
+            },
+            None => {
+                skipped.insert(issue.to_string());
+            }
+        }
+    }
+
+    if !found_any {
+        println!("No problems found!");
+    } else if !skipped.is_empty() {
+        let hint = if !fix && !interactive {
+            ", run `timber doctor --fix` to apply the recommended repairs"
+        } else {
+            ""
+        };
+        return Err(Error::Invalid(format!(
+            "{} problem(s) were left unrepaired{hint}",
+            skipped.len()
+        )));
+    }
+    Ok(())
+}
+
+fn run_config_command(config: &Config, command: cli::ConfigCommand) -> error::Result<()> {
+    match command {
+        cli::ConfigCommand::Set {
+            database_path,
+            backup_retention,
+            backend,
+            sync_dir,
+            prompt_format,
+        } => {
+            if let Some(database_path) = database_path {
+                // Save update to disk
+                config::update_config(config, |stored| {
+                    stored.database_path = database_path.clone()
+                })?;
+                println!(
+                    "Successfully updated database path to: {}",
+                    database_path.display()
+                );
+            }
+            if let Some(backup_retention) = backup_retention {
+                config::update_config(config, |stored| stored.backup_retention = backup_retention)?;
+                println!("Now keeping up to {backup_retention} backups");
+            }
+            if let Some(backend) = backend {
+                config::update_config(config, |stored| stored.backend = backend)?;
+                println!(
+                    "Now storing data as {}, run `timber convert` to move existing data over",
+                    match backend {
+                        Backend::Sqlite => "an SQLite database",
+                        Backend::Text => "a plain-text log",
+                    }
+                );
+            }
+            if let Some(sync_dir) = sync_dir {
+                config::update_config(config, |stored| stored.sync_dir = Some(sync_dir.clone()))?;
+                println!("Now syncing through {}", sync_dir.display());
+            }
+            if let Some(prompt_format) = prompt_format {
+                config::update_config(config, |stored| {
+                    stored.prompt_format = prompt_format.clone()
+                })?;
+                println!("`timber prompt` now shows \"{prompt_format}\"");
             }
-            display_daily_time_summary(&conn);
         }
-        Commands::Gui => {
-            let _ = gui::main(conn);
-            return;
+        cli::ConfigCommand::Show => println!("# Profile: {}\n{config}", config.profile),
+        cli::ConfigCommand::Path => println!("{}", config.config_path.display()),
+        cli::ConfigCommand::Reset => {
+            // ! Should I do anything with the old config?
+            config::reset_config(config)?;
+            println!("Your config has been reset!")
         }
     }
+    Ok(())
 }
 
-fn end_session(conn: &Connection) {
-    match commands::session::end_session(conn) {
-        Ok(Some(delta)) => {
-            println!(
-                "Finished logging: {}hr {}m",
-                delta.num_hours(),
-                delta.num_minutes().wrapping_rem(60)
-            );
+fn run_profile_command(
+    overrides: &config::Overrides,
+    command: cli::ProfileCommand,
+) -> error::Result<()> {
+    let mut file = config::ConfigFile::load(overrides.config_file())?;
+    let active = file
+        .active_profile
+        .clone()
+        .unwrap_or_else(|| config::DEFAULT_PROFILE.to_string());
+    match command {
+        cli::ProfileCommand::Use { name } => {
+            if file.profile(&name).is_none() {
+                let config = Config::for_profile(&name)?;
+                println!(
+                    "Created profile '{name}' with database {}",
+                    config.database_path.display()
+                );
+                file.profiles.insert(name.clone(), config);
+            }
+            file.active_profile = Some(name.clone());
+            file.save()?;
+            println!("Now using profile '{name}'");
         }
-        Ok(None) => println!("Warning: No active session was found to end!"),
-        Err(_) => println!("Error: Unable to to finish the session"),
-    };
+        cli::ProfileCommand::List => {
+            for name in file.profile_names() {
+                let marker = if name == active { "*" } else { " " };
+                let path = file
+                    .profile(&name)
+                    .map(|p| p.database_path.display().to_string())
+                    .unwrap_or_default();
+                println!("{marker} {name} ({path})");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_team_command(databases: &[PathBuf], command: cli::TeamCommand) -> error::Result<()> {
+    let cli::TeamCommand::Report { from, to, csv } = command;
+    if databases.is_empty() {
+        return Err(Error::Invalid(
+            "Give each teammate's database with --db, e.g. `timber team report --db alice.db --db bob.db`"
+                .into(),
+        ));
+    }
+    let clock = clock::from_env()?;
+    let (week_start, week_end) = utils::current_week_range(&*clock);
+    let start = from
+        .map(|day| day.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .unwrap_or(week_start);
+    let end = to
+        .map(|day| day.and_hms_opt(23, 59, 59).unwrap().and_utc())
+        .unwrap_or(week_end);
+    if end < start {
+        return Err(Error::Invalid("--to must not be before --from".into()));
+    }
+
+    let mut connections = vec![];
+    let mut names = HashSet::new();
+    for arg in databases {
+        let (name, path) = team::member_from_arg(arg);
+        if !names.insert(name.clone()) {
+            return Err(Error::Invalid(format!(
+                "More than one database is named '{name}', name them with `--db <name>=<path>`"
+            )));
+        }
+        connections.push((name, db::open_read_only(&path)?));
+    }
+    let members: Vec<(String, &dyn Store)> = connections
+        .iter()
+        .map(|(name, conn)| (name.clone(), conn as &dyn Store))
+        .collect();
+    let report = team::report(&members, &*clock, &start, &end)?;
+    if csv {
+        print!("{}", report.to_csv());
+    } else {
+        println!(
+            "Team report for {} to {}\n",
+            start.format("%b %d, %Y"),
+            end.format("%b %d, %Y")
+        );
+        println!("{report}");
+    }
+    Ok(())
 }
 
-fn start_session(conn: &Connection, input: UserInput, note: Option<String>) {
-    let client_id = match utils::handle_user_client_input(conn, Some(input)) {
-        Some(id) => id,
-        None => {
-            println!("Error: No client with that name found. Do they exist?");
-            return;
+fn show_schema_status(conn: &rusqlite::Connection) -> error::Result<()> {
+    db::init_schema(conn)?;
+    let current = db::get_schema_version(conn);
+    println!("Schema version: {current}");
+    println!("Latest version: {}", db::SCHEMA_VERSION);
+    if current > db::SCHEMA_VERSION {
+        println!("This database was created by a newer version of Timber, please upgrade.");
+    } else if current == db::SCHEMA_VERSION {
+        println!("The database is up to date.");
+    } else {
+        println!("Pending migrations:");
+        for (version, migration) in db::MIGRATIONS.iter().enumerate().skip(current as usize) {
+            println!("  {}: {}", version + 1, migration.description);
         }
+        println!(
+            "These will be applied (after a backup) by `timber db migrate`, or the next time Timber runs."
+        );
+    }
+    Ok(())
+}
+
+fn run_prompt(
+    config: &Config,
+    format: Option<String>,
+    json: bool,
+    snippet: Option<timber::prompt::Snippet>,
+) -> error::Result<()> {
+    use timber::prompt;
+
+    if let Some(snippet) = snippet {
+        println!("{}", snippet.text());
+        return Ok(());
+    }
+    let format = format.unwrap_or_else(|| config.prompt_format.clone());
+    let state = prompt_state(config)?;
+    if json {
+        println!("{}", prompt::render_json(&format, state.as_ref()));
+    } else {
+        let line = prompt::render(&format, state.as_ref());
+        if !line.is_empty() {
+            println!("{line}");
+        }
+    }
+    Ok(())
+}
+
+/// The active session's client and minutes so far with today's total, asking timberd if it runs
+fn prompt_state(config: &Config) -> error::Result<Option<timber::prompt::PromptState>> {
+    use timber::prompt::PromptState;
+
+    #[cfg(all(unix, feature = "daemon"))]
+    if !clock::is_pinned()
+        && let Some(mut daemon) = daemon::Daemon::connect(config)
+        && let Ok((active, summary)) = daemon.status()
+    {
+        return Ok(active.map(|(client, minutes)| PromptState {
+            client,
+            minutes,
+            today_minutes: summary.total_minutes(),
+        }));
+    }
+
+    // Read as it is, the next full command migrates it if needed
+    let Some(store) = store::open_read_only(config)? else {
+        return Ok(None);
+    };
+    let tracker = Tracker::new(store).with_clock(clock::from_env()?);
+    let Some(view) = tracker.active_session()? else {
+        return Ok(None);
     };
-    match db::get_active_session(conn) {
-        // ! Wrote func in view? ?
-        Ok(Some(session)) => {
+    let (start, end) = utils::current_day_range(tracker.clock());
+    Ok(Some(PromptState {
+        client: view.client_name,
+        minutes: view.duration.num_minutes(),
+        today_minutes: tracker.summarize(&start, &end)?.total_minutes(),
+    }))
+}
+
+/// Run `command` through timberd if it is one of the everyday commands the daemon serves,
+/// returning whether it was
+#[cfg(all(unix, feature = "daemon"))]
+fn run_with_daemon(
+    daemon: &mut daemon::Daemon,
+    command: &Commands,
+    command_line: &str,
+) -> error::Result<bool> {
+    use serde_json::json;
+
+    let clock = clock::SystemClock;
+    match command {
+        Commands::Status => {
+            let (active, summary) = daemon.status()?;
+            print_status(active, &summary);
+        }
+        Commands::Summary { range } => {
+            let range = match range {
+                cli::SummaryRange::Daily => "daily",
+                cli::SummaryRange::Weekly => "weekly",
+                cli::SummaryRange::Monthly => "monthly",
+            };
+            println!("{}", daemon.summary(range)?);
+        }
+        Commands::Client(ClientOptions::Add { name, note }) => {
+            let created = daemon.request(
+                "POST",
+                "/clients",
+                json!({ "name": name, "note": note, "command": command_line }),
+            )?;
+            println!("Client added with id {}", created["id"])
+        }
+        Commands::Session(SessionOptions::Start { input, note, time }) => {
+            let client_id = daemon_client(daemon, input)?;
+            let started = daemon.request(
+                "POST",
+                "/sessions/start",
+                json!({
+                    "client": client_id,
+                    "note": note,
+                    "at": time.resolve(&clock)?.to_rfc3339(),
+                    "command": command_line,
+                }),
+            )?;
+            println!(
+                "Started logging session {} for {}",
+                started["id"],
+                daemon_client_name(daemon, client_id)
+            );
+        }
+        Commands::Session(SessionOptions::End { time }) => {
+            let ended = daemon.request(
+                "POST",
+                "/sessions/stop",
+                json!({ "at": time.resolve(&clock)?.to_rfc3339(), "command": command_line }),
+            )?;
+            match ended["minutes"].as_i64() {
+                Some(minutes) => print_finished(TimeDelta::minutes(minutes)),
+                None => {
+                    return Err(Error::NotFound(
+                        "No active session was found to end!".into(),
+                    ));
+                }
+            }
+        }
+        Commands::Switch { input, note, time } => {
+            let client_id = daemon_client(daemon, input)?;
+            let switched = daemon.request(
+                "POST",
+                "/sessions/switch",
+                json!({
+                    "client": client_id,
+                    "note": note,
+                    "at": time.resolve(&clock)?.to_rfc3339(),
+                    "command": command_line,
+                }),
+            )?;
+            match switched["ended_minutes"].as_i64() {
+                Some(minutes) => print_finished(TimeDelta::minutes(minutes)),
+                None => eprintln!("Warning: No active session was found to end!"),
+            }
             println!(
-                "Error: Cannot start a session because you are currently have a session for {}",
-                db::get_client_by_id(conn, session.client_id)
-                    .expect("Error: Unable to get client information")
-                    .name
-            )
-        }
-        Ok(None) => {
-            match db::store_session(
-                conn,
-                &Session {
-                    id: 0, // Will be assigned by sqlite instead
-                    client_id,
-                    start_timestamp: Utc::now().to_rfc3339(),
-                    end_timestamp: Option::None,
-                    note,
-                    offset_minutes: 0,
-                },
-            ) {
-                Ok(id) => println!(
-                    "Started logging session {} for {}",
-                    id,
-                    db::get_client_by_id(conn, client_id).unwrap().name
-                ),
-                Err(_) => println!("Error: Unable to start a new session"),
-            }
-        }
-        Err(e) => eprintln!("Error: Unable to get the current session: {e}"),
+                "Started logging session {} for {}",
+                switched["id"],
+                daemon_client_name(daemon, client_id)
+            );
+        }
+        Commands::Patch { minutes } => {
+            daemon.request(
+                "POST",
+                "/sessions/patch",
+                json!({ "minutes": minutes, "command": command_line }),
+            )?;
+            println!("Successfully patched active session with {minutes} minutes!")
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// The id of the client `input` refers to, like `resolve_client` but asking timberd
+#[cfg(all(unix, feature = "daemon"))]
+fn daemon_client(daemon: &mut daemon::Daemon, input: &UserInput) -> error::Result<i32> {
+    match input {
+        UserInput::ById(id) => Ok(*id),
+        UserInput::ByName(name) => utils::choose_client(name.clone(), daemon.clients(Some(name))?)?
+            .ok_or_else(|| {
+                Error::NotFound("No client with that name found. Do they exist?".into())
+            }),
     }
 }
+
+/// Like `client_name`, but asking timberd
+#[cfg(all(unix, feature = "daemon"))]
+fn daemon_client_name(daemon: &mut daemon::Daemon, client_id: i32) -> String {
+    daemon
+        .clients(None)
+        .ok()
+        .and_then(|clients| clients.into_iter().find(|client| client.id == client_id))
+        .map(|client| client.name)
+        .unwrap_or_else(|| format!("client {client_id}"))
+}
+
+/// The active session's client and minutes so far, then today's summary
+fn print_status(active: Option<(String, i64)>, summary: &views::Summary) {
+    match active {
+        Some((client_name, minutes)) => {
+            let (hours, minutes) = utils::split_minutes(minutes);
+            println!("Active session: {client_name} ({hours}h {minutes}m)");
+        }
+        None => println!("Active session: None!"),
+    }
+    println!("{summary}");
+}
+
+/// Whether `command` can change clients, sessions or the lock, and so runs as an operation for
+/// undo and the audit log. Undo, redo, backups, sync and convert record themselves.
+fn is_mutating(command: &Commands) -> bool {
+    match command {
+        Commands::Client(command) => !matches!(command, ClientOptions::List),
+        Commands::Session(command) => !matches!(
+            command,
+            SessionOptions::List { .. } | SessionOptions::Current | SessionOptions::History { .. }
+        ),
+        Commands::Lock { until } => until.is_some(),
+        Commands::Switch { .. } | Commands::Patch { .. } | Commands::Doctor { .. } => true,
+        _ => false,
+    }
+}
+
+fn client_not_found() -> Error {
+    Error::NotFound("Provided client could not be found!".into())
+}
+
+/// For commands built on features that only SQLite databases have
+fn needs_sqlite(command: &str) -> Error {
+    Error::Invalid(format!(
+        "`timber {command}` needs the SQLite backend, see `timber convert`"
+    ))
+}
+
+fn find_client(store: &dyn Store, input: UserInput) -> error::Result<Client> {
+    let client_id =
+        utils::handle_user_client_input(store, Some(input))?.ok_or_else(client_not_found)?;
+    store.get_client(client_id)
+}
+
+fn resolve_client(store: &dyn Store, input: UserInput) -> error::Result<i32> {
+    utils::handle_user_client_input(store, Some(input))?
+        .ok_or_else(|| Error::NotFound("No client with that name found. Do they exist?".into()))
+}
+
+fn print_finished(delta: TimeDelta) {
+    println!(
+        "Finished logging: {}hr {}m",
+        delta.num_hours(),
+        delta.num_minutes().wrapping_rem(60)
+    );
+}
+
+/// The name of a client for messages, which shouldn't fail just because the client is missing
+fn client_name(store: &dyn Store, client_id: i32) -> String {
+    store
+        .get_client(client_id)
+        .map(|client| client.name)
+        .unwrap_or_else(|_| format!("client {client_id}"))
+}
@@ -1,33 +1,44 @@
 use chrono::{DateTime, Duration, TimeDelta, Utc};
-#[derive(Debug)]
+
+use crate::clock::Clock;
+#[derive(Debug, Clone)]
 pub struct Session {
     pub id: i32,
     pub client_id: i32,
-    pub start_timestamp: String,       // stored in RFC339
-    pub end_timestamp: Option<String>, // stored in RFC339
+    pub start_timestamp: DateTime<Utc>, // stored as seconds since the Unix epoch
+    pub end_timestamp: Option<DateTime<Utc>>,
     pub note: Option<String>,
     pub offset_minutes: i32, // can be negative or positive
 }
 impl Session {
-    pub fn get_timedelta(&self) -> TimeDelta {
-        let start: DateTime<Utc> = self
-            .start_timestamp
-            .parse::<DateTime<Utc>>()
-            .expect("Invalid start timestamp");
-
-        let end = match &self.end_timestamp {
-            Some(end_str) => end_str
-                .parse::<DateTime<Utc>>()
-                .expect("Failed to parse ending timestamp"),
-            None => Utc::now(),
-        };
-        end - start + Duration::minutes(self.offset_minutes.into())
+    /// How long the session ran, or has been running so far according to `clock`
+    pub fn get_timedelta(&self, clock: &dyn Clock) -> TimeDelta {
+        let end = self.end_timestamp.unwrap_or_else(|| clock.now());
+        end - self.start_timestamp + Duration::minutes(self.offset_minutes.into())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Client {
     pub id: i32,
     pub name: String,
     pub note: Option<String>,
 }
+
+/// A client as typed by the user, either its id or a name or alias
+#[derive(Clone)]
+pub enum UserInput {
+    ByName(String),
+    ById(i32),
+}
+
+/// A single field change recorded in the audit log
+#[derive(Debug)]
+pub struct AuditEntry {
+    pub timestamp: String, // stored in RFC339
+    pub action: String,    // insert, update or delete
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub command: Option<String>, // the timber command that made the change, if known
+}
@@ -0,0 +1,151 @@
+//! The one-line status of `timber prompt`, for shell prompts and status bars
+
+use std::fmt::Write as _;
+
+use crate::utils;
+
+/// The placeholders a prompt template can use
+pub const PLACEHOLDERS: &str = "{client}, {elapsed}, {minutes} and {today}";
+
+pub const DEFAULT_FORMAT: &str = "{client} {elapsed}";
+
+/// What a prompt shows about the active session
+pub struct PromptState {
+    pub client: String,
+    /// How long the active session has run
+    pub minutes: i64,
+    /// Time tracked today across all clients
+    pub today_minutes: i64,
+}
+
+/// Fill in `template`, or nothing when no session is active so idle prompts stay clean
+pub fn render(template: &str, state: Option<&PromptState>) -> String {
+    let Some(state) = state else {
+        return String::new();
+    };
+    let duration = |minutes: i64| {
+        let (hours, minutes) = utils::split_minutes(minutes);
+        format!("{hours}h {minutes}m")
+    };
+    template
+        .replace("{client}", &state.client)
+        .replace("{elapsed}", &duration(state.minutes))
+        .replace("{minutes}", &state.minutes.to_string())
+        .replace("{today}", &duration(state.today_minutes))
+}
+
+/// The prompt as a waybar custom module expects it: text, a tooltip and a class to style by
+pub fn render_json(template: &str, state: Option<&PromptState>) -> String {
+    let tooltip = match state {
+        Some(state) => {
+            let (hours, minutes) = utils::split_minutes(state.today_minutes);
+            format!("Tracked today: {hours}h {minutes}m")
+        }
+        None => "No active session".to_string(),
+    };
+    let class = if state.is_some() { "active" } else { "idle" };
+    format!(
+        "{{\"text\":\"{}\",\"tooltip\":\"{}\",\"class\":\"{class}\"}}",
+        json_escape(&render(template, state)),
+        json_escape(&tooltip)
+    )
+}
+
+fn json_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Where a prompt can be shown, for `timber prompt --snippet`
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum Snippet {
+    Bash,
+    Zsh,
+    Fish,
+    Tmux,
+    Waybar,
+    Polybar,
+}
+
+impl Snippet {
+    /// Config to paste into the shell's or bar's config file
+    pub fn text(self) -> &'static str {
+        match self {
+            Snippet::Bash => {
+                "# ~/.bashrc\n\
+                 __timber_prompt() { local t; t=$(timber prompt 2>/dev/null); [ -n \"$t\" ] && printf '[%s] ' \"$t\"; }\n\
+                 PS1='$(__timber_prompt)'\"$PS1\""
+            }
+            Snippet::Zsh => {
+                "# ~/.zshrc\n\
+                 setopt PROMPT_SUBST\n\
+                 __timber_prompt() { local t; t=$(timber prompt 2>/dev/null); [[ -n $t ]] && print -n \"[$t] \"; }\n\
+                 PROMPT='$(__timber_prompt)'$PROMPT"
+            }
+            Snippet::Fish => {
+                "# ~/.config/fish/functions/fish_right_prompt.fish\n\
+                 function fish_right_prompt\n    \
+                     timber prompt 2>/dev/null\n\
+                 end"
+            }
+            Snippet::Tmux => {
+                "# ~/.tmux.conf\n\
+                 set -g status-interval 15\n\
+                 set -g status-right '#(timber prompt 2>/dev/null) %H:%M'"
+            }
+            Snippet::Waybar => {
+                "// ~/.config/waybar/config, then add \"custom/timber\" to a modules list\n\
+                 \"custom/timber\": {\n    \
+                     \"exec\": \"timber prompt --json\",\n    \
+                     \"return-type\": \"json\",\n    \
+                     \"interval\": 15,\n    \
+                     \"on-click\": \"timber gui\"\n\
+                 }"
+            }
+            Snippet::Polybar => {
+                "; ~/.config/polybar/config.ini, then add timber to a modules list\n\
+                 [module/timber]\n\
+                 type = custom/script\n\
+                 exec = timber prompt 2>/dev/null\n\
+                 interval = 15\n\
+                 click-left = timber gui &"
+            }
+        }
+    }
+}
+
+// TESTS
+
+#[test]
+fn test_prompt_rendering() {
+    let state = PromptState {
+        client: "Acme \"East\"".into(),
+        minutes: 65,
+        today_minutes: 200,
+    };
+    assert_eq!(render(DEFAULT_FORMAT, Some(&state)), "Acme \"East\" 1h 5m");
+    assert_eq!(
+        render("{minutes}m of {today}", Some(&state)),
+        "65m of 3h 20m"
+    );
+    assert_eq!(render(DEFAULT_FORMAT, None), "");
+    assert_eq!(
+        render_json(DEFAULT_FORMAT, Some(&state)),
+        r#"{"text":"Acme \"East\" 1h 5m","tooltip":"Tracked today: 3h 20m","class":"active"}"#
+    );
+    assert_eq!(
+        render_json(DEFAULT_FORMAT, None),
+        r#"{"text":"","tooltip":"No active session","class":"idle"}"#
+    );
+}
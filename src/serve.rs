@@ -0,0 +1,512 @@
+//! A small JSON API over HTTP, for editor plugins and scripts that want to drive Timber without
+//! parsing its output. It serves one request at a time on top of a [`Tracker`]:
+//!
+//! | Request                  | Body                                 | Response                      |
+//! |--------------------------|--------------------------------------|-------------------------------|
+//! | `GET /clients`           | `?name=` to find clients by name     | the clients                   |
+//! | `POST /clients`          | `{"name", "note"?}`                  | `{"id"}`                      |
+//! | `GET /sessions`          | `?client_id=` to filter              | the sessions, newest first    |
+//! | `GET /sessions/active`   |                                      | the active session, or `null` |
+//! | `POST /sessions/start`   | `{"client", "note"?, "at"?}`         | `{"id"}`                      |
+//! | `POST /sessions/stop`    | `{"at"?}`                            | `{"minutes"}`, or `null`      |
+//! | `POST /sessions/switch`  | `{"client", "note"?, "at"?}`         | `{"id", "ended_minutes"}`     |
+//! | `POST /sessions/patch`   | `{"minutes"}`                        | `{}`                          |
+//! | `GET /summary`           | `?range=daily\|weekly\|monthly` or `?from=&to=` days | minutes per client |
+//!
+//! `client` is an id or a name, `at` takes the same times as `--at` on the command line. Changes
+//! can carry a `command` to show in `timber undo` instead of one made up from the request. Errors
+//! come back as `{"error"}` with a status matching the CLI's exit code: 404 for anything missing,
+//! 409 for conflicts and 400 for invalid requests.
+//!
+//! So that web pages open in a browser can't reach it, requests naming a `Host` or `Origin` other
+//! than this machine are refused with 403, and `POST`s must be sent as `application/json`.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::{Value, json};
+
+use crate::{
+    error::{Error, Result},
+    journal,
+    models::{Client, Session},
+    store::Store,
+    tracker::Tracker,
+    utils,
+};
+
+/// Answer requests on `listen`, e.g. `127.0.0.1:7777`, until the process is stopped
+pub fn serve<S: Store>(tracker: &Tracker<S>, listen: &str) -> Result<()> {
+    let server = tiny_http::Server::http(listen).map_err(|e| {
+        Error::Io(std::io::Error::other(format!(
+            "Unable to listen on {listen}: {e}"
+        )))
+    })?;
+    println!("Serving the Timber API on http://{listen}");
+    for mut request in server.incoming_requests() {
+        let header = |name: &str| {
+            request
+                .headers()
+                .iter()
+                .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+                .map(|h| h.value.as_str().to_string())
+        };
+        let refused = check_headers(
+            request.method().as_str(),
+            header("Host").as_deref(),
+            header("Origin").as_deref(),
+            header("Content-Type").as_deref(),
+        );
+        let mut body = String::new();
+        let (status, value) = match refused {
+            Some(refused) => refused,
+            None => match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => handle(tracker, request.method().as_str(), request.url(), &body),
+                Err(e) => (
+                    400,
+                    json!({ "error": format!("Unable to read the request: {e}") }),
+                ),
+            },
+        };
+        let response = tiny_http::Response::from_string(value.to_string())
+            .with_status_code(status)
+            .with_header(
+                "Content-Type: application/json"
+                    .parse::<tiny_http::Header>()
+                    .expect("a valid header"),
+            );
+        if let Err(e) = request.respond(response) {
+            eprintln!("Warning: Unable to send a response: {e}");
+        }
+    }
+    Ok(())
+}
+
+/// Refuse requests that could come from a web page rather than a local program: ones addressed to
+/// another host (DNS rebinding), sent from another origin, or changes that a page could send
+/// without asking first because they aren't JSON
+fn check_headers(
+    method: &str,
+    host: Option<&str>,
+    origin: Option<&str>,
+    content_type: Option<&str>,
+) -> Option<(u16, Value)> {
+    if let Some(host) = host.filter(|host| !is_local(host)) {
+        return Some((
+            403,
+            json!({ "error": format!("Requests for {host} are not served") }),
+        ));
+    }
+    if let Some(origin) = origin.filter(|origin| !is_local(origin)) {
+        return Some((
+            403,
+            json!({ "error": format!("Requests from {origin} are not served") }),
+        ));
+    }
+    let is_json = content_type
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
+    if method == "POST" && !is_json {
+        return Some((
+            415,
+            json!({ "error": "Changes must be sent with Content-Type: application/json" }),
+        ));
+    }
+    None
+}
+
+/// Whether a `Host` (`localhost:7777`) or `Origin` (`http://localhost:7777`) names this machine
+fn is_local(value: &str) -> bool {
+    let host = value.split_once("://").map_or(value, |(_, rest)| rest);
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or(""),
+        None => host.split(':').next().unwrap_or(""),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Route one request, returning the HTTP status and JSON body
+pub fn handle<S: Store>(tracker: &Tracker<S>, method: &str, url: &str, body: &str) -> (u16, Value) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let body: Value = if body.trim().is_empty() {
+        json!({})
+    } else {
+        match serde_json::from_str(body) {
+            Ok(body) => body,
+            Err(e) => {
+                return (
+                    400,
+                    json!({ "error": format!("The body is not JSON: {e}") }),
+                );
+            }
+        }
+    };
+
+    let result = match method {
+        "GET" => get(tracker, path, query),
+        // Record changes like the CLI does, so `timber undo` can reverse them
+        "POST" => match tracker
+            .store()
+            .as_connection()
+            .map(|conn| journal::Operation::begin(conn, &command_for(path, &body)))
+            .transpose()
+        {
+            Ok(_operation) => post(tracker, path, &body),
+            Err(e) => Err(e.into()),
+        },
+        _ => Err(Error::Invalid(format!("{method} is not supported"))),
+    };
+    match result {
+        Ok(Some(value)) => (200, value),
+        Ok(None) => (404, json!({ "error": format!("No such endpoint {path}") })),
+        Err(e) => {
+            let status = match e {
+                Error::NotFound(_) => 404,
+                Error::Conflict(_) => 409,
+                Error::Invalid(_) | Error::Ambiguous { .. } => 400,
+                _ => 500,
+            };
+            (status, json!({ "error": e.to_string() }))
+        }
+    }
+}
+
+fn get<S: Store>(tracker: &Tracker<S>, path: &str, query: &str) -> Result<Option<Value>> {
+    let param = |name: &str| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    };
+    let value = match path {
+        "/clients" => {
+            let clients = match param("name") {
+                Some(name) => tracker.store().find_clients_by_name(&decode(name))?,
+                None => tracker.clients()?,
+            };
+            Value::Array(clients.iter().map(client_json).collect())
+        }
+        "/sessions" => {
+            let client_id = param("client_id")
+                .map(|id| {
+                    id.parse()
+                        .map_err(|_| Error::Invalid(format!("'{id}' is not a client id")))
+                })
+                .transpose()?;
+            let sessions = tracker.sessions(client_id)?;
+            Value::Array(sessions.iter().map(|s| session_json(tracker, s)).collect())
+        }
+        "/sessions/active" => match tracker.active_session()? {
+            Some(view) => {
+                let mut value = session_json(tracker, &view.session);
+                value["client_name"] = json!(view.client_name);
+                value
+            }
+            None => Value::Null,
+        },
+        "/summary" => {
+            let clock = tracker.clock();
+            let day = |name: &str| {
+                param(name)
+                    .map(|day| {
+                        NaiveDate::parse_from_str(day, "%Y-%m-%d").map_err(|_| {
+                            Error::Invalid(format!("'{day}' is not a day like 2025-07-18"))
+                        })
+                    })
+                    .transpose()
+            };
+            let (start, end) = match (day("from")?, day("to")?) {
+                (Some(from), Some(to)) => (
+                    from.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+                    to.and_hms_opt(23, 59, 59).unwrap().and_utc(),
+                ),
+                (None, None) => match param("range").unwrap_or("weekly") {
+                    "daily" => utils::current_day_range(clock),
+                    "weekly" => utils::current_week_range(clock),
+                    "monthly" => utils::current_month_range(clock),
+                    range => {
+                        return Err(Error::Invalid(format!(
+                            "'{range}' is not daily, weekly or monthly"
+                        )));
+                    }
+                },
+                _ => return Err(Error::Invalid("Give both from and to".into())),
+            };
+            let summary = tracker.summarize(&start, &end)?;
+            json!({
+                "from": start.to_rfc3339(),
+                "to": end.to_rfc3339(),
+                "clients": summary
+                    .clients
+                    .iter()
+                    .map(|(name, minutes)| json!({ "name": name, "minutes": minutes }))
+                    .collect::<Vec<_>>(),
+                "total_minutes": summary.total_minutes(),
+            })
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+fn post<S: Store>(tracker: &Tracker<S>, path: &str, body: &Value) -> Result<Option<Value>> {
+    let note = body["note"].as_str().map(str::to_string);
+    let value = match path {
+        "/clients" => {
+            let name = body["name"]
+                .as_str()
+                .ok_or_else(|| Error::Invalid("A client needs a name".into()))?;
+            json!({ "id": tracker.add_client(name, note)? })
+        }
+        "/sessions/start" => {
+            let id = tracker.start(
+                resolve_client(tracker, body)?,
+                note,
+                resolve_at(tracker, body)?,
+            )?;
+            json!({ "id": id })
+        }
+        "/sessions/stop" => match tracker.stop(resolve_at(tracker, body)?)? {
+            Some(delta) => json!({ "minutes": delta.num_minutes() }),
+            None => Value::Null,
+        },
+        "/sessions/switch" => {
+            let (ended, id) = tracker.switch(
+                resolve_client(tracker, body)?,
+                note,
+                resolve_at(tracker, body)?,
+            )?;
+            json!({ "id": id, "ended_minutes": ended.map(|delta| delta.num_minutes()) })
+        }
+        "/sessions/patch" => {
+            let minutes = body["minutes"]
+                .as_i64()
+                .and_then(|minutes| i32::try_from(minutes).ok())
+                .ok_or_else(|| Error::Invalid("Give the minutes to add as a number".into()))?;
+            tracker.patch(minutes)?;
+            json!({})
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+/// The command line a request stands for, shown by `timber undo` and `timber session history`
+fn command_for(path: &str, body: &Value) -> String {
+    if let Some(command) = body["command"].as_str() {
+        return command.to_string();
+    }
+    let client = match &body["client"] {
+        Value::String(name) => name.clone(),
+        other => other.to_string(),
+    };
+    match path {
+        "/clients" => format!("client add {}", body["name"].as_str().unwrap_or_default()),
+        "/sessions/start" => format!("session start {client}"),
+        "/sessions/stop" => "session end".into(),
+        "/sessions/switch" => format!("switch {client}"),
+        "/sessions/patch" => format!("patch {}", body["minutes"]),
+        _ => format!("serve {path}"),
+    }
+}
+
+/// Undo the percent-encoding of a query parameter, e.g. `Acme%20Corp` or `Acme+Corp`
+fn decode(param: &str) -> String {
+    let mut bytes = vec![];
+    let mut rest = param.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, hex) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+                continue;
+            }
+            (b'+', _) => bytes.push(b' '),
+            _ => bytes.push(byte),
+        }
+        rest = tail;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn resolve_client<S: Store>(tracker: &Tracker<S>, body: &Value) -> Result<i32> {
+    match &body["client"] {
+        Value::Number(id) => id
+            .as_i64()
+            .and_then(|id| i32::try_from(id).ok())
+            .ok_or_else(|| Error::Invalid(format!("{id} is not a client id"))),
+        Value::String(name) => {
+            let candidates = tracker.store().find_clients_by_name(name)?;
+            match candidates.as_slice() {
+                [client] => Ok(client.id),
+                [] => Err(Error::NotFound(format!("No client matches '{name}'"))),
+                _ => Err(Error::Invalid(format!(
+                    "'{name}' matches several clients: {}",
+                    candidates
+                        .iter()
+                        .map(|client| client.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))),
+            }
+        }
+        _ => Err(Error::Invalid("Give the client as an id or a name".into())),
+    }
+}
+
+fn resolve_at<S: Store>(tracker: &Tracker<S>, body: &Value) -> Result<DateTime<Utc>> {
+    let now = tracker.clock().now();
+    match body["at"].as_str() {
+        Some(at) => utils::parse_natural_time(at, now)
+            .ok_or_else(|| Error::Invalid(format!("Unable to understand the time '{at}'"))),
+        None => Ok(now),
+    }
+}
+
+fn client_json(client: &Client) -> Value {
+    json!({ "id": client.id, "name": client.name, "note": client.note })
+}
+
+fn session_json<S: Store>(tracker: &Tracker<S>, session: &Session) -> Value {
+    json!({
+        "id": session.id,
+        "client_id": session.client_id,
+        "start": session.start_timestamp.to_rfc3339(),
+        "end": session.end_timestamp.map(|end| end.to_rfc3339()),
+        "note": session.note,
+        "offset_minutes": session.offset_minutes,
+        "minutes": session.get_timedelta(tracker.clock()).num_minutes(),
+    })
+}
+
+// TESTS
+
+#[test]
+fn test_api_drives_the_tracker() {
+    use crate::clock::FixedClock;
+
+    let now: DateTime<Utc> = "2025-07-18T17:00:00Z".parse().unwrap();
+    let tracker = Tracker::open_in_memory()
+        .unwrap()
+        .with_clock(Box::new(FixedClock(now)));
+
+    let (status, created) = handle(&tracker, "POST", "/clients", r#"{"name": "Acme"}"#);
+    assert_eq!((status, created["id"].as_i64()), (200, Some(1)));
+    assert_eq!(
+        handle(&tracker, "POST", "/clients", r#"{"name": "Acme"}"#).0,
+        409
+    );
+    let (_, found) = handle(&tracker, "GET", "/clients?name=ac%6De", "");
+    assert_eq!(found[0]["name"], "Acme");
+
+    let (status, started) = handle(
+        &tracker,
+        "POST",
+        "/sessions/start",
+        r#"{"client": "acme", "at": "2025-07-18T15:00:00Z"}"#,
+    );
+    assert_eq!((status, started["id"].as_i64()), (200, Some(1)));
+    let (_, active) = handle(&tracker, "GET", "/sessions/active", "");
+    assert_eq!(active["client_name"], "Acme");
+    assert_eq!(active["minutes"], 120);
+
+    assert_eq!(
+        handle(&tracker, "POST", "/sessions/patch", r#"{"minutes": 15}"#).0,
+        200
+    );
+    let (_, stopped) = handle(&tracker, "POST", "/sessions/stop", "");
+    assert_eq!(stopped["minutes"], 135);
+    assert_eq!(
+        handle(&tracker, "GET", "/sessions/active", ""),
+        (200, Value::Null)
+    );
+
+    let (_, summary) = handle(
+        &tracker,
+        "GET",
+        "/summary?from=2025-07-18&to=2025-07-18",
+        "",
+    );
+    assert_eq!(summary["clients"][0]["name"], "Acme");
+    assert_eq!(summary["total_minutes"], 135);
+
+    // Changes can be undone like the CLI's
+    assert_eq!(journal::undo(tracker.connection()).unwrap(), "session end");
+    assert_eq!(
+        handle(&tracker, "POST", "/sessions/start", r#"{"client": 9}"#).0,
+        404
+    );
+    handle(
+        &tracker,
+        "POST",
+        "/clients",
+        r#"{"name": "Globex", "command": "client add Globex --note 'Big'"}"#,
+    );
+    assert_eq!(
+        journal::undo(tracker.connection()).unwrap(),
+        "client add Globex --note 'Big'"
+    );
+    assert_eq!(handle(&tracker, "POST", "/clients", "{").0, 400);
+    assert_eq!(handle(&tracker, "GET", "/nowhere", "").0, 404);
+
+    // A locked period is in the way, not a server error
+    crate::lock::set_lock(tracker.connection(), "2025-07-31".parse().unwrap()).unwrap();
+    assert_eq!(handle(&tracker, "POST", "/sessions/stop", "").0, 409);
+}
+
+#[test]
+fn test_browsers_are_kept_out() {
+    let json = Some("application/json; charset=utf-8");
+    for host in ["127.0.0.1:7777", "localhost", "LOCALHOST:80", "[::1]:7777"] {
+        assert_eq!(check_headers("POST", Some(host), None, json), None);
+    }
+    for origin in [
+        "http://localhost:7777",
+        "http://127.0.0.1",
+        "http://[::1]:7777",
+    ] {
+        assert_eq!(check_headers("POST", None, Some(origin), json), None);
+    }
+
+    // DNS rebinding names the attacker's host, a cross-site request carries its origin
+    for host in [
+        "evil.example:7777",
+        "localhost.evil.example",
+        "192.168.1.5:7777",
+    ] {
+        assert_eq!(check_headers("GET", Some(host), None, None).unwrap().0, 403);
+    }
+    for origin in [
+        "https://evil.example",
+        "null",
+        "http://localhost.evil.example",
+    ] {
+        assert_eq!(
+            check_headers("GET", Some("localhost:7777"), Some(origin), None)
+                .unwrap()
+                .0,
+            403
+        );
+    }
+
+    // Pages can send forms and plain text without a preflight, but not JSON
+    assert_eq!(check_headers("GET", None, None, None), None);
+    for content_type in [
+        None,
+        Some("text/plain"),
+        Some("application/x-www-form-urlencoded"),
+    ] {
+        assert_eq!(
+            check_headers("POST", Some("localhost"), None, content_type)
+                .unwrap()
+                .0,
+            415
+        );
+    }
+}
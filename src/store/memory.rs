@@ -0,0 +1,303 @@
+use std::{cell::RefCell, collections::BTreeMap};
+
+use chrono::{DateTime, Utc};
+
+use super::Store;
+use crate::{
+    error::{Error, Result},
+    models::{Client, Session},
+};
+
+#[derive(Clone, Default)]
+struct State {
+    clients: BTreeMap<i32, Client>,
+    sessions: BTreeMap<i32, Session>,
+    last_client_id: i32,
+    last_session_id: i32,
+}
+
+/// A store that only lives as long as the value, for tests and throwaway tracking
+#[derive(Default)]
+pub struct MemoryStore {
+    state: RefCell<State>,
+    /// The state at each open `begin`, restored by `rollback`
+    saved: RefCell<Vec<State>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    /// Add a client keeping its id, without any checks, for loading stores that assign ids
+    /// themselves
+    pub(crate) fn load_client(&self, client: Client) {
+        let mut state = self.state.borrow_mut();
+        state.last_client_id = state.last_client_id.max(client.id);
+        state.clients.insert(client.id, client);
+    }
+
+    /// Add a session keeping its id, see [`MemoryStore::load_client`]
+    pub(crate) fn load_session(&self, session: Session) {
+        let mut state = self.state.borrow_mut();
+        state.last_session_id = state.last_session_id.max(session.id);
+        state.sessions.insert(session.id, session);
+    }
+}
+
+fn not_found(what: &str, id: i32) -> Error {
+    Error::NotFound(format!("No {what} with id {id} was found"))
+}
+
+/// Sessions must belong to a client, as SQLite's foreign key insists
+fn check_client(state: &State, session: &Session) -> Result<()> {
+    if !state.clients.contains_key(&session.client_id) {
+        return Err(not_found("client", session.client_id));
+    }
+    Ok(())
+}
+
+impl Store for MemoryStore {
+    fn list_clients(&self) -> Result<Vec<Client>> {
+        Ok(self.state.borrow().clients.values().cloned().collect())
+    }
+
+    fn get_client(&self, id: i32) -> Result<Client> {
+        self.state
+            .borrow()
+            .clients
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| not_found("client", id))
+    }
+
+    fn add_client(&self, client: &Client) -> Result<i32> {
+        let mut state = self.state.borrow_mut();
+        if state.clients.values().any(|c| c.name == client.name) {
+            return Err(Error::Conflict(format!(
+                "A client named '{}' already exists!",
+                client.name
+            )));
+        }
+        state.last_client_id += 1;
+        let id = state.last_client_id;
+        state.clients.insert(
+            id,
+            Client {
+                id,
+                ..client.clone()
+            },
+        );
+        Ok(id)
+    }
+
+    fn update_client(&self, client: &Client) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state
+            .clients
+            .values()
+            .any(|c| c.id != client.id && c.name == client.name)
+        {
+            return Err(Error::Conflict(format!(
+                "A client named '{}' already exists!",
+                client.name
+            )));
+        }
+        let stored = state
+            .clients
+            .get_mut(&client.id)
+            .ok_or_else(|| not_found("client", client.id))?;
+        *stored = client.clone();
+        Ok(())
+    }
+
+    fn insert_client(&self, client: &Client) -> Result<()> {
+        let state = self.state.borrow();
+        if state.clients.contains_key(&client.id) {
+            return Err(Error::Conflict(format!(
+                "A client with id {} already exists!",
+                client.id
+            )));
+        }
+        if state.clients.values().any(|c| c.name == client.name) {
+            return Err(Error::Conflict(format!(
+                "A client named '{}' already exists!",
+                client.name
+            )));
+        }
+        drop(state);
+        self.load_client(client.clone());
+        Ok(())
+    }
+
+    fn remove_client(&self, id: i32) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.sessions.values().any(|s| s.client_id == id) {
+            return Err(Error::Conflict(format!(
+                "Client {id} is referenced in some of your sessions"
+            )));
+        }
+        state
+            .clients
+            .remove(&id)
+            .map(|_| ())
+            .ok_or_else(|| not_found("client", id))
+    }
+
+    fn get_session(&self, id: i32) -> Result<Session> {
+        self.state
+            .borrow()
+            .sessions
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| not_found("session", id))
+    }
+
+    fn add_session(&self, session: &Session) -> Result<i32> {
+        let mut state = self.state.borrow_mut();
+        check_client(&state, session)?;
+        state.last_session_id += 1;
+        let id = state.last_session_id;
+        state.sessions.insert(
+            id,
+            Session {
+                id,
+                ..session.clone()
+            },
+        );
+        Ok(id)
+    }
+
+    fn insert_session(&self, session: &Session) -> Result<()> {
+        let state = self.state.borrow();
+        check_client(&state, session)?;
+        if state.sessions.contains_key(&session.id) {
+            return Err(Error::Conflict(format!(
+                "A session with id {} already exists!",
+                session.id
+            )));
+        }
+        drop(state);
+        self.load_session(session.clone());
+        Ok(())
+    }
+
+    fn update_session(&self, session: &Session) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        check_client(&state, session)?;
+        let stored = state
+            .sessions
+            .get_mut(&session.id)
+            .ok_or_else(|| not_found("session", session.id))?;
+        *stored = session.clone();
+        Ok(())
+    }
+
+    fn remove_session(&self, id: i32) -> Result<()> {
+        self.state
+            .borrow_mut()
+            .sessions
+            .remove(&id)
+            .map(|_| ())
+            .ok_or_else(|| not_found("session", id))
+    }
+
+    fn list_sessions(&self, client_id: Option<i32>) -> Result<Vec<Session>> {
+        let mut sessions: Vec<Session> = self
+            .state
+            .borrow()
+            .sessions
+            .values()
+            .filter(|s| client_id.is_none_or(|id| s.client_id == id))
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.start_timestamp));
+        Ok(sessions)
+    }
+
+    fn sessions_within_range(
+        &self,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<Session>> {
+        let mut sessions: Vec<Session> = self
+            .state
+            .borrow()
+            .sessions
+            .values()
+            .filter(|s| s.start_timestamp >= *start && s.start_timestamp <= *end)
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|s| s.start_timestamp);
+        Ok(sessions)
+    }
+
+    fn active_session(&self) -> Result<Option<Session>> {
+        Ok(self
+            .state
+            .borrow()
+            .sessions
+            .values()
+            .filter(|s| s.end_timestamp.is_none())
+            .max_by_key(|s| s.start_timestamp)
+            .cloned())
+    }
+
+    fn latest_session_end(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(self
+            .state
+            .borrow()
+            .sessions
+            .values()
+            .filter_map(|s| s.end_timestamp)
+            .max())
+    }
+
+    fn begin(&self) -> Result<()> {
+        let snapshot = self.state.borrow().clone();
+        self.saved.borrow_mut().push(snapshot);
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<()> {
+        self.saved.borrow_mut().pop();
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<()> {
+        if let Some(snapshot) = self.saved.borrow_mut().pop() {
+            *self.state.borrow_mut() = snapshot;
+        }
+        Ok(())
+    }
+}
+
+// TESTS
+
+#[test]
+fn test_rollback_restores_state() {
+    use super::atomically;
+
+    let store = MemoryStore::new();
+    let client = Client {
+        id: 0,
+        name: "Alice".into(),
+        note: None,
+    };
+    let client_id = store.add_client(&client).unwrap();
+    let failed: Result<()> = atomically(&store, || {
+        store.add_session(&Session {
+            id: 0,
+            client_id,
+            start_timestamp: Utc::now(),
+            end_timestamp: None,
+            note: None,
+            offset_minutes: 0,
+        })?;
+        store.add_client(&client).map(|_| ())
+    });
+
+    assert!(matches!(failed, Err(Error::Conflict(_))));
+    assert!(store.list_sessions(None).unwrap().is_empty());
+    assert!(store.active_session().unwrap().is_none());
+}
@@ -0,0 +1,448 @@
+//! Where clients and sessions are kept. The business logic in `commands`, `views` and the GUI
+//! only talks to a [`Store`], so it runs the same against SQLite or anything else.
+
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    db,
+    error::{Error, Result},
+    models::{Client, Session},
+};
+
+pub mod memory;
+pub mod sqlite;
+pub mod text;
+
+pub use memory::MemoryStore;
+pub use text::TextStore;
+
+/// Client and session storage. Lookups, updates and removals of a missing id give
+/// [`Error::NotFound`](crate::Error), as do sessions of a missing client.
+pub trait Store {
+    fn list_clients(&self) -> Result<Vec<Client>>;
+    fn get_client(&self, id: i32) -> Result<Client>;
+    /// Add a client, returning its id. Fails with a conflict if the name is taken.
+    fn add_client(&self, client: &Client) -> Result<i32>;
+    fn update_client(&self, client: &Client) -> Result<()>;
+    /// Remove a client. Fails with a conflict while sessions still refer to it.
+    fn remove_client(&self, id: i32) -> Result<()>;
+    /// Add a client under its own id rather than a new one, for copying stores. Fails with a
+    /// conflict if the id or the name is taken.
+    fn insert_client(&self, client: &Client) -> Result<()>;
+
+    /// Resolve a user-typed client name: the exact name, then ignoring case, then a unique
+    /// prefix. More than one result means the input is ambiguous. Case is only ignored for ASCII
+    /// letters, as SQLite's `NOCASE` and `LIKE` do.
+    fn find_clients_by_name(&self, name: &str) -> Result<Vec<Client>> {
+        let mut clients = self.list_clients()?;
+        clients.sort_by(|a, b| a.name.cmp(&b.name));
+        let lower = name.to_ascii_lowercase();
+        let stages: [&dyn Fn(&Client) -> bool; 3] = [
+            &|c| c.name == name,
+            &|c| c.name.to_ascii_lowercase() == lower,
+            &|c| c.name.to_ascii_lowercase().starts_with(&lower),
+        ];
+        for matches in stages {
+            let found: Vec<Client> = clients.iter().filter(|c| matches(c)).cloned().collect();
+            if !found.is_empty() {
+                return Ok(found);
+            }
+        }
+        Ok(vec![])
+    }
+
+    fn get_session(&self, id: i32) -> Result<Session>;
+    /// Add a session, returning its id
+    fn add_session(&self, session: &Session) -> Result<i32>;
+    /// Add a session under its own id, see [`insert_client`](Store::insert_client)
+    fn insert_session(&self, session: &Session) -> Result<()>;
+    fn update_session(&self, session: &Session) -> Result<()>;
+    fn remove_session(&self, id: i32) -> Result<()>;
+    /// Every session, or only those of `client_id`, newest first
+    fn list_sessions(&self, client_id: Option<i32>) -> Result<Vec<Session>>;
+    /// Sessions starting between `start` and `end` (inclusive), oldest first
+    fn sessions_within_range(
+        &self,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<Session>>;
+    /// The most recently started session that hasn't ended
+    fn active_session(&self) -> Result<Option<Session>>;
+    /// The latest end time of any finished session
+    fn latest_session_end(&self) -> Result<Option<DateTime<Utc>>>;
+
+    /// Sessions that were set aside because they could not be read, as (session id, reason)
+    fn quarantined_sessions(&self) -> Result<Vec<(i32, String)>> {
+        Ok(vec![])
+    }
+    fn discard_quarantined_session(&self, _id: i32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Start a group of changes that [`commit`](Store::commit) keeps and
+    /// [`rollback`](Store::rollback) undoes. Groups may be nested.
+    fn begin(&self) -> Result<()>;
+    fn commit(&self) -> Result<()>;
+    fn rollback(&self) -> Result<()>;
+
+    /// The SQLite database behind this store, for features only it supports (undo, locking,
+    /// aliases and backups)
+    fn as_connection(&self) -> Option<&Connection> {
+        None
+    }
+}
+
+impl<S: Store + ?Sized> Store for Box<S> {
+    fn list_clients(&self) -> Result<Vec<Client>> {
+        (**self).list_clients()
+    }
+    fn get_client(&self, id: i32) -> Result<Client> {
+        (**self).get_client(id)
+    }
+    fn add_client(&self, client: &Client) -> Result<i32> {
+        (**self).add_client(client)
+    }
+    fn update_client(&self, client: &Client) -> Result<()> {
+        (**self).update_client(client)
+    }
+    fn remove_client(&self, id: i32) -> Result<()> {
+        (**self).remove_client(id)
+    }
+    fn insert_client(&self, client: &Client) -> Result<()> {
+        (**self).insert_client(client)
+    }
+    fn find_clients_by_name(&self, name: &str) -> Result<Vec<Client>> {
+        (**self).find_clients_by_name(name)
+    }
+    fn get_session(&self, id: i32) -> Result<Session> {
+        (**self).get_session(id)
+    }
+    fn add_session(&self, session: &Session) -> Result<i32> {
+        (**self).add_session(session)
+    }
+    fn insert_session(&self, session: &Session) -> Result<()> {
+        (**self).insert_session(session)
+    }
+    fn update_session(&self, session: &Session) -> Result<()> {
+        (**self).update_session(session)
+    }
+    fn remove_session(&self, id: i32) -> Result<()> {
+        (**self).remove_session(id)
+    }
+    fn list_sessions(&self, client_id: Option<i32>) -> Result<Vec<Session>> {
+        (**self).list_sessions(client_id)
+    }
+    fn sessions_within_range(
+        &self,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<Session>> {
+        (**self).sessions_within_range(start, end)
+    }
+    fn active_session(&self) -> Result<Option<Session>> {
+        (**self).active_session()
+    }
+    fn latest_session_end(&self) -> Result<Option<DateTime<Utc>>> {
+        (**self).latest_session_end()
+    }
+    fn quarantined_sessions(&self) -> Result<Vec<(i32, String)>> {
+        (**self).quarantined_sessions()
+    }
+    fn discard_quarantined_session(&self, id: i32) -> Result<()> {
+        (**self).discard_quarantined_session(id)
+    }
+    fn begin(&self) -> Result<()> {
+        (**self).begin()
+    }
+    fn commit(&self) -> Result<()> {
+        (**self).commit()
+    }
+    fn rollback(&self) -> Result<()> {
+        (**self).rollback()
+    }
+    fn as_connection(&self) -> Option<&Connection> {
+        (**self).as_connection()
+    }
+}
+
+/// Run `f` so that either all of the changes it makes to `store` are kept, or none are
+pub fn atomically<T>(store: &dyn Store, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    store.begin()?;
+    match f() {
+        Ok(value) => {
+            store.commit()?;
+            Ok(value)
+        }
+        Err(e) => {
+            store.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+/// How a profile's data is stored
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// An SQLite database, which supports every feature
+    #[default]
+    Sqlite,
+    /// A plain-text file with one record per line, for keeping in version control
+    Text,
+}
+
+/// Open the store of `config`, creating it if needed. SQLite databases are migrated and backed up.
+pub fn open(config: &Config) -> Result<Box<dyn Store>> {
+    match config.backend {
+        Backend::Sqlite => Ok(Box::new(db::init_db(config)?)),
+        Backend::Text => Ok(Box::new(TextStore::open(&config.database_path)?)),
+    }
+}
+
+/// Open the store of `config` without migrating or backing anything up, for quick lookups such
+/// as prompts and shell completions. `None` if nothing has been tracked yet.
+pub fn open_read_only(config: &Config) -> Result<Option<Box<dyn Store>>> {
+    if !config.database_path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(match config.backend {
+        Backend::Sqlite => Box::new(db::open_read_only(&config.database_path)?),
+        Backend::Text => Box::new(TextStore::open(&config.database_path)?),
+    }))
+}
+
+/// Open or create a store of `backend` at `path`, without any of the extras of [`open`]
+pub fn open_path(backend: Backend, path: &Path) -> Result<Box<dyn Store>> {
+    match backend {
+        Backend::Sqlite => {
+            let conn = Connection::open(path)?;
+            db::init_schema(&conn)?;
+            db::apply_migrations(&conn)?;
+            Ok(Box::new(conn))
+        }
+        Backend::Text => Ok(Box::new(TextStore::open(path)?)),
+    }
+}
+
+/// Copy every client and session of `from` into the empty store `to`, keeping their ids, and
+/// return how many of each were copied
+pub fn copy(from: &dyn Store, to: &dyn Store) -> Result<(usize, usize)> {
+    if !to.list_clients()?.is_empty() || !to.list_sessions(None)?.is_empty() {
+        return Err(Error::Conflict(
+            "Clients and sessions can only be copied into an empty store".into(),
+        ));
+    }
+    let clients = from.list_clients()?;
+    let sessions = from.list_sessions(None)?;
+
+    atomically(to, || {
+        for client in &clients {
+            to.insert_client(client)?;
+        }
+        for session in &sessions {
+            match to.insert_session(session) {
+                Err(Error::NotFound(_)) => {
+                    return Err(Error::Invalid(format!(
+                        "Session {} belongs to a missing client, run `timber doctor` first",
+                        session.id
+                    )));
+                }
+                result => result?,
+            }
+        }
+        Ok((clients.len(), sessions.len()))
+    })
+}
+
+// TESTS
+
+#[test]
+fn test_open_read_only() {
+    let dir = std::env::temp_dir().join(format!("timber-read-only-test-{}", std::process::id()));
+    let config = Config {
+        database_path: dir.join("timber.db"),
+        backup_dir: dir.join("backups"),
+        ..Config::default()
+    };
+    assert!(open_read_only(&config).unwrap().is_none());
+
+    let client = Client {
+        id: 0,
+        name: "Acme".into(),
+        note: None,
+    };
+    open(&config).unwrap().add_client(&client).unwrap();
+    let store = open_read_only(&config).unwrap().unwrap();
+    assert_eq!(store.list_clients().unwrap().len(), 1);
+    assert!(store.add_client(&client).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+fn check_store_parity(store: &dyn Store) {
+    let client = |name: &str| Client {
+        id: 0,
+        name: name.into(),
+        note: None,
+    };
+    let acme = store.add_client(&client("ACME")).unwrap();
+    store.add_client(&client("Émile")).unwrap();
+    let session = Session {
+        id: 0,
+        client_id: acme,
+        start_timestamp: "2025-07-18T09:00:00Z".parse().unwrap(),
+        end_timestamp: None,
+        note: None,
+        offset_minutes: 0,
+    };
+    let session_id = store.add_session(&session).unwrap();
+
+    // Sessions need a client, and missing ids aren't skipped over quietly
+    let orphan = Session {
+        client_id: 99,
+        ..session.clone()
+    };
+    assert!(matches!(
+        store.add_session(&orphan),
+        Err(Error::NotFound(_))
+    ));
+    let moved = Session {
+        id: session_id,
+        ..orphan.clone()
+    };
+    assert!(matches!(
+        store.update_session(&moved),
+        Err(Error::NotFound(_))
+    ));
+    let missing = Session {
+        id: 99,
+        ..session.clone()
+    };
+    assert!(matches!(
+        store.update_session(&missing),
+        Err(Error::NotFound(_))
+    ));
+    assert!(matches!(store.remove_session(99), Err(Error::NotFound(_))));
+    let renamed = Client {
+        id: 99,
+        ..client("Initech")
+    };
+    assert!(matches!(
+        store.update_client(&renamed),
+        Err(Error::NotFound(_))
+    ));
+    assert!(matches!(store.remove_client(99), Err(Error::NotFound(_))));
+    assert!(matches!(
+        store.insert_session(&orphan),
+        Err(Error::NotFound(_))
+    ));
+    let taken = Client {
+        id: acme,
+        ..client("Initech")
+    };
+    assert!(matches!(
+        store.insert_client(&taken),
+        Err(Error::Conflict(_))
+    ));
+    let stored = Session {
+        id: session_id,
+        ..session
+    };
+    assert!(matches!(
+        store.insert_session(&stored),
+        Err(Error::Conflict(_))
+    ));
+    store.remove_session(session_id).unwrap();
+    store.remove_client(acme).unwrap();
+
+    // Case is only folded for ASCII letters
+    store.add_client(&client("Acme")).unwrap();
+    assert_eq!(store.find_clients_by_name("acme").unwrap()[0].name, "Acme");
+    assert_eq!(
+        store.find_clients_by_name("ÉMILE").unwrap()[0].name,
+        "Émile"
+    );
+    assert!(store.find_clients_by_name("émile").unwrap().is_empty());
+    assert!(store.find_clients_by_name("ém").unwrap().is_empty());
+}
+
+#[test]
+fn test_stores_agree() {
+    let conn = Connection::open_in_memory().unwrap();
+    db::init_schema(&conn).unwrap();
+    db::apply_migrations(&conn).unwrap();
+    check_store_parity(&conn);
+    check_store_parity(&MemoryStore::new());
+
+    let path = std::env::temp_dir().join(format!("timber-parity-test-{}.txt", std::process::id()));
+    check_store_parity(&TextStore::open(&path).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_copy_keeps_ids() {
+    let from = MemoryStore::new();
+    for name in ["Acme", "Gone", "Globex"] {
+        from.add_client(&Client {
+            id: 0,
+            name: name.into(),
+            note: None,
+        })
+        .unwrap();
+    }
+    from.remove_client(2).unwrap();
+    for client_id in [3, 1, 3] {
+        from.add_session(&Session {
+            id: 0,
+            client_id,
+            start_timestamp: "2025-07-18T09:00:00Z".parse().unwrap(),
+            end_timestamp: None,
+            note: None,
+            offset_minutes: 0,
+        })
+        .unwrap();
+    }
+    from.remove_session(2).unwrap();
+
+    let conn = Connection::open_in_memory().unwrap();
+    db::init_schema(&conn).unwrap();
+    db::apply_migrations(&conn).unwrap();
+    assert_eq!(copy(&from, &conn).unwrap(), (2, 2));
+    let ids = |store: &dyn Store| {
+        let mut sessions: Vec<(i32, i32)> = store
+            .list_sessions(None)
+            .unwrap()
+            .iter()
+            .map(|s| (s.id, s.client_id))
+            .collect();
+        sessions.sort();
+        (store.get_client(3).unwrap().name, sessions)
+    };
+    assert_eq!(ids(&conn), ("Globex".to_string(), vec![(1, 3), (3, 3)]));
+    assert!(matches!(copy(&from, &conn), Err(Error::Conflict(_))));
+
+    // New ids carry on after the copied ones
+    let initech = Client {
+        id: 0,
+        name: "Initech".into(),
+        note: None,
+    };
+    assert_eq!(conn.add_client(&initech).unwrap(), 4);
+    let back = MemoryStore::new();
+    copy(&conn, &back).unwrap();
+    assert_eq!(ids(&back), ids(&conn));
+    assert_eq!(
+        back.add_client(&Client {
+            id: 0,
+            name: "Hooli".into(),
+            note: None
+        })
+        .unwrap(),
+        5
+    );
+}
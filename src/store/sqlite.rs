@@ -0,0 +1,187 @@
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+
+use super::Store;
+use crate::{
+    db,
+    error::{Error, Result},
+    models::{Client, Session},
+};
+
+/// Turn a missing row into a `NotFound` naming what was looked up
+fn found<T>(result: rusqlite::Result<T>, what: &str, id: i32) -> Result<T> {
+    match result {
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            Err(Error::NotFound(format!("No {what} with id {id} was found")))
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Turn a statement that changed nothing into a `NotFound`, like [`found`] does for lookups
+fn changed(conn: &Connection, what: &str, id: i32) -> Result<()> {
+    if conn.changes() == 0 {
+        return Err(Error::NotFound(format!("No {what} with id {id} was found")));
+    }
+    Ok(())
+}
+
+/// Turn a session refused by the foreign key on its client into a `NotFound`
+fn client_found<T>(result: rusqlite::Result<T>, session: &Session) -> Result<T> {
+    match result {
+        Err(rusqlite::Error::SqliteFailure(err, _))
+            if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY =>
+        {
+            Err(Error::NotFound(format!(
+                "No client with id {} was found",
+                session.client_id
+            )))
+        }
+        result => Ok(result?),
+    }
+}
+
+impl Store for Connection {
+    fn list_clients(&self) -> Result<Vec<Client>> {
+        Ok(db::list_clients(self)?)
+    }
+
+    fn get_client(&self, id: i32) -> Result<Client> {
+        found(db::get_client_by_id(self, id), "client", id)
+    }
+
+    fn add_client(&self, client: &Client) -> Result<i32> {
+        db::store_client(self, client)?.ok_or_else(|| {
+            Error::Conflict(format!("A client named '{}' already exists!", client.name))
+        })
+    }
+
+    fn update_client(&self, client: &Client) -> Result<()> {
+        match db::commit_client_changes(self, client) {
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Err(Error::Conflict(format!(
+                    "A client named '{}' already exists!",
+                    client.name
+                )))
+            }
+            result => {
+                result?;
+                changed(self, "client", client.id)
+            }
+        }
+    }
+
+    fn remove_client(&self, id: i32) -> Result<()> {
+        match db::remove_client(self, id) {
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Err(Error::Conflict(format!(
+                    "Client {id} is referenced in some of your sessions"
+                )))
+            }
+            result => {
+                result?;
+                changed(self, "client", id)
+            }
+        }
+    }
+
+    fn insert_client(&self, client: &Client) -> Result<()> {
+        match db::insert_client(self, client) {
+            Err(rusqlite::Error::SqliteFailure(err, _))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Err(Error::Conflict(format!(
+                    "A client with id {} or named '{}' already exists!",
+                    client.id, client.name
+                )))
+            }
+            result => Ok(result?),
+        }
+    }
+
+    // Aliases are only kept in SQLite
+    fn find_clients_by_name(&self, name: &str) -> Result<Vec<Client>> {
+        Ok(db::find_clients_by_name(self, name)?)
+    }
+
+    fn get_session(&self, id: i32) -> Result<Session> {
+        found(db::get_session_by_id(self, id), "session", id)
+    }
+
+    fn add_session(&self, session: &Session) -> Result<i32> {
+        client_found(db::store_session(self, session), session)
+    }
+
+    fn insert_session(&self, session: &Session) -> Result<()> {
+        match client_found(db::insert_session(self, session), session) {
+            Err(Error::Db(rusqlite::Error::SqliteFailure(err, _)))
+                if err.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Err(Error::Conflict(format!(
+                    "A session with id {} already exists!",
+                    session.id
+                )))
+            }
+            result => result,
+        }
+    }
+
+    fn update_session(&self, session: &Session) -> Result<()> {
+        client_found(db::commit_session_changes(self, session), session)?;
+        changed(self, "session", session.id)
+    }
+
+    fn remove_session(&self, id: i32) -> Result<()> {
+        db::remove_session(self, id)?;
+        changed(self, "session", id)
+    }
+
+    fn list_sessions(&self, client_id: Option<i32>) -> Result<Vec<Session>> {
+        Ok(db::list_sessions(self, client_id)?)
+    }
+
+    fn sessions_within_range(
+        &self,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<Session>> {
+        Ok(db::get_sessions_within_range(self, start, end)?)
+    }
+
+    fn active_session(&self) -> Result<Option<Session>> {
+        Ok(db::get_active_session(self)?)
+    }
+
+    fn latest_session_end(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(db::get_latest_session_end(self)?)
+    }
+
+    fn quarantined_sessions(&self) -> Result<Vec<(i32, String)>> {
+        Ok(db::list_quarantined_sessions(self)?)
+    }
+
+    fn discard_quarantined_session(&self, id: i32) -> Result<()> {
+        Ok(db::remove_quarantined_session(self, id)?)
+    }
+
+    // Savepoints rather than transactions, so groups can be nested
+    fn begin(&self) -> Result<()> {
+        Ok(self.execute_batch("SAVEPOINT store")?)
+    }
+
+    fn commit(&self) -> Result<()> {
+        Ok(self.execute_batch("RELEASE store")?)
+    }
+
+    fn rollback(&self) -> Result<()> {
+        Ok(self.execute_batch("ROLLBACK TO store; RELEASE store")?)
+    }
+
+    fn as_connection(&self) -> Option<&Connection> {
+        Some(self)
+    }
+}
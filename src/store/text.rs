@@ -0,0 +1,367 @@
+//! A plain-text store meant for version control. Every client and session is one line of
+//! tab-separated fields, written in a stable order so that diffs only show what changed:
+//!
+//! ```text
+//! client  1  Acme  Their note
+//! session  1  1  2025-07-18T09:00:00Z  2025-07-18T12:30:00Z  0  Planning
+//! session  2  1  2025-07-18T13:00:00Z  -  15
+//! ```
+//!
+//! Fields are separated by tabs, shown as spaces above. Sessions are fields of id, client id,
+//! start, end (`-` while running), offset minutes and note. Tabs, newlines and backslashes inside
+//! names and notes are escaped as `\t`, `\n` and `\\`.
+
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use super::{MemoryStore, Store};
+use crate::{
+    error::{Error, Result},
+    models::{Client, Session},
+};
+
+const HEADER: &str = "# Timber time log. Fields are separated by tabs, see `timber convert --help`";
+
+pub struct TextStore {
+    path: PathBuf,
+    records: MemoryStore,
+    /// Lines that could not be read with the reason, written back untouched so nothing is lost
+    unreadable: RefCell<Vec<(String, String)>>,
+    /// How many groups of changes are open, the file is only written once all are committed
+    depth: Cell<usize>,
+}
+
+impl TextStore {
+    /// Read the log at `path`, a missing file is created on the first change
+    pub fn open(path: &Path) -> Result<Self> {
+        let store = TextStore {
+            path: path.to_path_buf(),
+            records: MemoryStore::new(),
+            unreadable: RefCell::new(vec![]),
+            depth: Cell::new(0),
+        };
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                return Err(Error::Config(format!(
+                    "{} is not a plain-text log, check the backend in `timber config show`",
+                    path.display()
+                )));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Err(reason) = store.read_line(line) {
+                store
+                    .unreadable
+                    .borrow_mut()
+                    .push((line.to_string(), format!("line {}: {reason}", number + 1)));
+            }
+        }
+        Ok(store)
+    }
+
+    fn read_line(&self, line: &str) -> std::result::Result<(), String> {
+        let fields: Vec<String> = line.split('\t').map(unescape).collect();
+        let field = |i: usize| fields.get(i).map(String::as_str).unwrap_or_default();
+        let id = |i: usize| {
+            field(i)
+                .parse::<i32>()
+                .map_err(|_| format!("'{}' is not an id", field(i)))
+        };
+        let note = |i: usize| Some(field(i).to_string()).filter(|note| !note.is_empty());
+
+        match field(0) {
+            "client" => self.records.load_client(Client {
+                id: id(1)?,
+                name: field(2).to_string(),
+                note: note(3),
+            }),
+            "session" => self.records.load_session(Session {
+                id: id(1)?,
+                client_id: id(2)?,
+                start_timestamp: parse_timestamp(field(3))?,
+                end_timestamp: match field(4) {
+                    "-" => None,
+                    end => Some(parse_timestamp(end)?),
+                },
+                offset_minutes: field(5)
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a number of minutes", field(5)))?,
+                note: note(6),
+            }),
+            kind => return Err(format!("unknown record '{kind}'")),
+        }
+        Ok(())
+    }
+
+    fn render(&self) -> Result<String> {
+        let mut out = format!("{HEADER}\n");
+        let mut clients = self.records.list_clients()?;
+        clients.sort_by_key(|c| c.id);
+        for client in clients {
+            let note = client.note.as_deref().unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "client\t{}\t{}\t{}",
+                client.id,
+                escape(&client.name),
+                escape(note)
+            );
+        }
+        let mut sessions = self.records.list_sessions(None)?;
+        sessions.sort_by_key(|s| (s.start_timestamp, s.id));
+        for session in sessions {
+            let end = session
+                .end_timestamp
+                .map(format_timestamp)
+                .unwrap_or_else(|| "-".into());
+            let note = session.note.as_deref().unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "session\t{}\t{}\t{}\t{end}\t{}\t{}",
+                session.id,
+                session.client_id,
+                format_timestamp(session.start_timestamp),
+                session.offset_minutes,
+                escape(note)
+            );
+        }
+        for (line, _) in self.unreadable.borrow().iter() {
+            let _ = writeln!(out, "{line}");
+        }
+        Ok(out)
+    }
+
+    /// Write the file, unless the change is part of a group that is still open
+    fn changed(&self) -> Result<()> {
+        if self.depth.get() > 0 {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Write beside the log and swap it in, so a crash never leaves half a file
+        let temp = self.path.with_extension("tmp");
+        std::fs::write(&temp, self.render()?)?;
+        std::fs::rename(&temp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn parse_timestamp(field: &str) -> std::result::Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(field)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|_| format!("'{field}' is not a timestamp"))
+}
+
+pub(crate) fn escape(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+pub(crate) fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+impl Store for TextStore {
+    fn list_clients(&self) -> Result<Vec<Client>> {
+        self.records.list_clients()
+    }
+
+    fn get_client(&self, id: i32) -> Result<Client> {
+        self.records.get_client(id)
+    }
+
+    fn add_client(&self, client: &Client) -> Result<i32> {
+        let id = self.records.add_client(client)?;
+        self.changed()?;
+        Ok(id)
+    }
+
+    fn update_client(&self, client: &Client) -> Result<()> {
+        self.records.update_client(client)?;
+        self.changed()
+    }
+
+    fn remove_client(&self, id: i32) -> Result<()> {
+        self.records.remove_client(id)?;
+        self.changed()
+    }
+
+    fn insert_client(&self, client: &Client) -> Result<()> {
+        self.records.insert_client(client)?;
+        self.changed()
+    }
+
+    fn get_session(&self, id: i32) -> Result<Session> {
+        self.records.get_session(id)
+    }
+
+    fn add_session(&self, session: &Session) -> Result<i32> {
+        let id = self.records.add_session(session)?;
+        self.changed()?;
+        Ok(id)
+    }
+
+    fn insert_session(&self, session: &Session) -> Result<()> {
+        self.records.insert_session(session)?;
+        self.changed()
+    }
+
+    fn update_session(&self, session: &Session) -> Result<()> {
+        self.records.update_session(session)?;
+        self.changed()
+    }
+
+    fn remove_session(&self, id: i32) -> Result<()> {
+        self.records.remove_session(id)?;
+        self.changed()
+    }
+
+    fn list_sessions(&self, client_id: Option<i32>) -> Result<Vec<Session>> {
+        self.records.list_sessions(client_id)
+    }
+
+    fn sessions_within_range(
+        &self,
+        start: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+    ) -> Result<Vec<Session>> {
+        self.records.sessions_within_range(start, end)
+    }
+
+    fn active_session(&self) -> Result<Option<Session>> {
+        self.records.active_session()
+    }
+
+    fn latest_session_end(&self) -> Result<Option<DateTime<Utc>>> {
+        self.records.latest_session_end()
+    }
+
+    /// Unreadable lines, numbered from 1 in the order they appear
+    fn quarantined_sessions(&self) -> Result<Vec<(i32, String)>> {
+        Ok(self
+            .unreadable
+            .borrow()
+            .iter()
+            .enumerate()
+            .map(|(i, (_, reason))| (i as i32 + 1, reason.clone()))
+            .collect())
+    }
+
+    fn discard_quarantined_session(&self, id: i32) -> Result<()> {
+        let index = usize::try_from(id - 1)
+            .ok()
+            .filter(|&i| i < self.unreadable.borrow().len())
+            .ok_or_else(|| Error::NotFound(format!("No unreadable line {id} was found")))?;
+        self.unreadable.borrow_mut().remove(index);
+        self.changed()
+    }
+
+    fn begin(&self) -> Result<()> {
+        self.depth.set(self.depth.get() + 1);
+        self.records.begin()
+    }
+
+    fn commit(&self) -> Result<()> {
+        self.records.commit()?;
+        self.depth.set(self.depth.get().saturating_sub(1));
+        self.changed()
+    }
+
+    fn rollback(&self) -> Result<()> {
+        self.records.rollback()?;
+        self.depth.set(self.depth.get().saturating_sub(1));
+        Ok(())
+    }
+}
+
+// TESTS
+
+#[test]
+fn test_text_store_round_trip() {
+    let dir = std::env::temp_dir().join(format!("timber-text-test-{}", std::process::id()));
+    let path = dir.join("timber.log");
+    let store = TextStore::open(&path).unwrap();
+    let client_id = store
+        .add_client(&Client {
+            id: 0,
+            name: "Acme\tLtd".into(),
+            note: Some("two\nlines".into()),
+        })
+        .unwrap();
+    let session = |start: &str, end: Option<&str>| Session {
+        id: 0,
+        client_id,
+        start_timestamp: start.parse().unwrap(),
+        end_timestamp: end.map(|end| end.parse().unwrap()),
+        note: None,
+        offset_minutes: -5,
+    };
+    // Added out of order, but written by start time
+    store
+        .add_session(&session("2025-07-18T13:00:00Z", None))
+        .unwrap();
+    store
+        .add_session(&session(
+            "2025-07-18T09:00:00Z",
+            Some("2025-07-18T12:30:00Z"),
+        ))
+        .unwrap();
+
+    let mut content = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = content.lines().skip(1).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "client\t1\tAcme\\tLtd\ttwo\\nlines",
+            "session\t2\t1\t2025-07-18T09:00:00Z\t2025-07-18T12:30:00Z\t-5\t",
+            "session\t1\t1\t2025-07-18T13:00:00Z\t-\t-5\t",
+        ]
+    );
+
+    // A bad merge leaves a line behind, which is reported but kept
+    content.push_str("<<<<<<< HEAD\n");
+    std::fs::write(&path, &content).unwrap();
+    let reopened = TextStore::open(&path).unwrap();
+    assert_eq!(reopened.get_client(1).unwrap().name, "Acme\tLtd");
+    assert_eq!(reopened.list_sessions(None).unwrap().len(), 2);
+    assert_eq!(reopened.quarantined_sessions().unwrap().len(), 1);
+    reopened.remove_session(1).unwrap();
+    assert!(
+        std::fs::read_to_string(&path)
+            .unwrap()
+            .ends_with("<<<<<<< HEAD\n")
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
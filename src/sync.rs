@@ -0,0 +1,1119 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, OptionalExtension, params, params_from_iter, types::Value};
+
+use crate::{
+    db,
+    error::Result,
+    store::text::{escape, unescape},
+};
+
+// Machines sync by exchanging change logs through a shared directory. Every client and session
+// has a globally unique `uid`. Once a database has synced, triggers keep the latest value of each
+// of their fields in `sync_fields`, stamped with the time of the change. Each machine appends its
+// own changes to `<machine>.log` in the directory and reads what the others added to theirs since
+// it last looked, keeping whichever value of a field has the latest stamp. Removals are recorded
+// as a `deleted` field, and a row changed after it was removed elsewhere is kept, so a session is
+// never lost. A copy of a database that has synced shares its machine id, so a machine that finds
+// its id used by someone else, in its own log or another, takes a new one.
+
+/// Synced tables with their fields and the columns they are stored in. A session's client is
+/// synced as the client's uid, since local ids differ between machines.
+const SYNCED_TABLES: [(&str, &[(&str, &str)]); 2] = [
+    ("clients", &[("name", "name"), ("note", "note")]),
+    (
+        "sessions",
+        &[
+            ("client", "client_id"),
+            ("start_timestamp", "start_timestamp"),
+            ("end_timestamp", "end_timestamp"),
+            ("note", "note"),
+            ("offset_minutes", "offset_minutes"),
+        ],
+    ),
+];
+
+/// Fields stored as numbers, everything else is text
+const INTEGER_FIELDS: [&str; 4] = [
+    "start_timestamp",
+    "end_timestamp",
+    "offset_minutes",
+    "deleted",
+];
+
+/// Changes are only recorded once a database has synced, and not while this connection applies
+/// changes from other machines
+fn tracking() -> String {
+    format!(
+        "(SELECT value FROM meta WHERE key = 'sync_machine') IS NOT NULL
+        AND {} IS NULL",
+        db::connection_flag("sync_applying")
+    )
+}
+
+pub fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE clients ADD COLUMN uid TEXT;
+        ALTER TABLE sessions ADD COLUMN uid TEXT;
+        UPDATE clients SET uid = lower(hex(randomblob(16)));
+        UPDATE sessions SET uid = lower(hex(randomblob(16)));
+        CREATE UNIQUE INDEX clients_uid ON clients (uid);
+        CREATE UNIQUE INDEX sessions_uid ON sessions (uid);
+
+        CREATE TABLE sync_fields (
+            table_name TEXT NOT NULL,
+            uid TEXT NOT NULL,
+            field TEXT NOT NULL,
+            value,
+            stamp INTEGER NOT NULL,
+            machine TEXT NOT NULL,
+            exported INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (table_name, uid, field)
+        );
+
+        -- Rows created on two machines that turned out to be the same client or session
+        CREATE TABLE sync_aliases (
+            table_name TEXT NOT NULL,
+            uid TEXT NOT NULL,
+            local_uid TEXT NOT NULL,
+            PRIMARY KEY (table_name, uid)
+        );
+
+        -- The latest stamp read from each other machine, anything later is news
+        CREATE TABLE sync_machines (
+            machine TEXT PRIMARY KEY,
+            seen INTEGER NOT NULL
+        );
+
+        -- How many bytes of each log in the directory have been read, or for this machine's own
+        -- log, written
+        CREATE TABLE sync_logs (
+            name TEXT PRIMARY KEY,
+            length INTEGER NOT NULL
+        );",
+    )?;
+    create_triggers(conn)
+}
+
+/// The SQL for the value of `field` in `row`
+fn field_value(field: &str, column: &str, row: &str) -> String {
+    if field == "client" {
+        format!("(SELECT uid FROM clients WHERE id = {row}.{column})")
+    } else {
+        format!("{row}.{column}")
+    }
+}
+
+/// Record `value` as the latest `field` of row `uid`. Stamps are milliseconds, bumped past any
+/// stamp already known for the row so that local changes always supersede what they replace.
+fn record_change(table: &str, uid: &str, field: &str, value: &str, condition: &str) -> String {
+    let tracking = tracking();
+    format!(
+        "INSERT INTO sync_fields (table_name, uid, field, value, stamp, machine)
+        SELECT '{table}', {uid}, '{field}', {value},
+            MAX(
+                CAST(unixepoch('subsec') * 1000 AS INTEGER),
+                COALESCE(
+                    (SELECT MAX(stamp) + 1 FROM sync_fields
+                     WHERE table_name = '{table}' AND uid = {uid}),
+                    0
+                )
+            ),
+            (SELECT value FROM meta WHERE key = 'sync_machine')
+        WHERE {tracking} {condition}
+        ON CONFLICT (table_name, uid, field) DO UPDATE SET
+            value = excluded.value, stamp = excluded.stamp, machine = excluded.machine,
+            exported = 0;"
+    )
+}
+
+/// (Re)create the triggers giving new rows a uid and recording changes, needed whenever a synced
+/// table's columns change
+pub fn create_triggers(conn: &Connection) -> rusqlite::Result<()> {
+    for (table, fields) in SYNCED_TABLES {
+        let new_uid = format!("(SELECT uid FROM {table} WHERE rowid = NEW.rowid)");
+        let inserted: Vec<String> = fields
+            .iter()
+            .map(|(field, column)| {
+                record_change(
+                    table,
+                    &new_uid,
+                    field,
+                    &field_value(field, column, "NEW"),
+                    "",
+                )
+            })
+            .collect();
+        let updated: Vec<String> = fields
+            .iter()
+            .map(|(field, column)| {
+                record_change(
+                    table,
+                    "NEW.uid",
+                    field,
+                    &field_value(field, column, "NEW"),
+                    &format!("AND OLD.{column} IS NOT NEW.{column}"),
+                )
+            })
+            .collect();
+        let deleted = record_change(table, "OLD.uid", "deleted", "1", "");
+
+        conn.execute_batch(&format!(
+            "DROP TRIGGER IF EXISTS sync_{table}_insert;
+            CREATE TRIGGER sync_{table}_insert AFTER INSERT ON {table}
+            BEGIN
+                UPDATE {table} SET uid = lower(hex(randomblob(16)))
+                WHERE rowid = NEW.rowid AND uid IS NULL;
+                {}
+            END;
+
+            DROP TRIGGER IF EXISTS sync_{table}_update;
+            CREATE TRIGGER sync_{table}_update AFTER UPDATE ON {table}
+            BEGIN
+                {}
+            END;
+
+            DROP TRIGGER IF EXISTS sync_{table}_delete;
+            CREATE TRIGGER sync_{table}_delete AFTER DELETE ON {table}
+            BEGIN
+                {deleted}
+            END;",
+            inserted.join("\n"),
+            updated.join("\n"),
+        ))?;
+    }
+    Ok(())
+}
+
+/// What a sync did
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Other machines found in the directory
+    pub machines: usize,
+    /// Changes from other machines that were new here
+    pub imported: usize,
+    /// Changes from this machine written to the directory
+    pub exported: usize,
+    /// Lines of other machines' logs that could not be read
+    pub unreadable: usize,
+    /// The id this machine took after finding another database syncing under its old one
+    pub new_machine: Option<String>,
+    /// Changes made on more than one machine, and how they were settled
+    pub conflicts: Vec<String>,
+}
+
+/// A change read from another machine's log
+struct Change {
+    stamp: i64,
+    machine: String,
+    table: String,
+    uid: String,
+    field: String,
+    value: Value,
+}
+
+impl Change {
+    fn parse(line: &str) -> Option<Change> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [stamp, machine, table, uid, field, value] = fields[..] else {
+            return None;
+        };
+        SYNCED_TABLES.iter().find(|(name, _)| *name == table)?;
+        let value = match unescape(value) {
+            value if value.is_empty() => Value::Null,
+            value if INTEGER_FIELDS.contains(&field) => Value::Integer(value.parse().ok()?),
+            value => Value::Text(value),
+        };
+        Some(Change {
+            stamp: stamp.parse().ok()?,
+            machine: machine.to_string(),
+            table: table.to_string(),
+            uid: uid.to_string(),
+            field: field.to_string(),
+            value,
+        })
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(r) => r.to_string(),
+        Value::Text(text) => escape(text),
+        Value::Blob(_) => String::new(),
+    }
+}
+
+/// Exchange changes with the other machines syncing through `dir`
+pub fn sync(conn: &Connection, dir: &Path) -> Result<SyncReport> {
+    fs::create_dir_all(dir)?;
+    let tx = conn.unchecked_transaction()?;
+    let _audit = db::ConnectionFlag::set(conn, "audit_command", "sync")?;
+    // Changes applied from other machines aren't recorded as this machine's
+    let _applying = db::ConnectionFlag::set(conn, "sync_applying", "1")?;
+    let mut machine = machine_id(&tx)?;
+    let mut report = SyncReport::default();
+
+    // Only this machine writes its log, anything added since is another database's
+    let own_log = format!("{machine}.log");
+    let written = log_length(&tx, &own_log)?;
+    let length = match fs::metadata(dir.join(&own_log)) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == ErrorKind::NotFound => 0,
+        Err(e) => return Err(e.into()),
+    };
+    if length > written {
+        machine = new_machine_id(&tx, &machine)?;
+        report.new_machine = Some(machine.clone());
+    } else if length < written {
+        // The log was lost, write everything again
+        tx.execute(
+            "UPDATE sync_fields SET exported = 0 WHERE machine = ?1",
+            [&machine],
+        )?;
+    }
+
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .filter(|path| path.file_stem().is_none_or(|stem| stem != machine.as_str()))
+        .collect();
+    paths.sort();
+    report.machines = paths.len();
+    let mut read = vec![];
+    for path in &paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let (lines, length) = read_log(path, log_length(&tx, &name)?)?;
+        set_log_length(&tx, &name, length)?;
+        for line in lines.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Change::parse(line) {
+                Some(change) => read.push(change),
+                None => report.unreadable += 1,
+            }
+        }
+    }
+    if report.new_machine.is_none() && read.iter().any(|change| change.machine == machine) {
+        machine = new_machine_id(&tx, &machine)?;
+        report.new_machine = Some(machine.clone());
+    }
+
+    // Only the latest change to each field matters, logs keep every change ever made
+    let mut changes: HashMap<(String, String, String), Change> = HashMap::new();
+    for change in read {
+        let key = (
+            change.table.clone(),
+            change.uid.clone(),
+            change.field.clone(),
+        );
+        if changes
+            .get(&key)
+            .is_none_or(|known| (change.stamp, &change.machine) > (known.stamp, &known.machine))
+        {
+            changes.insert(key, change);
+        }
+    }
+    let mut changes: Vec<Change> = changes.into_values().collect();
+    changes.sort_by(|a, b| (a.stamp, &a.machine).cmp(&(b.stamp, &b.machine)));
+
+    let mut merge = Merge {
+        conn: &tx,
+        conflicts: vec![],
+        touched: BTreeSet::new(),
+        removed: BTreeSet::new(),
+        removals: vec![],
+    };
+    for change in &changes {
+        if merge.import(change)? {
+            report.imported += 1;
+        }
+    }
+    merge.apply()?;
+    report.conflicts = merge.conflicts;
+    for change in &changes {
+        tx.execute(
+            "INSERT INTO sync_machines (machine, seen) VALUES (?1, ?2)
+             ON CONFLICT (machine) DO UPDATE SET seen = MAX(seen, excluded.seen)",
+            params![change.machine, change.stamp],
+        )?;
+    }
+
+    report.exported = export(&tx, dir, &machine)?;
+    tx.commit()?;
+    Ok(report)
+}
+
+/// This machine's id, created on the first sync along with a record of everything there is
+fn machine_id(conn: &Connection) -> rusqlite::Result<String> {
+    let existing: Option<String> = conn
+        .query_row(
+            "SELECT value FROM meta WHERE key = 'sync_machine'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(machine) = existing {
+        return Ok(machine);
+    }
+
+    let machine: String =
+        conn.query_row("SELECT lower(hex(randomblob(8)))", [], |row| row.get(0))?;
+    conn.execute(
+        "INSERT INTO meta (key, value) VALUES ('sync_machine', ?1)",
+        [&machine],
+    )?;
+    for (table, fields) in SYNCED_TABLES {
+        for (field, column) in fields {
+            conn.execute(
+                &format!(
+                    "INSERT OR IGNORE INTO sync_fields (table_name, uid, field, value, stamp, machine)
+                    SELECT '{table}', r.uid, '{field}', {},
+                        CAST(unixepoch('subsec') * 1000 AS INTEGER), ?1
+                    FROM {table} r",
+                    field_value(field, column, "r")
+                ),
+                [&machine],
+            )?;
+        }
+    }
+    Ok(machine)
+}
+
+/// Give this machine a new id after finding another database, e.g. a copy of this one, syncing
+/// under the current one. Changes that haven't been shared yet go out under the new id.
+fn new_machine_id(conn: &Connection, old: &str) -> rusqlite::Result<String> {
+    let machine: String =
+        conn.query_row("SELECT lower(hex(randomblob(8)))", [], |row| row.get(0))?;
+    conn.execute(
+        "UPDATE meta SET value = ?1 WHERE key = 'sync_machine'",
+        [&machine],
+    )?;
+    conn.execute(
+        "UPDATE sync_fields SET machine = ?1 WHERE machine = ?2 AND exported = 0",
+        [&machine, old],
+    )?;
+    Ok(machine)
+}
+
+fn log_length(conn: &Connection, name: &str) -> rusqlite::Result<u64> {
+    Ok(conn
+        .query_row(
+            "SELECT length FROM sync_logs WHERE name = ?1",
+            [name],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()?
+        .unwrap_or(0) as u64)
+}
+
+fn set_log_length(conn: &Connection, name: &str, length: u64) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO sync_logs (name, length) VALUES (?1, ?2)
+         ON CONFLICT (name) DO UPDATE SET length = excluded.length",
+        params![name, length as i64],
+    )?;
+    Ok(())
+}
+
+/// The complete lines of the log at `path` after its first `from` bytes, and the length read up
+/// to. A log shorter than `from` was replaced and is read from the start.
+fn read_log(path: &Path, from: u64) -> Result<(String, u64)> {
+    let mut file = fs::File::open(path)?;
+    let from = if file.metadata()?.len() < from {
+        0
+    } else {
+        from
+    };
+    file.seek(SeekFrom::Start(from))?;
+    let mut bytes = vec![];
+    file.read_to_end(&mut bytes)?;
+    // A last line without its newline is still being written or copied, it is read next time
+    let complete = bytes.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    bytes.truncate(complete);
+    Ok((
+        String::from_utf8_lossy(&bytes).into_owned(),
+        from + complete as u64,
+    ))
+}
+
+/// Append this machine's unexported changes to its log, returning how many were written
+fn export(conn: &Connection, dir: &Path, machine: &str) -> Result<usize> {
+    let mut stmt = conn.prepare(
+        "SELECT stamp, table_name, uid, field, value FROM sync_fields
+         WHERE machine = ?1 AND exported = 0
+         ORDER BY stamp, table_name, uid, field",
+    )?;
+    let lines = stmt
+        .query_map([machine], |row| {
+            Ok(format!(
+                "{}\t{machine}\t{}\t{}\t{}\t{}\n",
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                format_value(&row.get(4)?)
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if lines.is_empty() {
+        return Ok(0);
+    }
+
+    let path = dir.join(format!("{machine}.log"));
+    let new = !path.exists();
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+    if new {
+        writeln!(
+            file,
+            "# Changes made by Timber on machine {machine}, see `timber sync --help`"
+        )?;
+    }
+    file.write_all(lines.concat().as_bytes())?;
+    file.sync_all()?;
+    set_log_length(conn, &format!("{machine}.log"), file.metadata()?.len())?;
+
+    conn.execute(
+        "UPDATE sync_fields SET exported = 1 WHERE machine = ?1 AND exported = 0",
+        [machine],
+    )?;
+    Ok(lines.len())
+}
+
+/// The latest known value of a field, with its stamp and the machine it came from
+type Latest = (Value, i64, String);
+
+/// Brings imported changes into the local tables
+struct Merge<'a> {
+    conn: &'a Connection,
+    conflicts: Vec<String>,
+    /// Rows with new changes, as (table, uid)
+    touched: BTreeSet<(String, String)>,
+    /// Rows another machine removed in this sync
+    removed: BTreeSet<(String, String)>,
+    /// Clients to remove once sessions have moved off them
+    removals: Vec<(i32, String)>,
+}
+
+impl Merge<'_> {
+    /// Keep `change` if it is newer than what is known, returning whether it was
+    fn import(&mut self, change: &Change) -> Result<bool> {
+        let local: Option<(Value, i64, String, bool)> = self
+            .conn
+            .query_row(
+                "SELECT value, stamp, machine, exported FROM sync_fields
+                 WHERE table_name = ?1 AND uid = ?2 AND field = ?3",
+                params![change.table, change.uid, change.field],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()?;
+        let newer = match &local {
+            Some((_, stamp, machine, _)) => (change.stamp, &change.machine) > (*stamp, machine),
+            None => true,
+        };
+        // A conflict is a change there that is news here, to a field changed here that hasn't
+        // been shared yet, so neither machine knew of the other's change
+        let seen: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT seen FROM sync_machines WHERE machine = ?1",
+                [&change.machine],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some((value, _, _, false)) = &local
+            && *value != change.value
+            && seen.is_none_or(|seen| change.stamp > seen)
+        {
+            let what = self.describe(&change.table, &change.uid)?;
+            let label = field_label(&change.field);
+            let here = self.show(&change.field, value)?;
+            let there = self.show(&change.field, &change.value)?;
+            self.conflicts.push(if newer {
+                format!(
+                    "{what}: took the {label} {there} from machine {} over {here} from this machine",
+                    change.machine
+                )
+            } else {
+                format!(
+                    "{what}: kept the {label} {here} from this machine over {there} from machine {}",
+                    change.machine
+                )
+            });
+        }
+        if !newer {
+            return Ok(false);
+        }
+
+        self.conn.execute(
+            "INSERT OR REPLACE INTO sync_fields
+                (table_name, uid, field, value, stamp, machine, exported)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, 1)",
+            params![
+                change.table,
+                change.uid,
+                change.field,
+                change.value,
+                change.stamp,
+                change.machine
+            ],
+        )?;
+        let row = (change.table.clone(), change.uid.clone());
+        if change.field == "deleted" {
+            self.removed.insert(row.clone());
+        }
+        self.touched.insert(row);
+        Ok(true)
+    }
+
+    fn apply(&mut self) -> Result<()> {
+        // Clients first, so the sessions referring to them can find them
+        let touched = std::mem::take(&mut self.touched);
+        for table in ["clients", "sessions"] {
+            for (_, uid) in touched.iter().filter(|(t, _)| t == table) {
+                self.materialize(table, uid, false)?;
+            }
+        }
+        for (id, name) in std::mem::take(&mut self.removals) {
+            let used: bool = self.conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM sessions WHERE client_id = ?1)",
+                [id],
+                |row| row.get(0),
+            )?;
+            if used {
+                self.conflicts.push(format!(
+                    "Client '{name}' was removed on another machine, but kept as sessions here still use it"
+                ));
+            } else {
+                self.conn
+                    .execute("DELETE FROM clients WHERE id = ?1", [id])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The uid of the local row standing in for `uid`
+    fn local_uid(&self, table: &str, uid: &str) -> rusqlite::Result<String> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT local_uid FROM sync_aliases WHERE table_name = ?1 AND uid = ?2",
+                [table, uid],
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or_else(|| uid.to_string()))
+    }
+
+    fn local_id(&self, table: &str, uid: &str) -> rusqlite::Result<Option<i32>> {
+        let uid = self.local_uid(table, uid)?;
+        self.conn
+            .query_row(
+                &format!("SELECT id FROM {table} WHERE uid = ?1"),
+                [uid],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// The latest value of each field across `uid` and every row merged into it, and whether
+    /// any of them is still alive: not removed, or changed after its removal
+    fn latest(&self, table: &str, uid: &str) -> rusqlite::Result<(HashMap<String, Latest>, bool)> {
+        let mut uids: Vec<String> = vec![uid.to_string()];
+        let mut stmt = self
+            .conn
+            .prepare("SELECT uid FROM sync_aliases WHERE table_name = ?1 AND local_uid = ?2")?;
+        for alias in stmt.query_map([table, uid], |row| row.get(0))? {
+            uids.push(alias?);
+        }
+
+        let mut latest: HashMap<String, Latest> = HashMap::new();
+        let mut alive = false;
+        for member in &uids {
+            let mut stmt = self.conn.prepare(
+                "SELECT field, value, stamp, machine FROM sync_fields
+                 WHERE table_name = ?1 AND uid = ?2",
+            )?;
+            let fields = stmt
+                .query_map([table, member], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        (row.get(1)?, row.get(2)?, row.get(3)?),
+                    ))
+                })?
+                .collect::<rusqlite::Result<Vec<(String, Latest)>>>()?;
+            let removed_at = fields
+                .iter()
+                .find(|(field, _)| field == "deleted")
+                .map(|(_, (_, stamp, _))| *stamp);
+            let changed_at = fields
+                .iter()
+                .filter(|(field, _)| field != "deleted")
+                .map(|(_, (_, stamp, _))| *stamp)
+                .max();
+            alive |=
+                changed_at.is_some() && removed_at.is_none_or(|removed| Some(removed) < changed_at);
+
+            for (field, value) in fields {
+                let newer = latest
+                    .get(&field)
+                    .is_none_or(|(_, stamp, machine)| (value.1, &value.2) > (*stamp, machine));
+                if newer {
+                    latest.insert(field, value);
+                }
+            }
+        }
+        Ok((latest, alive))
+    }
+
+    /// Make the local row of `uid` match its latest fields. `keep` brings back a removed row,
+    /// for a client that sessions still use.
+    fn materialize(&mut self, table: &str, uid: &str, keep: bool) -> Result<()> {
+        let uid = self.local_uid(table, uid)?;
+        let (fields, alive) = self.latest(table, &uid)?;
+        let id: Option<i32> = self
+            .conn
+            .query_row(
+                &format!("SELECT id FROM {table} WHERE uid = ?1"),
+                [&uid],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let what = self.describe(table, &uid)?;
+
+        if !alive && !keep {
+            match (id, table) {
+                (Some(id), "clients") => self.removals.push((id, what)),
+                (Some(id), _) => self.attempt(&what, |conn| {
+                    conn.execute(&format!("DELETE FROM {table} WHERE id = ?1"), [id])
+                })?,
+                (None, _) => {}
+            }
+            return Ok(());
+        }
+        if !alive {
+            self.conflicts.push(format!(
+                "{what} was removed, but brought back as sessions from another machine use it"
+            ));
+        } else if self.removed.contains(&(table.to_string(), uid.clone())) && id.is_some() {
+            self.conflicts.push(format!(
+                "{what} was removed on another machine, but kept as it was changed afterwards"
+            ));
+        } else if id.is_none() && fields.contains_key("deleted") {
+            self.conflicts.push(format!(
+                "{what} was removed here, but brought back as another machine changed it afterwards"
+            ));
+        }
+
+        let value = |field: &str| fields.get(field).map(|(value, _, _)| value.clone());
+        // The same client or session may have been created on two machines, e.g. before their
+        // first sync, these are found by name or by client and start
+        let (columns, duplicate_query, mut duplicate_key): (Vec<(&str, Value)>, &str, Vec<Value>) =
+            match table {
+                "clients" => {
+                    let Some(name @ Value::Text(_)) = value("name") else {
+                        return Ok(()); // Not all of it has synced yet
+                    };
+                    (
+                        vec![
+                            ("name", name.clone()),
+                            ("note", value("note").unwrap_or(Value::Null)),
+                        ],
+                        "SELECT uid FROM clients WHERE name = ?1 AND uid != ?2",
+                        vec![name],
+                    )
+                }
+                _ => {
+                    let (Some(Value::Text(client)), Some(start @ Value::Integer(_))) =
+                        (value("client"), value("start_timestamp"))
+                    else {
+                        return Ok(());
+                    };
+                    let Some(client_id) = self.local_id("clients", &client)? else {
+                        // Removed here or not synced yet
+                        self.materialize("clients", &client, true)?;
+                        if self.local_id("clients", &client)?.is_none() {
+                            self.conflicts
+                                .push(format!("{what} will be added once its client has synced"));
+                            return Ok(());
+                        }
+                        return self.materialize(table, &uid, keep);
+                    };
+                    let client_id = Value::Integer(client_id.into());
+                    (
+                        vec![
+                            ("client_id", client_id.clone()),
+                            ("start_timestamp", start.clone()),
+                            (
+                                "end_timestamp",
+                                value("end_timestamp").unwrap_or(Value::Null),
+                            ),
+                            ("note", value("note").unwrap_or(Value::Null)),
+                            (
+                                "offset_minutes",
+                                value("offset_minutes").unwrap_or(Value::Integer(0)),
+                            ),
+                        ],
+                        "SELECT uid FROM sessions
+                         WHERE client_id = ?1 AND start_timestamp = ?2 AND uid != ?3",
+                        vec![client_id, start],
+                    )
+                }
+            };
+
+        duplicate_key.push(Value::Text(uid.clone()));
+        let duplicate: Option<String> = self
+            .conn
+            .query_row(duplicate_query, params_from_iter(duplicate_key), |row| {
+                row.get(0)
+            })
+            .optional()?;
+        if let Some(other) = duplicate {
+            self.conn.execute(
+                "UPDATE sync_aliases SET local_uid = ?3 WHERE table_name = ?1 AND local_uid = ?2",
+                params![table, uid, other],
+            )?;
+            self.conn.execute(
+                "INSERT OR REPLACE INTO sync_aliases (table_name, uid, local_uid)
+                 VALUES (?1, ?2, ?3)",
+                params![table, uid, other],
+            )?;
+            if let Some(id) = id {
+                self.fold(table, id, &other)?;
+            }
+            self.conflicts.push(format!(
+                "{what} was added on more than one machine, they are now one"
+            ));
+            return self.materialize(table, &other, keep);
+        }
+
+        let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+        let mut values: Vec<Value> = columns.into_iter().map(|(_, value)| value).collect();
+        match id {
+            Some(id) => {
+                let assignments: Vec<String> = names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| format!("{name} = ?{}", i + 2))
+                    .collect();
+                values.insert(0, Value::Integer(id.into()));
+                self.attempt(&what, |conn| {
+                    conn.execute(
+                        &format!(
+                            "UPDATE {table} SET {} WHERE id = ?1",
+                            assignments.join(", ")
+                        ),
+                        params_from_iter(&values),
+                    )
+                })?;
+            }
+            None => {
+                values.push(Value::Text(uid.clone()));
+                let placeholders: Vec<String> =
+                    (1..=values.len()).map(|i| format!("?{i}")).collect();
+                self.attempt(&what, |conn| {
+                    conn.execute(
+                        &format!(
+                            "INSERT INTO {table} ({}, uid) VALUES ({})",
+                            names.join(", "),
+                            placeholders.join(", ")
+                        ),
+                        params_from_iter(&values),
+                    )
+                })?;
+                if table == "clients" {
+                    self.add_waiting_sessions(&uid)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fold the local row `id` into the row of `into`, which is the same client or session
+    fn fold(&mut self, table: &str, id: i32, into: &str) -> Result<()> {
+        if table == "clients"
+            && let Some(into_id) = self.local_id(table, into)?
+        {
+            self.conn.execute(
+                "UPDATE sessions SET client_id = ?1 WHERE client_id = ?2",
+                [into_id, id],
+            )?;
+            self.conn.execute(
+                "UPDATE OR IGNORE client_aliases SET client_id = ?1 WHERE client_id = ?2",
+                [into_id, id],
+            )?;
+        }
+        let what = self.describe(table, into)?;
+        self.attempt(&what, |conn| {
+            conn.execute(&format!("DELETE FROM {table} WHERE id = ?1"), [id])
+        })
+    }
+
+    /// Sessions that synced before their client did
+    fn add_waiting_sessions(&mut self, client: &str) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "SELECT f.uid FROM sync_fields f
+             WHERE f.table_name = 'sessions' AND f.field = 'client'
+                AND f.value IN (
+                    SELECT ?1 UNION SELECT uid FROM sync_aliases
+                    WHERE table_name = 'clients' AND local_uid = ?1
+                )
+                AND NOT EXISTS (SELECT 1 FROM sessions s WHERE s.uid = f.uid)",
+        )?;
+        let waiting = stmt
+            .query_map([client], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        drop(stmt);
+        for uid in waiting {
+            self.materialize("sessions", &uid, false)?;
+        }
+        Ok(())
+    }
+
+    /// Run `change`, reporting rather than failing if a trigger refuses it, e.g. for a locked
+    /// session
+    fn attempt(
+        &mut self,
+        what: &str,
+        change: impl FnOnce(&Connection) -> rusqlite::Result<usize>,
+    ) -> Result<()> {
+        self.conn.execute_batch("SAVEPOINT sync_change")?;
+        match change(self.conn) {
+            Ok(_) => {
+                self.conn.execute_batch("RELEASE sync_change")?;
+                Ok(())
+            }
+            Err(rusqlite::Error::SqliteFailure(_, Some(message))) => {
+                self.conn
+                    .execute_batch("ROLLBACK TO sync_change; RELEASE sync_change")?;
+                self.conflicts
+                    .push(format!("{what} was changed on another machine: {message}"));
+                Ok(())
+            }
+            Err(e) => {
+                self.conn
+                    .execute_batch("ROLLBACK TO sync_change; RELEASE sync_change")?;
+                Err(e.into())
+            }
+        }
+    }
+
+    /// A row as people know it: "Session 12" or "Client 'Acme'"
+    fn describe(&self, table: &str, uid: &str) -> rusqlite::Result<String> {
+        let uid = self.local_uid(table, uid)?;
+        let local: Option<(i32, String)> = match table {
+            "clients" => self
+                .conn
+                .query_row(
+                    "SELECT id, name FROM clients WHERE uid = ?1",
+                    [&uid],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?,
+            _ => self
+                .conn
+                .query_row("SELECT id FROM sessions WHERE uid = ?1", [&uid], |row| {
+                    Ok((row.get(0)?, String::new()))
+                })
+                .optional()?,
+        };
+        Ok(match (table, local) {
+            ("clients", Some((_, name))) => format!("Client '{name}'"),
+            ("clients", None) => {
+                let name: Option<String> = self
+                    .conn
+                    .query_row(
+                        "SELECT value FROM sync_fields
+                         WHERE table_name = 'clients' AND uid = ?1 AND field = 'name'",
+                        [&uid],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                match name {
+                    Some(name) => format!("Client '{name}'"),
+                    None => "A client".into(),
+                }
+            }
+            (_, Some((id, _))) => format!("Session {id}"),
+            (_, None) => "A session".into(),
+        })
+    }
+
+    /// A field's value as people know it
+    fn show(&self, field: &str, value: &Value) -> rusqlite::Result<String> {
+        Ok(match (field, value) {
+            (_, Value::Null) => "(none)".into(),
+            ("client", Value::Text(uid)) => self.describe("clients", uid)?.replace("Client ", ""),
+            ("start_timestamp" | "end_timestamp", Value::Integer(secs)) => {
+                DateTime::from_timestamp(*secs, 0)
+                    .map(|time| {
+                        time.with_timezone(&Local)
+                            .format("%b %d, %Y %I:%M %p")
+                            .to_string()
+                    })
+                    .unwrap_or_else(|| secs.to_string())
+            }
+            (_, Value::Text(text)) => format!("'{text}'"),
+            (_, value) => format_value(value),
+        })
+    }
+}
+
+fn field_label(field: &str) -> &str {
+    match field {
+        "start_timestamp" => "start",
+        "end_timestamp" => "end",
+        "offset_minutes" => "offset",
+        "deleted" => "removal",
+        field => field,
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+fn test_machine() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    crate::db::init_schema(&conn).unwrap();
+    crate::db::apply_migrations(&conn).unwrap();
+    conn
+}
+
+#[test]
+fn test_machines_converge() {
+    use crate::{db, models::Session};
+
+    let dir = std::env::temp_dir().join(format!("timber-sync-test-{}", std::process::id()));
+    let (desktop, laptop) = (test_machine(), test_machine());
+    let session = |start: &str| Session {
+        id: 0,
+        client_id: 1,
+        start_timestamp: start.parse().unwrap(),
+        end_timestamp: None,
+        note: None,
+        offset_minutes: 0,
+    };
+
+    // Both machines had Acme before they first synced
+    for conn in [&desktop, &laptop] {
+        conn.execute("INSERT INTO clients (name) VALUES ('Acme')", [])
+            .unwrap();
+    }
+    db::store_session(&desktop, &session("2025-07-18T09:00:00Z")).unwrap();
+    let sync_all = || {
+        sync(&desktop, &dir).unwrap();
+        sync(&laptop, &dir).unwrap();
+        sync(&desktop, &dir).unwrap()
+    };
+    sync_all();
+    assert_eq!(db::list_clients(&laptop).unwrap().len(), 1);
+    let on_laptop = db::list_sessions(&laptop, None).unwrap();
+    assert_eq!(on_laptop.len(), 1);
+
+    // Removed on the desktop while the laptop changed it: the change wins and nothing is lost
+    let mut changed = on_laptop[0].clone();
+    db::remove_session(&desktop, 1).unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(5)); // Stamps are in milliseconds
+    changed.note = Some("Planning".into());
+    db::commit_session_changes(&laptop, &changed).unwrap();
+    sync(&desktop, &dir).unwrap();
+    let report = sync(&laptop, &dir).unwrap();
+    assert_eq!(report.machines, 1);
+    assert_eq!(report.conflicts.len(), 1);
+    sync(&desktop, &dir).unwrap();
+    let restored = db::list_sessions(&desktop, None).unwrap();
+    assert_eq!(restored.len(), 1);
+    assert_eq!(restored[0].note.as_deref(), Some("Planning"));
+
+    // Both edit the same field, the later edit wins on both
+    let mut on_desktop = restored[0].clone();
+    on_desktop.offset_minutes = 5;
+    db::commit_session_changes(&desktop, &on_desktop).unwrap();
+    let mut on_laptop = db::list_sessions(&laptop, None).unwrap()[0].clone();
+    on_laptop.offset_minutes = 10;
+    db::commit_session_changes(&laptop, &on_laptop).unwrap();
+    sync_all();
+    let offsets = |conn: &Connection| db::list_sessions(conn, None).unwrap()[0].offset_minutes;
+    assert_eq!(offsets(&desktop), offsets(&laptop));
+
+    db::store_session(&laptop, &session("2025-07-18T13:00:00Z")).unwrap();
+    sync_all();
+    assert_eq!(db::list_sessions(&desktop, None).unwrap().len(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_copies_take_their_own_machine_id() {
+    use crate::db;
+
+    let dir = std::env::temp_dir().join(format!("timber-sync-copy-test-{}", std::process::id()));
+    let desktop = test_machine();
+    desktop
+        .execute("INSERT INTO clients (name) VALUES ('Acme')", [])
+        .unwrap();
+    sync(&desktop, &dir).unwrap();
+    let machine = |conn: &Connection| -> String {
+        conn.query_row(
+            "SELECT value FROM meta WHERE key = 'sync_machine'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+
+    // The laptop starts as a copy of the synced desktop, so both sync as the same machine
+    let mut laptop = test_machine();
+    rusqlite::backup::Backup::new(&desktop, &mut laptop)
+        .unwrap()
+        .run_to_completion(100, std::time::Duration::ZERO, None)
+        .unwrap();
+    assert_eq!(machine(&laptop), machine(&desktop));
+    desktop
+        .execute("INSERT INTO clients (name) VALUES ('Globex')", [])
+        .unwrap();
+    assert!(sync(&desktop, &dir).unwrap().new_machine.is_none());
+    laptop
+        .execute("INSERT INTO clients (name) VALUES ('Initech')", [])
+        .unwrap();
+    let report = sync(&laptop, &dir).unwrap();
+    assert_eq!(report.new_machine, Some(machine(&laptop)));
+    assert_ne!(machine(&laptop), machine(&desktop));
+    assert_eq!(report.imported, 2); // Globex's name and note, from the log they shared
+    assert!(sync(&desktop, &dir).unwrap().new_machine.is_none());
+    assert_eq!(db::list_clients(&desktop).unwrap().len(), 3);
+    assert_eq!(db::list_clients(&laptop).unwrap().len(), 3);
+
+    // Logs are read from where the last sync stopped, a line still being written waits
+    let desktop_log = dir.join(format!("{}.log", machine(&desktop)));
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(&desktop_log)
+        .unwrap();
+    write!(file, "1\t{}\tclients\tabc\tname", machine(&desktop)).unwrap();
+    let report = sync(&laptop, &dir).unwrap();
+    assert_eq!((report.imported, report.unreadable), (0, 0));
+    writeln!(file, "\tHooli").unwrap();
+    assert_eq!(sync(&laptop, &dir).unwrap().imported, 1);
+
+    // A log under another name carrying this machine's id is another database too
+    let laptop_id = machine(&laptop);
+    fs::write(
+        dir.join("stray.log"),
+        format!("1\t{laptop_id}\tclients\tdef\tname\tUmbrella\n"),
+    )
+    .unwrap();
+    let report = sync(&laptop, &dir).unwrap();
+    assert!(report.new_machine.is_some_and(|new| new != laptop_id));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
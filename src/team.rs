@@ -0,0 +1,230 @@
+//! Reports combining several people's databases, for `timber team report`. Clients are matched
+//! by name across databases, ignoring case and surrounding spaces.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{clock::Clock, error::Result, store::Store, utils, views};
+
+/// Minutes worked per client and person over a period
+#[derive(Debug, Default)]
+pub struct TeamReport {
+    /// Everyone in the report, in the order they were given
+    pub members: Vec<String>,
+    /// (client, member, minutes), ordered by client and then as the members were given
+    pub rows: Vec<(String, String, i64)>,
+}
+
+impl TeamReport {
+    /// Total minutes per client, ordered by client
+    pub fn client_totals(&self) -> Vec<(String, i64)> {
+        let mut totals: Vec<(String, i64)> = vec![];
+        for (client, _, minutes) in &self.rows {
+            match totals.last_mut() {
+                Some((last, total)) if last == client => *total += minutes,
+                _ => totals.push((client.clone(), *minutes)),
+            }
+        }
+        totals
+    }
+
+    /// Total minutes per member, in the order they were given, including those with no time
+    pub fn member_totals(&self) -> Vec<(String, i64)> {
+        self.members
+            .iter()
+            .map(|member| {
+                let minutes = self
+                    .rows
+                    .iter()
+                    .filter(|(_, m, _)| m == member)
+                    .map(|(_, _, minutes)| minutes)
+                    .sum();
+                (member.clone(), minutes)
+            })
+            .collect()
+    }
+
+    pub fn total_minutes(&self) -> i64 {
+        self.rows.iter().map(|(_, _, minutes)| minutes).sum()
+    }
+
+    /// One line per client and member, for spreadsheets
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("client,person,minutes,hours\n");
+        for (client, member, minutes) in &self.rows {
+            let _ = writeln!(
+                out,
+                "{},{},{minutes},{:.2}",
+                csv_field(client),
+                csv_field(member),
+                *minutes as f64 / 60.0
+            );
+        }
+        out
+    }
+}
+
+impl fmt::Display for TeamReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "By client:")?;
+        for (client, minutes) in self.client_totals() {
+            let (hours, minutes) = utils::split_minutes(minutes);
+            writeln!(f, "{client}: {hours}h {minutes}m")?;
+            for (_, member, minutes) in self.rows.iter().filter(|(c, _, _)| *c == client) {
+                let (hours, minutes) = utils::split_minutes(*minutes);
+                writeln!(f, "  {member}: {hours}h {minutes}m")?;
+            }
+        }
+        writeln!(f, "\nBy person:")?;
+        for (member, minutes) in self.member_totals() {
+            let (hours, minutes) = utils::split_minutes(minutes);
+            writeln!(f, "{member}: {hours}h {minutes}m")?;
+        }
+        let (hours, minutes) = utils::split_minutes(self.total_minutes());
+        write!(f, "\nTotal: {hours}h {minutes}m")
+    }
+}
+
+/// Split a `--db` argument into the member's name and their database. `alice=team/timber.db`
+/// names the member explicitly, otherwise the file name is used, e.g. `alice` for `alice.db`.
+pub fn member_from_arg(arg: &Path) -> (String, PathBuf) {
+    let text = arg.to_string_lossy();
+    if let Some((name, path)) = text.split_once('=')
+        && !name.is_empty()
+        && !name.contains(std::path::is_separator)
+    {
+        return (name.to_string(), PathBuf::from(path));
+    }
+    let name = arg
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| text.into_owned());
+    (name, arg.to_path_buf())
+}
+
+/// Summarize each member's store over the period and combine them
+pub fn report(
+    members: &[(String, &dyn Store)],
+    clock: &dyn Clock,
+    start: &DateTime<Utc>,
+    end: &DateTime<Utc>,
+) -> Result<TeamReport> {
+    // Keyed by the matching form of the client name, showing the first spelling seen
+    let mut clients: BTreeMap<String, (String, Vec<(String, i64)>)> = BTreeMap::new();
+    for (member, store) in members {
+        for (client, minutes) in views::summarize(*store, clock, start, end)?.clients {
+            let (_, times) = clients
+                .entry(client.trim().to_lowercase())
+                .or_insert_with(|| (client.trim().to_string(), vec![]));
+            match times.iter_mut().find(|(m, _)| m == member) {
+                Some((_, total)) => *total += minutes,
+                None => times.push((member.clone(), minutes)),
+            }
+        }
+    }
+    let rows = clients
+        .into_values()
+        .flat_map(|(client, times)| {
+            times
+                .into_iter()
+                .map(move |(member, minutes)| (client.clone(), member, minutes))
+        })
+        .collect();
+    Ok(TeamReport {
+        members: members.iter().map(|(member, _)| member.clone()).collect(),
+        rows,
+    })
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// TESTS
+
+#[test]
+fn test_team_report_matches_clients_by_name() {
+    use crate::{
+        clock::FixedClock,
+        models::{Client, Session},
+        store::MemoryStore,
+    };
+
+    let log = |store: &MemoryStore, client: &str, hours: i64| {
+        let client_id = store
+            .add_client(&Client {
+                id: 0,
+                name: client.into(),
+                note: None,
+            })
+            .unwrap();
+        let start: DateTime<Utc> = "2025-07-18T09:00:00Z".parse().unwrap();
+        store
+            .add_session(&Session {
+                id: 0,
+                client_id,
+                start_timestamp: start,
+                end_timestamp: Some(start + chrono::Duration::hours(hours)),
+                note: None,
+                offset_minutes: 0,
+            })
+            .unwrap();
+    };
+    let alice = MemoryStore::new();
+    log(&alice, "Acme", 3);
+    log(&alice, "Globex, Inc", 1);
+    let bob = MemoryStore::new();
+    log(&bob, "acme ", 2);
+    let carol = MemoryStore::new();
+
+    let clock = FixedClock("2025-07-20T00:00:00Z".parse().unwrap());
+    let members: Vec<(String, &dyn Store)> = vec![
+        ("alice".into(), &alice),
+        ("bob".into(), &bob),
+        ("carol".into(), &carol),
+    ];
+    let report = report(
+        &members,
+        &clock,
+        &"2025-07-18T00:00:00Z".parse().unwrap(),
+        &"2025-07-18T23:59:59Z".parse().unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        report.client_totals(),
+        vec![("Acme".into(), 300), ("Globex, Inc".into(), 60)]
+    );
+    assert_eq!(
+        report.member_totals(),
+        vec![
+            ("alice".into(), 240),
+            ("bob".into(), 120),
+            ("carol".into(), 0)
+        ]
+    );
+    assert_eq!(
+        report.to_csv(),
+        "client,person,minutes,hours\n\
+         Acme,alice,180,3.00\n\
+         Acme,bob,120,2.00\n\
+         \"Globex, Inc\",alice,60,1.00\n"
+    );
+    assert_eq!(
+        member_from_arg(Path::new("team/bob.db")),
+        ("bob".into(), PathBuf::from("team/bob.db"))
+    );
+    assert_eq!(
+        member_from_arg(Path::new("alice=shared/timber.db")),
+        ("alice".into(), PathBuf::from("shared/timber.db"))
+    );
+}
@@ -0,0 +1,197 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use rusqlite::Connection;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    commands,
+    config::Config,
+    db,
+    error::{Error, Result},
+    models::{Client, Session},
+    store::{self, Store},
+    views::{self, SessionView, Summary},
+};
+
+/// The main entry point for tools built on Timber. A `Tracker` wraps a [`Store`], by default a
+/// migrated SQLite database, and offers the everyday operations of the CLI, returning data
+/// instead of printing.
+///
+/// ```no_run
+/// use chrono::Utc;
+/// use timber::{Tracker, config::{Config, Overrides}};
+///
+/// let tracker = Tracker::open(&Config::load(&Overrides::default())?)?;
+/// let client_id = tracker.add_client("Acme", None)?;
+/// tracker.start(client_id, None, Utc::now())?;
+/// # Ok::<(), timber::Error>(())
+/// ```
+pub struct Tracker<S: Store = Connection> {
+    store: S,
+    clock: Box<dyn Clock>,
+}
+
+impl Tracker {
+    /// Open the database of `config`, creating or migrating it as needed
+    pub fn open(config: &Config) -> Result<Self> {
+        Ok(Tracker::new(db::init_db(config)?))
+    }
+
+    /// A tracker over a fresh database that only lives as long as the tracker
+    pub fn open_in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()?;
+        db::init_schema(&conn)?;
+        db::apply_migrations(&conn)?;
+        Ok(Tracker::new(conn))
+    }
+
+    /// The underlying connection, for anything the tracker doesn't cover
+    pub fn connection(&self) -> &Connection {
+        &self.store
+    }
+}
+
+impl<S: Store> Tracker<S> {
+    pub fn new(store: S) -> Self {
+        Tracker {
+            store,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    /// Use `clock` instead of the system clock, e.g. to pin the time in tests
+    pub fn with_clock(self, clock: Box<dyn Clock>) -> Self {
+        Tracker { clock, ..self }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+
+    pub fn clients(&self) -> Result<Vec<Client>> {
+        self.store.list_clients()
+    }
+
+    /// Add a client, returning its id
+    pub fn add_client(&self, name: &str, note: Option<String>) -> Result<i32> {
+        self.store.add_client(&Client {
+            id: 0,
+            name: name.to_string(),
+            note,
+        })
+    }
+
+    /// Start a session for `client_id` at `at`, returning the new session's id
+    pub fn start(&self, client_id: i32, note: Option<String>, at: DateTime<Utc>) -> Result<i32> {
+        commands::session::start_session(&self.store, self.clock(), client_id, note, at)
+    }
+
+    /// End the active session at `at`, returning how long it ran or `None` if nothing was active
+    pub fn stop(&self, at: DateTime<Utc>) -> Result<Option<TimeDelta>> {
+        commands::session::end_session(&self.store, self.clock(), at)
+    }
+
+    /// End the active session, if any, and start one for `client_id` at the same moment. Nothing
+    /// changes unless both steps succeed.
+    pub fn switch(
+        &self,
+        client_id: i32,
+        note: Option<String>,
+        at: DateTime<Utc>,
+    ) -> Result<(Option<TimeDelta>, i32)> {
+        store::atomically(&self.store, || {
+            let ended = commands::session::end_session(&self.store, self.clock(), at)?;
+            let started =
+                commands::session::start_session(&self.store, self.clock(), client_id, note, at)?;
+            Ok((ended, started))
+        })
+    }
+
+    /// Add (or with a negative number remove) minutes to the active session
+    pub fn patch(&self, minutes: i32) -> Result<()> {
+        commands::session::patch_session(&self.store, minutes)?
+            .ok_or_else(|| Error::NotFound("No active session was found to patch!".into()))
+    }
+
+    pub fn active_session(&self) -> Result<Option<SessionView>> {
+        self.store
+            .active_session()?
+            .map(|session| SessionView::from_session(&self.store, self.clock(), session))
+            .transpose()
+    }
+
+    /// Every session, or only those of `client_id`
+    pub fn sessions(&self, client_id: Option<i32>) -> Result<Vec<Session>> {
+        self.store.list_sessions(client_id)
+    }
+
+    /// Time worked per client between `start` and `end`
+    pub fn summarize(&self, start: &DateTime<Utc>, end: &DateTime<Utc>) -> Result<Summary> {
+        views::summarize(&self.store, self.clock(), start, end)
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+fn check_tracker_workflow<S: Store>(tracker: Tracker<S>) {
+    let acme = tracker.add_client("Acme", None).unwrap();
+    let globex = tracker.add_client("Globex", None).unwrap();
+    assert!(matches!(
+        tracker.add_client("Acme", None),
+        Err(Error::Conflict(_))
+    ));
+
+    let now = tracker.clock().now();
+    tracker
+        .start(acme, None, now - TimeDelta::hours(3))
+        .unwrap();
+    assert!(matches!(
+        tracker.start(globex, None, now),
+        Err(Error::Conflict(_))
+    ));
+    let (ended, _) = tracker
+        .switch(globex, None, now - TimeDelta::hours(1))
+        .unwrap();
+    assert_eq!(ended, Some(TimeDelta::hours(2)));
+    tracker.patch(-30).unwrap();
+
+    // A failed switch leaves the active session running
+    assert!(matches!(
+        tracker.switch(999, None, now - TimeDelta::minutes(10)),
+        Err(Error::NotFound(_))
+    ));
+    let active = tracker.active_session().unwrap().unwrap();
+    assert_eq!(active.client_name, "Globex");
+
+    tracker.stop(now).unwrap();
+    assert!(matches!(tracker.patch(5), Err(Error::NotFound(_))));
+    assert_eq!(tracker.sessions(Some(acme)).unwrap().len(), 1);
+
+    let summary = tracker
+        .summarize(&(now - TimeDelta::days(1)), &(now + TimeDelta::days(1)))
+        .unwrap();
+    assert_eq!(
+        summary.clients,
+        vec![("Acme".to_string(), 120), ("Globex".to_string(), 30)]
+    );
+    assert_eq!(summary.total_minutes(), 150);
+}
+
+#[test]
+fn test_tracker_workflow() {
+    use crate::clock::FixedClock;
+
+    let now: DateTime<Utc> = "2025-07-18T17:00:00Z".parse().unwrap();
+    check_tracker_workflow(
+        Tracker::open_in_memory()
+            .unwrap()
+            .with_clock(Box::new(FixedClock(now))),
+    );
+    check_tracker_workflow(
+        Tracker::new(store::MemoryStore::new()).with_clock(Box::new(FixedClock(now))),
+    );
+}
@@ -1,7 +1,14 @@
-use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc, Weekday};
-use rusqlite::Connection;
+use std::io::{self, BufRead, IsTerminal, Write};
 
-use crate::{cli::UserInput, db};
+use crate::{
+    clock::Clock,
+    error::{Error, Result},
+    models::{Client, UserInput},
+    store::Store,
+};
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveTime, TimeZone, Timelike, Utc, Weekday,
+};
 
 pub fn split_minutes(total_minutes: i64) -> (i64, i64) {
     let hours = total_minutes / 60;
@@ -9,8 +16,8 @@ pub fn split_minutes(total_minutes: i64) -> (i64, i64) {
     (hours, minutes)
 }
 
-pub fn current_day_range() -> (DateTime<Utc>, DateTime<Utc>) {
-    let now = chrono::Utc::now();
+pub fn current_day_range(clock: &dyn Clock) -> (DateTime<Utc>, DateTime<Utc>) {
+    let now = clock.now();
     let start = chrono::Utc
         .with_ymd_and_hms(now.year(), now.month(), now.day(), 0, 0, 0)
         .unwrap(); // Get time at start of day
@@ -19,8 +26,8 @@ pub fn current_day_range() -> (DateTime<Utc>, DateTime<Utc>) {
     (start, end)
 }
 
-pub fn current_week_range() -> (DateTime<Utc>, DateTime<Utc>) {
-    let today = Utc::now().date_naive();
+pub fn current_week_range(clock: &dyn Clock) -> (DateTime<Utc>, DateTime<Utc>) {
+    let today = clock.now().date_naive();
     let weekday = today.weekday();
 
     // Compute how many days we need to subtract to get to the most recent Saturday
@@ -36,8 +43,8 @@ pub fn current_week_range() -> (DateTime<Utc>, DateTime<Utc>) {
     (start, end)
 }
 
-pub fn current_month_range() -> (DateTime<Utc>, DateTime<Utc>) {
-    let today = Utc::now().date_naive();
+pub fn current_month_range(clock: &dyn Clock) -> (DateTime<Utc>, DateTime<Utc>) {
+    let today = clock.now().date_naive();
 
     // Start of the current month
     let start_date = NaiveDate::from_ymd_opt(today.year(), today.month(), 1).unwrap();
@@ -59,10 +66,224 @@ pub fn current_month_range() -> (DateTime<Utc>, DateTime<Utc>) {
     (start, end)
 }
 
-pub fn handle_user_client_input(conn: &Connection, input: Option<UserInput>) -> Option<i32> {
+/// Parse a duration such as `20m`, `1h30m`, `2 hours` or `90s`. Bare numbers are minutes.
+/// Durations too long to represent are not understood.
+pub fn parse_duration(input: &str) -> Option<Duration> {
+    let mut rest = input.trim();
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut total = Duration::zero();
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let value: i64 = rest[..digits].parse().ok()?;
+        rest = rest[digits..].trim_start();
+
+        let unit_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let part = match rest[..unit_len].to_ascii_lowercase().as_str() {
+            "h" | "hr" | "hrs" | "hour" | "hours" => Duration::try_hours(value)?,
+            "" | "m" | "min" | "mins" | "minute" | "minutes" => Duration::try_minutes(value)?,
+            "s" | "sec" | "secs" | "second" | "seconds" => Duration::try_seconds(value)?,
+            _ => return None,
+        };
+        total = total.checked_add(&part)?;
+        rest = rest[unit_len..].trim_start();
+    }
+    Some(total)
+}
+
+/// Parse a local time of day such as `14:30`, `09:15:30`, `5pm` or `9:15 am`
+fn parse_time_of_day(input: &str) -> Option<NaiveTime> {
+    let (clock, pm) = if let Some(clock) = input.strip_suffix("am") {
+        (clock.trim_end(), Some(false))
+    } else if let Some(clock) = input.strip_suffix("pm") {
+        (clock.trim_end(), Some(true))
+    } else {
+        (input, None)
+    };
+
+    let time = NaiveTime::parse_from_str(clock, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(clock, "%H:%M:%S"))
+        .ok()
+        .or_else(|| {
+            let hour = clock.parse::<u32>().ok().filter(|_| pm.is_some())?;
+            NaiveTime::from_hms_opt(hour, 0, 0)
+        })?;
+
+    match pm {
+        None => Some(time),
+        Some(_) if time.hour() == 0 || time.hour() > 12 => None,
+        Some(pm) => time.with_hour(time.hour() % 12 + if pm { 12 } else { 0 }),
+    }
+}
+
+fn parse_day(input: &str, today: NaiveDate) -> Option<NaiveDate> {
     match input {
-        Some(UserInput::ById(id)) => Some(id),
-        Some(UserInput::ByName(name)) => db::get_client_id_by_name(conn, name).ok().flatten(),
-        None => None,
+        "today" => Some(today),
+        "yesterday" => today.pred_opt(),
+        _ => NaiveDate::parse_from_str(input, "%Y-%m-%d").ok(),
+    }
+}
+
+/// Parse a point in time the way people type it, relative to `reference`:
+/// RFC3339 timestamps, `now`, local times (`14:30`, `5pm`), a day followed by a time
+/// (`yesterday 17:00`, `2025-07-16 09:00`) and offsets (`20m ago`, `-1h30m`).
+pub fn parse_natural_time(input: &str, reference: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(input.trim()) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+
+    let input = input.trim().to_ascii_lowercase();
+    if input == "now" {
+        return Some(reference);
     }
+    if let Some(offset) = input
+        .strip_suffix("ago")
+        .or_else(|| input.strip_prefix('-'))
+    {
+        return reference.checked_sub_signed(parse_duration(offset)?);
+    }
+
+    let today = reference.with_timezone(&Local).date_naive();
+    let (date, clock) = match input.split_once(' ') {
+        Some((day, clock)) if parse_day(day, today).is_some() => (parse_day(day, today)?, clock),
+        _ => (today, input.as_str()),
+    };
+    let time = parse_time_of_day(clock.trim())?;
+    Local
+        .from_local_datetime(&date.and_time(time))
+        .earliest()
+        .map(|local| local.with_timezone(&Utc))
+}
+
+/// The id of the client `input` refers to, or `None` if there is no such client. A name matching
+/// several clients is picked from interactively, or is an error when there is no terminal.
+pub fn handle_user_client_input(
+    store: &dyn Store,
+    input: Option<UserInput>,
+) -> Result<Option<i32>> {
+    match input {
+        Some(UserInput::ById(id)) => Ok(Some(id)),
+        Some(UserInput::ByName(name)) => {
+            let candidates = store.find_clients_by_name(&name)?;
+            choose_client(name, candidates)
+        }
+        None => Ok(None),
+    }
+}
+
+/// The client `name` refers to among the `candidates` it matches, asking the user to pick one when
+/// there are several
+pub fn choose_client(name: String, mut candidates: Vec<Client>) -> Result<Option<i32>> {
+    match candidates.len() {
+        0 => Ok(None),
+        1 => Ok(candidates.pop().map(|client| client.id)),
+        _ if io::stdin().is_terminal() => Ok(pick_client(&name, &candidates)),
+        _ => Err(Error::Ambiguous {
+            input: name,
+            candidates,
+        }),
+    }
+}
+
+fn pick_client(name: &str, candidates: &[Client]) -> Option<i32> {
+    println!("'{name}' matches several clients:");
+    let options: Vec<String> = candidates
+        .iter()
+        .map(|client| format!("{} (id {})", client.name, client.id))
+        .collect();
+    let choice = prompt_choice("Pick a client", &options)?;
+    Some(candidates[choice].id)
+}
+
+/// Print a numbered list of options and read the user's pick, returning its index
+pub fn prompt_choice(prompt: &str, options: &[String]) -> Option<usize> {
+    for (i, option) in options.iter().enumerate() {
+        println!("  [{}] {option}", i + 1);
+    }
+    print!("{prompt} [1-{}]: ", options.len());
+    io::stdout().flush().ok()?;
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line).ok()?;
+    let choice = line.trim().parse::<usize>().ok()?.checked_sub(1)?;
+    (choice < options.len()).then_some(choice)
+}
+
+// TESTS
+
+#[test]
+fn test_parse_natural_time() {
+    let reference: DateTime<Utc> = "2025-07-16T12:00:00+00:00".parse().unwrap();
+    let local = |date: &str, time: &str| {
+        let naive = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .unwrap()
+            .and_time(NaiveTime::parse_from_str(time, "%H:%M").unwrap());
+        Local
+            .from_local_datetime(&naive)
+            .unwrap()
+            .with_timezone(&Utc)
+    };
+    let today = reference.with_timezone(&Local).date_naive().to_string();
+    let yesterday = (reference.with_timezone(&Local).date_naive() - Duration::days(1)).to_string();
+
+    let parse = |input| parse_natural_time(input, reference);
+    assert_eq!(parse("now"), Some(reference));
+    assert_eq!(parse("20m ago"), Some(reference - Duration::minutes(20)));
+    assert_eq!(parse("-1h30m"), Some(reference - Duration::minutes(90)));
+    assert_eq!(parse("09:15"), Some(local(&today, "09:15")));
+    assert_eq!(parse("5pm"), Some(local(&today, "17:00")));
+    assert_eq!(parse("12:30 am"), Some(local(&today, "00:30")));
+    assert_eq!(parse("Yesterday 17:00"), Some(local(&yesterday, "17:00")));
+    assert_eq!(
+        parse("2025-01-02 08:00"),
+        Some(local("2025-01-02", "08:00"))
+    );
+    assert_eq!(
+        parse("2025-01-02T08:00:00+02:00"),
+        Some("2025-01-02T06:00:00+00:00".parse().unwrap())
+    );
+    assert_eq!(parse("13pm"), None);
+    assert_eq!(parse("later"), None);
+    assert_eq!(
+        parse_duration("2 hours 5 min"),
+        Some(Duration::minutes(125))
+    );
+}
+
+#[test]
+fn test_huge_times_are_not_understood() {
+    let reference: DateTime<Utc> = "2025-07-16T12:00:00+00:00".parse().unwrap();
+    assert_eq!(parse_duration("99999999999999999h"), None);
+    assert_eq!(parse_duration("2000000000000h 2000000000000h"), None);
+    assert_eq!(parse_natural_time("9999999999999m ago", reference), None);
+    assert_eq!(parse_natural_time("-99999999999h", reference), None);
+}
+
+#[test]
+fn test_ranges_follow_the_clock() {
+    use crate::clock::FixedClock;
+
+    let at = |time: &str| FixedClock(time.parse().unwrap());
+    let day = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+
+    // Weeks run Saturday to Friday, so a Friday is the last day of its week
+    let (start, end) = current_week_range(&at("2025-07-18T23:30:00Z"));
+    assert_eq!(start, day("2025-07-12T00:00:00Z"));
+    assert_eq!(end, day("2025-07-18T23:59:59Z"));
+    let (start, _) = current_week_range(&at("2025-07-19T00:00:00Z"));
+    assert_eq!(start, day("2025-07-19T00:00:00Z"));
+
+    let (start, end) = current_month_range(&at("2024-02-10T12:00:00Z"));
+    assert_eq!(start, day("2024-02-01T00:00:00Z"));
+    assert_eq!(end, day("2024-02-29T23:59:59Z"));
+
+    let (start, end) = current_day_range(&at("2025-12-31T08:00:00Z"));
+    assert_eq!(start, day("2025-12-31T00:00:00Z"));
+    assert_eq!(end, day("2026-01-01T00:00:00Z"));
 }
@@ -1,18 +1,25 @@
-use chrono::{DateTime, Local, Utc};
+use chrono::{DateTime, Local, TimeDelta, Utc};
 use std::{collections::HashMap, fmt};
 
-use rusqlite::Connection;
-
-use crate::{db, models::Session, utils};
+use crate::{
+    clock::Clock,
+    error::Result,
+    models::{AuditEntry, Session},
+    store::Store,
+    utils,
+};
 #[derive(Debug)]
 pub struct SessionView {
     pub session: Session,
     pub client_name: String,
+    /// How long the session had run when the view was made
+    pub duration: TimeDelta,
 }
 impl SessionView {
-    pub fn from_session(conn: &Connection, session: Session) -> Result<Self, rusqlite::Error> {
-        let client = db::get_client_by_id(conn, session.client_id)?;
+    pub fn from_session(store: &dyn Store, clock: &dyn Clock, session: Session) -> Result<Self> {
+        let client = store.get_client(session.client_id)?;
         Ok(SessionView {
+            duration: session.get_timedelta(clock),
             session,
             client_name: client.name,
         })
@@ -21,26 +28,17 @@ impl SessionView {
 
 impl std::fmt::Display for SessionView {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let start_utc = self
-            .session
-            .start_timestamp
-            .parse::<DateTime<Utc>>()
-            .unwrap_or_else(|_| Utc::now());
-        let start_local = start_utc.with_timezone(&Local);
+        let start_local = self.session.start_timestamp.with_timezone(&Local);
 
         let end_str = match &self.session.end_timestamp {
-            Some(end_ts) => {
-                let end_utc = end_ts
-                    .parse::<DateTime<Utc>>()
-                    .unwrap_or_else(|_| Utc::now());
+            Some(end_utc) => {
                 let end_local = end_utc.with_timezone(&Local);
                 format!("{}", end_local.format("%b %d, %Y %I:%M %p"))
             }
             None => "In progress".to_string(),
         };
 
-        let (hours, minutes) =
-            utils::split_minutes(self.session.get_timedelta().num_minutes() as i64);
+        let (hours, minutes) = utils::split_minutes(self.duration.num_minutes());
         let duration_str = format!("Duration: {hours}h {minutes}m");
 
         let note_str = match &self.session.note {
@@ -60,62 +58,137 @@ impl std::fmt::Display for SessionView {
     }
 }
 
-pub fn display_daily_time_summary(conn: &Connection) {
-    let (start, end) = utils::current_day_range();
-    display_client_time_summaries(conn, &start, &end);
+/// Minutes worked per client over a period, ordered by client name
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub clients: Vec<(String, i64)>,
+}
+
+impl Summary {
+    pub fn total_minutes(&self) -> i64 {
+        self.clients.iter().map(|(_, minutes)| minutes).sum()
+    }
 }
 
-pub fn display_client_time_summaries(
-    conn: &Connection,
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (client_name, minutes) in &self.clients {
+            let (hours, minutes) = utils::split_minutes(*minutes);
+            writeln!(f, "{client_name}:\n{hours}h {minutes}m\n")?;
+        }
+        let (hours, minutes) = utils::split_minutes(self.total_minutes());
+        write!(f, "Total: {hours}h {minutes}m")
+    }
+}
+
+pub fn summarize(
+    store: &dyn Store,
+    clock: &dyn Clock,
     start: &DateTime<Utc>,
     end: &DateTime<Utc>,
-) {
-    let results = db::get_sessions_within_range(conn, start, end)
-        .expect("An error occurred while fetching the daily sessions");
+) -> Result<Summary> {
     let mut client_totals: HashMap<i32, i64> = HashMap::new();
-    for result in results {
-        if client_totals.contains_key(&result.client_id) {
-            client_totals.insert(
-                result.client_id,
-                client_totals.get(&result.client_id).unwrap()
-                    + result.get_timedelta().num_minutes(),
-            );
-        } else {
-            client_totals.insert(result.client_id, result.get_timedelta().num_minutes());
-        }
+    for session in store.sessions_within_range(start, end)? {
+        *client_totals.entry(session.client_id).or_insert(0) +=
+            session.get_timedelta(clock).num_minutes();
     }
-    client_totals.iter().for_each(|(&key, &value)| {
-        let (hours, minutes) = utils::split_minutes(value);
-        println!(
-            "{}:\n{}h {}m\n",
-            db::get_client_by_id(conn, key).unwrap().name,
-            hours,
-            minutes
-        )
-    });
-
-    let (hours, minutes) = utils::split_minutes(client_totals.values().sum::<i64>());
-    println!("Total: {hours}h {minutes}m");
+    let mut clients: Vec<(String, i64)> = client_totals
+        .into_iter()
+        .map(|(client_id, minutes)| {
+            let client_name = store
+                .get_client(client_id)
+                .map(|client| client.name)
+                .unwrap_or_else(|_| format!("Unknown client {client_id}"));
+            (client_name, minutes)
+        })
+        .collect();
+    clients.sort();
+    Ok(Summary { clients })
 }
 
-pub fn display_sessions(conn: &Connection, sessions: Vec<Session>) {
+pub fn display_sessions(store: &dyn Store, clock: &dyn Clock, sessions: Vec<Session>) {
     for session in sessions {
-        if let Ok(view) = SessionView::from_session(conn, session) {
+        if let Ok(view) = SessionView::from_session(store, clock, session) {
             println!("\n{view}");
         } else {
-            println!("Error displaying session.");
+            eprintln!("Error displaying session.");
         }
     }
 }
 
-pub fn display_active_session(conn: &Connection) {
-    match db::get_active_session(conn) {
-        Ok(Some(session)) => {
-            display_sessions(conn, vec![session]);
+pub fn display_active_session(store: &dyn Store, clock: &dyn Clock) -> Result<()> {
+    match store.active_session()? {
+        Some(session) => {
+            display_sessions(store, clock, vec![session]);
         }
-        Ok(None) => {
+        None => {
             println!("No active session found!")
         }
-        Err(_) => println!("An error occurred"),
+    }
+    Ok(())
+}
+
+/// Show timestamps in local time, whether stored as epoch seconds or (before schema version 7) RFC3339
+fn format_audit_value(field: &str, value: Option<&str>) -> String {
+    let Some(value) = value else {
+        return "(none)".into();
+    };
+    if !field.ends_with("_timestamp") {
+        return value.into();
+    }
+    value
+        .parse::<i64>()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .or_else(|| value.parse::<DateTime<Utc>>().ok())
+        .map(|t| {
+            t.with_timezone(&Local)
+                .format("%b %d, %Y %I:%M %p")
+                .to_string()
+        })
+        .unwrap_or_else(|| value.into())
+}
+
+pub fn display_session_history(session_id: i32, entries: &[AuditEntry]) {
+    println!("History for session {session_id}:");
+    let mut last_change = None;
+    for entry in entries {
+        // Group the fields changed together by one command under a single heading
+        let change = (&entry.timestamp, &entry.action, &entry.command);
+        if last_change != Some(change) {
+            let when = entry
+                .timestamp
+                .parse::<DateTime<Utc>>()
+                .map(|t| {
+                    t.with_timezone(&Local)
+                        .format("%b %d, %Y %I:%M:%S %p")
+                        .to_string()
+                })
+                .unwrap_or_else(|_| entry.timestamp.clone());
+            let action = match entry.action.as_str() {
+                "insert" => "created",
+                "delete" => "deleted",
+                "override" => "changed in a locked period",
+                _ => "updated",
+            };
+            match &entry.command {
+                Some(command) => println!("\n{when}: {action} by `timber {command}`"),
+                None => println!("\n{when}: {action}"),
+            }
+            last_change = Some(change);
+        }
+
+        let old = format_audit_value(&entry.field, entry.old_value.as_deref());
+        let new = format_audit_value(&entry.field, entry.new_value.as_deref());
+        match entry.action.as_str() {
+            "insert" => println!("  {}: {new}", entry.field),
+            "delete" => println!("  {}: {old}", entry.field),
+            "override" => println!("  locked until: {old}"),
+            _ if entry.field == "offset_minutes" => {
+                let delta = new.parse::<i64>().unwrap_or(0) - old.parse::<i64>().unwrap_or(0);
+                println!("  {}: {old} -> {new} ({delta:+}m)", entry.field)
+            }
+            _ => println!("  {}: {old} -> {new}", entry.field),
+        }
     }
 }
@@ -0,0 +1,30 @@
+-- A database written by Timber at schema version 2, after:
+--   client add Acme --note "Main client"; client add Globex;
+--   session start 1 "Design review"; session end; session start 2; patch -m 15
+BEGIN TRANSACTION;
+CREATE TABLE clients (
+            id    INTEGER PRIMARY KEY AUTOINCREMENT,
+            name  TEXT NOT NULL UNIQUE,
+            note  TEXT
+        );
+INSERT INTO "clients" VALUES(1,'Acme','Main client');
+INSERT INTO "clients" VALUES(2,'Globex',NULL);
+CREATE TABLE meta (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+INSERT INTO "meta" VALUES('schema_version','2');
+CREATE TABLE sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            client_id INTEGER NOT NULL,
+            start_timestamp TEXT NOT NULL,
+            end_timestamp TEXT, 
+            note TEXT, offset_minutes INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY (client_id) REFERENCES clients(id)
+        );
+INSERT INTO "sessions" VALUES(1,1,'2026-10-18T23:24:03.973933419+00:00','2026-10-18T23:24:03.979909286+00:00','Design review',0);
+INSERT INTO "sessions" VALUES(2,2,'2026-10-18T23:24:03.984663341+00:00',NULL,NULL,15);
+DELETE FROM "sqlite_sequence";
+INSERT INTO "sqlite_sequence" VALUES('clients',2);
+INSERT INTO "sqlite_sequence" VALUES('sessions',2);
+COMMIT;